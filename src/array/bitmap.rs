@@ -129,6 +129,11 @@ impl Bitmap {
         }
     }
 
+    #[inline]
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.buffer.shrink_to_fit();
+    }
+
     #[inline]
     pub(crate) fn align(&mut self) {
         self.length = self.buffer.len() * 8;
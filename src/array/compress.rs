@@ -0,0 +1,533 @@
+use std::marker::PhantomData;
+
+use crate::primitive::Primitive;
+
+use super::scalar::{NullableFixedSizeListRef, Scalar, ScalarRef, ScalarRefMut};
+use super::{Array, ListArray, NullableFixedSizedListArray};
+
+/// How the values of a series are packed, following the Gorilla paper.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Encoding {
+    /// Xor with the previous value, only the meaningful bits are stored. Suits floats.
+    Xor,
+    /// Difference between two consecutive deltas, regular series take one bit per sample.
+    DeltaOfDelta,
+}
+
+pub trait Compressible: Primitive {
+    const ENCODING: Encoding;
+
+    fn to_bits(&self) -> u64;
+    fn from_bits(bits: u64) -> Self;
+}
+
+macro_rules! compressible_int {
+    ($($type:ty),*) => {
+        $(impl Compressible for $type {
+            const ENCODING: Encoding = Encoding::DeltaOfDelta;
+
+            #[inline]
+            fn to_bits(&self) -> u64 {
+                *self as u64
+            }
+
+            #[inline]
+            fn from_bits(bits: u64) -> Self {
+                bits as $type
+            }
+        })*
+    };
+}
+
+compressible_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl Compressible for bool {
+    const ENCODING: Encoding = Encoding::DeltaOfDelta;
+
+    #[inline]
+    fn to_bits(&self) -> u64 {
+        *self as u64
+    }
+
+    #[inline]
+    fn from_bits(bits: u64) -> Self {
+        bits != 0
+    }
+}
+
+impl Compressible for f32 {
+    const ENCODING: Encoding = Encoding::Xor;
+
+    #[inline]
+    fn to_bits(&self) -> u64 {
+        f32::to_bits(*self) as u64
+    }
+
+    #[inline]
+    fn from_bits(bits: u64) -> Self {
+        f32::from_bits(bits as u32)
+    }
+}
+
+impl Compressible for f64 {
+    const ENCODING: Encoding = Encoding::Xor;
+
+    #[inline]
+    fn to_bits(&self) -> u64 {
+        f64::to_bits(*self)
+    }
+
+    #[inline]
+    fn from_bits(bits: u64) -> Self {
+        f64::from_bits(bits)
+    }
+}
+
+#[derive(Debug, Default)]
+struct BitWriter {
+    buffer: Vec<u8>,
+    length: usize,
+}
+
+impl BitWriter {
+    /// Appends the lowest `n` bits of `value`, most significant first.
+    #[inline]
+    fn write(&mut self, value: u64, n: u32) {
+        for i in (0..n).rev() {
+            if self.length.is_multiple_of(8) {
+                self.buffer.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.buffer.last_mut().unwrap() |= 0x80 >> (self.length % 8);
+            }
+            self.length += 1;
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BitReader<'a> {
+    buffer: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    #[inline]
+    fn read(&mut self, n: u32) -> u64 {
+        let mut value = 0;
+        for _ in 0..n {
+            let bit = (self.buffer[self.position / 8] << (self.position % 8)) & 0x80;
+            value = (value << 1) | (bit >> 7) as u64;
+            self.position += 1;
+        }
+        value
+    }
+}
+
+/// Zigzag encoded delta of delta buckets, as `(prefix, prefix bits, value bits)`.
+const DOD_BUCKETS: [(u64, u32, u32); 4] = [(0b10, 2, 7), (0b110, 3, 9), (0b1110, 4, 12), (0b1111, 4, 64)];
+
+#[derive(Debug, Default)]
+struct Codec {
+    count: usize,
+    previous: u64,
+    previous_delta: i64,
+    /// Leading and trailing zeros of the last stored xor.
+    window: Option<(u32, u32)>,
+}
+
+impl Codec {
+    fn encode(&mut self, encoding: Encoding, value: u64, writer: &mut BitWriter) {
+        if self.count == 0 {
+            writer.write(value, 64);
+        } else {
+            match encoding {
+                Encoding::Xor => self.encode_xor(value, writer),
+                Encoding::DeltaOfDelta => self.encode_dod(value, writer),
+            }
+        }
+        self.count += 1;
+        self.previous = value;
+    }
+
+    fn encode_xor(&mut self, value: u64, writer: &mut BitWriter) {
+        let xor = value ^ self.previous;
+        if xor == 0 {
+            writer.write(0, 1);
+            return;
+        }
+        let (leading, trailing) = (xor.leading_zeros(), xor.trailing_zeros());
+        match self.window {
+            Some((window_leading, window_trailing)) if leading >= window_leading && trailing >= window_trailing => {
+                writer.write(0b10, 2);
+                writer.write(xor >> window_trailing, 64 - window_leading - window_trailing);
+            }
+            _ => {
+                let length = 64 - leading - trailing;
+                writer.write(0b11, 2);
+                writer.write(leading as u64, 6);
+                writer.write(length as u64 - 1, 6);
+                writer.write(xor >> trailing, length);
+                self.window = Some((leading, trailing));
+            }
+        }
+    }
+
+    fn encode_dod(&mut self, value: u64, writer: &mut BitWriter) {
+        let delta = value.wrapping_sub(self.previous) as i64;
+        let dod = delta.wrapping_sub(self.previous_delta);
+        self.previous_delta = delta;
+        let zigzag = ((dod << 1) ^ (dod >> 63)) as u64;
+        if zigzag == 0 {
+            writer.write(0, 1);
+            return;
+        }
+        let (prefix, prefix_bits, value_bits) = DOD_BUCKETS
+            .into_iter()
+            .find(|(_, _, value_bits)| *value_bits == 64 || zigzag < 1 << value_bits)
+            .unwrap();
+        writer.write(prefix, prefix_bits);
+        writer.write(zigzag, value_bits);
+    }
+
+    fn decode(&mut self, encoding: Encoding, reader: &mut BitReader<'_>) -> u64 {
+        let value = if self.count == 0 {
+            reader.read(64)
+        } else {
+            match encoding {
+                Encoding::Xor => self.decode_xor(reader),
+                Encoding::DeltaOfDelta => self.decode_dod(reader),
+            }
+        };
+        self.count += 1;
+        self.previous = value;
+        value
+    }
+
+    fn decode_xor(&mut self, reader: &mut BitReader<'_>) -> u64 {
+        if reader.read(1) == 0 {
+            return self.previous;
+        }
+        if reader.read(1) == 1 {
+            let leading = reader.read(6) as u32;
+            let length = reader.read(6) as u32 + 1;
+            self.window = Some((leading, 64 - leading - length));
+        }
+        let (leading, trailing) = self.window.unwrap();
+        self.previous ^ (reader.read(64 - leading - trailing) << trailing)
+    }
+
+    fn decode_dod(&mut self, reader: &mut BitReader<'_>) -> u64 {
+        let mut ones = 0;
+        while ones < 4 && reader.read(1) == 1 {
+            ones += 1;
+        }
+        let dod = match ones {
+            0 => 0,
+            ones => {
+                let zigzag = reader.read(DOD_BUCKETS[ones - 1].2);
+                ((zigzag >> 1) as i64) ^ -((zigzag & 1) as i64)
+            }
+        };
+        self.previous_delta = self.previous_delta.wrapping_add(dod);
+        self.previous.wrapping_add(self.previous_delta as u64)
+    }
+}
+
+#[inline]
+fn validity_len(list_size: usize) -> usize {
+    list_size.div_ceil(8)
+}
+
+/// Encodes a list as its validity bitmap followed by the bit stream of its valid values.
+fn encode<P: Compressible>(list: &NullableFixedSizeListRef<'_, P>, list_size: usize) -> Vec<u8> {
+    let mut validity = vec![0; validity_len(list_size)];
+    let mut writer = BitWriter::default();
+    let mut codec = Codec::default();
+    for n in 0..list_size {
+        if let Some(Some(value)) = list.get(n) {
+            validity[n / 8] |= 1 << (n % 8);
+            codec.encode(P::ENCODING, value.to_bits(), &mut writer);
+        }
+    }
+    validity.extend(writer.buffer);
+    validity
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CompressedList<P: Compressible> {
+    data: Vec<u8>,
+    list_size: usize,
+    _marker: PhantomData<P>,
+}
+
+/// A compressed list is decoded while it is read, so it is never mutated in place and
+/// the same reference serves as both `Ref` and `RefMut`.
+#[derive(Debug, PartialEq)]
+pub struct CompressedListRef<'a, P: Compressible> {
+    data: &'a [u8],
+    list_size: usize,
+    _marker: PhantomData<P>,
+}
+
+impl<'a, P: Compressible> CompressedListRef<'a, P> {
+    #[inline]
+    fn new(data: &'a [u8], list_size: usize) -> Self {
+        Self {
+            data,
+            list_size,
+            _marker: PhantomData,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.list_size
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list_size == 0
+    }
+
+    /// Decodes the values before `n`, prefer [`CompressedListRef::iter`] for scans.
+    #[inline]
+    pub fn get(&self, n: usize) -> Option<Option<P>> {
+        self.iter().nth(n)
+    }
+
+    #[inline]
+    pub fn iter(&self) -> CompressedListIterator<'a, P> {
+        let (validity, data) = self.data.split_at(validity_len(self.list_size));
+        CompressedListIterator {
+            validity,
+            reader: BitReader {
+                buffer: data,
+                position: 0,
+            },
+            codec: Codec::default(),
+            list_size: self.list_size,
+            pos: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CompressedListIterator<'a, P: Compressible> {
+    validity: &'a [u8],
+    reader: BitReader<'a>,
+    codec: Codec,
+    list_size: usize,
+    pos: usize,
+    _marker: PhantomData<P>,
+}
+
+impl<'a, P: Compressible> Iterator for CompressedListIterator<'a, P> {
+    type Item = Option<P>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.list_size {
+            return None;
+        }
+        let valid = self.validity[self.pos / 8] & (1 << (self.pos % 8)) != 0;
+        self.pos += 1;
+        Some(valid.then(|| P::from_bits(self.codec.decode(P::ENCODING, &mut self.reader))))
+    }
+}
+
+impl<P: Compressible> Scalar for CompressedList<P> {
+    type Ref<'a> = CompressedListRef<'a, P>;
+    type RefMut<'a> = CompressedListRef<'a, P>;
+
+    fn as_ref(&self) -> Self::Ref<'_> {
+        CompressedListRef::new(&self.data, self.list_size)
+    }
+}
+
+impl<'a, P: Compressible> ScalarRef<'a> for CompressedListRef<'a, P> {
+    type Owned = CompressedList<P>;
+}
+
+impl<'a, P: Compressible> ScalarRefMut<'a> for CompressedListRef<'a, P> {
+    type Owned = CompressedList<P>;
+}
+
+/// Read-only counterpart of [`NullableFixedSizedListArray`], every list is compressed on its own
+/// with the [`Encoding`] of its primitive.
+#[derive(Debug, Clone)]
+pub struct CompressedListArray<P: Compressible> {
+    data: ListArray<u8>,
+    list_size: usize,
+    _marker: PhantomData<P>,
+}
+
+impl<P: Compressible> CompressedListArray<P> {
+    #[inline]
+    pub fn new(list_size: usize) -> Self {
+        Self {
+            data: ListArray::new(),
+            list_size,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Compresses and appends a raw list.
+    #[inline]
+    pub fn encode(&mut self, list: &NullableFixedSizeListRef<'_, P>) {
+        self.data.push(&encode(list, self.list_size));
+    }
+
+    /// Size of the compressed lists in bytes.
+    #[inline]
+    pub fn compressed_size(&self) -> usize {
+        self.data.data.len()
+    }
+}
+
+impl<P: Compressible> From<&NullableFixedSizedListArray<P>> for CompressedListArray<P> {
+    fn from(array: &NullableFixedSizedListArray<P>) -> Self {
+        let mut compressed = Self::new(array.data.list_size);
+        for list in array.iter() {
+            compressed.encode(&list);
+        }
+        compressed
+    }
+}
+
+impl<P: Compressible> Array for CompressedListArray<P> {
+    type Item = CompressedList<P>;
+    type ItemRef<'a> = CompressedListRef<'a, P>;
+    type ItemRefMut<'a> = CompressedListRef<'a, P>;
+
+    #[inline]
+    fn get(&self, id: usize) -> Option<Self::ItemRef<'_>> {
+        Some(CompressedListRef::new(self.data.get(id)?, self.list_size))
+    }
+
+    #[inline]
+    fn get_unchecked(&self, id: usize) -> Self::ItemRef<'_> {
+        CompressedListRef::new(self.data.get_unchecked(id), self.list_size)
+    }
+
+    /// Compressed lists can not be written in place.
+    #[inline]
+    fn get_mut(&mut self, _: usize) -> Option<Self::ItemRefMut<'_>> {
+        None
+    }
+
+    #[inline]
+    fn push(&mut self, value: Self::ItemRef<'_>) {
+        debug_assert_eq!(value.list_size, self.list_size);
+        self.data.push(value.data);
+    }
+
+    #[inline]
+    fn push_zero(&mut self) {
+        self.data.push(&vec![0; validity_len(self.list_size)]);
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::array::scalar::{NullableFixedSizedList, Scalar};
+    use crate::array::{Array, NullableFixedSizedListArray};
+
+    use super::{CompressedListArray, Compressible};
+
+    /// Compares bits, so that NaN round trips too.
+    fn bits<P: Compressible>(values: &[Option<P>]) -> Vec<Option<u64>> {
+        values
+            .iter()
+            .map(|value| value.as_ref().map(Compressible::to_bits))
+            .collect()
+    }
+
+    fn round_trip<P: Compressible>(lists: Vec<Vec<Option<P>>>) -> CompressedListArray<P> {
+        let mut array = NullableFixedSizedListArray::new(lists[0].len());
+        for list in &lists {
+            array.push(NullableFixedSizedList::from(list.clone()).as_ref());
+        }
+        let compressed = CompressedListArray::from(&array);
+        assert_eq!(compressed.len(), lists.len());
+        for (id, list) in lists.iter().enumerate() {
+            let decoded = compressed.get(id).unwrap().iter().collect::<Vec<_>>();
+            assert_eq!(bits(&decoded), bits(list));
+        }
+        compressed
+    }
+
+    #[test]
+    fn test_xor() {
+        round_trip(vec![
+            vec![Some(1.0f64), Some(1.0), Some(1.5), None, Some(-0.0), Some(f64::NAN)],
+            vec![
+                Some(f64::MAX),
+                Some(f64::MIN_POSITIVE),
+                Some(f64::INFINITY),
+                Some(0.1),
+                Some(0.2),
+                None,
+            ],
+            vec![None; 6],
+        ]);
+        round_trip(vec![vec![Some(3.25f32), Some(-7.5), None, Some(f32::NEG_INFINITY)]]);
+    }
+
+    #[test]
+    fn test_delta_of_delta() {
+        round_trip(vec![
+            vec![
+                Some(i64::MIN),
+                Some(i64::MAX),
+                Some(0),
+                Some(-1),
+                Some(100),
+                Some(10_000),
+            ],
+            vec![Some(10), Some(20), Some(30), None, Some(40), Some(1 << 40)],
+        ]);
+        round_trip(vec![vec![Some(u64::MAX), Some(0), Some(u64::MAX / 2), None]]);
+        round_trip(vec![vec![Some(i8::MIN), Some(i8::MAX), Some(-1), Some(0)]]);
+        round_trip(vec![vec![Some(true), Some(false), None, Some(false)]]);
+    }
+
+    #[test]
+    fn test_compression_ratio() {
+        let counter = (0..120).map(|i| Some(i as f64 * 10.0)).collect::<Vec<_>>();
+        let gauge = (0..120).map(|i| Some(0.5 + (i % 3) as f64)).collect::<Vec<_>>();
+        let compressed = round_trip(vec![counter, gauge]);
+        assert!(compressed.compressed_size() < 2 * 120 * 4);
+        let timestamps = (0..120)
+            .map(|i| Some(1_600_000_000_000 + i * 15_000))
+            .collect::<Vec<Option<i64>>>();
+        let compressed = round_trip(vec![timestamps]);
+        assert!(compressed.compressed_size() < 120 / 8 + 40);
+    }
+
+    #[test]
+    fn test_get() {
+        let mut array = NullableFixedSizedListArray::new(3);
+        array.push(NullableFixedSizedList::from(vec![Some(1u32), None, Some(3)]).as_ref());
+        let mut compressed = CompressedListArray::from(&array);
+        compressed.push_zero();
+        let list = compressed.get(0).unwrap();
+        assert_eq!(list.get(0), Some(Some(1)));
+        assert_eq!(list.get(1), Some(None));
+        assert_eq!(list.get(2), Some(Some(3)));
+        assert_eq!(list.get(3), None);
+        assert!(compressed.get(1).unwrap().iter().all(|value| value.is_none()));
+        assert!(compressed.get(2).is_none());
+        assert!(compressed.get_mut(0).is_none());
+    }
+}
@@ -64,6 +64,26 @@ where
             .map(|(&symbol, &())| symbol + 1);
     }
 
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Shrinks the values and rebuilds the dedup table at the exact size.
+    pub(crate) fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        let mut dedup = HashMap::with_capacity_and_hasher(self.data.len(), ());
+        for index in 0..self.data.len() {
+            let hash = hash_with_state(&self.hash_state, &self.data.get_unchecked(index));
+            if let RawEntryMut::Vacant(entry) = dedup.raw_entry_mut().from_hash(hash, |_| false) {
+                entry.insert_with_hasher(hash, index, (), |index| {
+                    hash_with_state(&self.hash_state, &self.data.get_unchecked(*index))
+                });
+            }
+        }
+        self.dedup = dedup;
+    }
+
     #[inline]
     pub(crate) fn get(&self, id: usize) -> Option<Option<A::ItemRef<'_>>> {
         if id == 0 {
@@ -1,4 +1,5 @@
 pub(crate) mod bitmap;
+pub mod compress;
 pub(crate) mod dictionary;
 pub mod scalar;
 
@@ -48,6 +49,8 @@ pub trait Array: 'static + Debug + Send + Sync + Sized {
     fn push(&mut self, value: Self::ItemRef<'_>);
     fn push_zero(&mut self);
     fn len(&self) -> usize;
+    /// Releases the spare capacity, for arrays that will not grow anymore.
+    fn shrink_to_fit(&mut self);
 
     #[inline]
     fn iter(&self) -> ArrayIterator<'_, Self> {
@@ -61,7 +64,13 @@ pub trait Array: 'static + Debug + Send + Sync + Sized {
 }
 
 pub trait IdentifiedArray: Array {
-    type ID: Eq + Hash;
+    type ID: Eq + Hash + Copy;
+
+    fn id(&self, offset: usize) -> Option<Self::ID>;
+    fn lookup_id(&self, value: Self::ItemRef<'_>) -> Option<Self::ID>;
+    /// Value behind an id returned by [`IdentifiedArray::id`] or [`IdentifiedArray::lookup_id`].
+    fn get_by_id(&self, id: Self::ID) -> Self::ItemRef<'_>;
+    fn for_each_id(&self, f: impl FnMut(Self::ID, Self::ItemRef<'_>));
 }
 
 #[derive(Debug, Clone)]
@@ -132,6 +141,11 @@ impl<P: Primitive> Array for FixedSizedListArray<P> {
     fn len(&self) -> usize {
         self.data.len() / self.list_size
     }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -200,6 +214,12 @@ impl<P: Primitive> Array for ListArray<P> {
     fn len(&self) -> usize {
         self.offsets.len() - 1
     }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.offsets.shrink_to_fit();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -216,6 +236,12 @@ impl<P: Primitive> NullableFixedSizedListArray<P> {
             validity: Bitmap::new(),
         }
     }
+
+    /// Every list keeps a byte aligned validity, so slices of the bitmap never start mid-byte.
+    #[inline]
+    fn validity_step(&self) -> usize {
+        (self.data.list_size + 7) / 8 * 8
+    }
 }
 
 impl<P: Primitive> Array for NullableFixedSizedListArray<P> {
@@ -225,7 +251,7 @@ impl<P: Primitive> Array for NullableFixedSizedListArray<P> {
 
     #[inline]
     fn get(&self, id: usize) -> Option<Self::ItemRef<'_>> {
-        if id * self.data.list_size > self.data.data.len() {
+        if id >= self.len() {
             None
         } else {
             Some(self.get_unchecked(id))
@@ -234,7 +260,7 @@ impl<P: Primitive> Array for NullableFixedSizedListArray<P> {
 
     #[inline]
     fn get_unchecked(&self, id: usize) -> Self::ItemRef<'_> {
-        let validity_step = (self.data.list_size + 7) / 8 * 8;
+        let validity_step = self.validity_step();
         NullableFixedSizeListRef {
             validity: self.validity.slice(id * validity_step, (id + 1) * validity_step),
             data: self
@@ -245,22 +271,31 @@ impl<P: Primitive> Array for NullableFixedSizedListArray<P> {
 
     #[inline]
     fn get_mut(&mut self, offset: usize) -> Option<Self::ItemRefMut<'_>> {
+        if offset >= self.len() {
+            return None;
+        }
+        let validity_step = self.validity_step();
         let (start, end) = (offset * self.data.list_size, (offset + 1) * self.data.list_size);
         Some(NullableFixedSizeListRefMut::new(
-            self.validity.slice_mut(start, end),
+            self.validity
+                .slice_mut(offset * validity_step, (offset + 1) * validity_step),
             self.data.slice_raw_mut(start, end),
         ))
     }
 
     #[inline]
     fn push(&mut self, value: Self::ItemRef<'_>) {
-        self.validity.add(value.validity);
+        let validity_step = self.validity_step();
+        for i in 0..validity_step {
+            self.validity
+                .push(i < value.validity.len() && value.validity.get_bit(i));
+        }
         self.data.data.extend_from_slice(value.data);
     }
 
     #[inline]
     fn push_zero(&mut self) {
-        for _ in 0..self.data.list_size {
+        for _ in 0..self.validity_step() {
             self.validity.push(false);
         }
         self.data.push_zero();
@@ -270,6 +305,12 @@ impl<P: Primitive> Array for NullableFixedSizedListArray<P> {
     fn len(&self) -> usize {
         self.data.len()
     }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.validity.shrink_to_fit();
+        self.data.shrink_to_fit();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -339,6 +380,12 @@ where
     fn len(&self) -> usize {
         self.data.len()
     }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.values.shrink_to_fit();
+        self.data.shrink_to_fit();
+    }
 }
 
 impl<A: Array + Default> IdentifiedArray for IdArray<A>
@@ -346,6 +393,31 @@ where
     for<'a, 'b> A::ItemRef<'a>: PartialEq<A::ItemRef<'b>> + Hash,
 {
     type ID = usize;
+
+    #[inline]
+    fn id(&self, offset: usize) -> Option<Self::ID> {
+        self.data.get(offset).copied()
+    }
+
+    #[inline]
+    fn lookup_id(&self, value: Self::ItemRef<'_>) -> Option<Self::ID> {
+        match value {
+            Some(value) => self.values.lookup(value),
+            None => Some(0),
+        }
+    }
+
+    #[inline]
+    fn get_by_id(&self, id: Self::ID) -> Self::ItemRef<'_> {
+        self.values.get_unchecked(id)
+    }
+
+    #[inline]
+    fn for_each_id(&self, mut f: impl FnMut(Self::ID, Self::ItemRef<'_>)) {
+        for id in 0..=self.values.len() {
+            f(id, self.values.get_unchecked(id));
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone)]
@@ -393,6 +465,11 @@ impl<P: Primitive> Array for PrimitiveArray<P> {
     fn len(&self) -> usize {
         self.data.len()
     }
+
+    #[inline]
+    fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -443,6 +520,10 @@ impl<P: Primitive, const SIZE: usize> Array for ConstFixedSizedListArray<P, SIZE
     fn len(&self) -> usize {
         self.array.len()
     }
+
+    fn shrink_to_fit(&mut self) {
+        self.array.shrink_to_fit();
+    }
 }
 
 #[cfg(test)]
@@ -451,7 +532,7 @@ mod tests {
     use crate::array::NullableFixedSizedListArray;
 
     use super::scalar::Scalar;
-    use super::{Array, FixedSizedListArray, IdArray, ListArray, PrimitiveArray};
+    use super::{Array, FixedSizedListArray, IdArray, IdentifiedArray, ListArray, PrimitiveArray};
 
     #[test]
     fn test_fixed_sized_list_array() {
@@ -485,6 +566,13 @@ mod tests {
         let mut ref_mut = array.get_mut(0).unwrap();
         ref_mut.insert(0, Some(1));
         assert!(array.get(0).unwrap().get(0) == Some(Some(&1)));
+        array.push_zero();
+        let mut ref_mut = array.get_mut(2).unwrap();
+        ref_mut.insert(1, Some(4));
+        assert!(array.get(2).unwrap().get(0) == Some(None));
+        assert!(array.get(2).unwrap().get(1) == Some(Some(&4)));
+        assert!(array.get(1).unwrap().get(1) == Some(Some(&3)));
+        assert!(array.get(3).is_none());
     }
 
     #[test]
@@ -500,6 +588,24 @@ mod tests {
         assert!(array.get(3).unwrap().unwrap().as_ptr() == array.get(1).unwrap().unwrap().as_ptr());
     }
 
+    #[test]
+    fn test_id_array_lookup() {
+        let mut array = IdArray::<ListArray<u8>>::new(ListArray::<u8>::new());
+        array.push(Some("foo".as_ref()));
+        array.push(None);
+        array.push(Some("bar".as_ref()));
+        array.push(Some("foo".as_ref()));
+        assert!(array.id(0) == array.id(3));
+        assert!(array.id(1) == Some(0));
+        assert!(array.lookup_id(Some("bar".as_ref())) == array.id(2));
+        assert!(array.lookup_id(Some("quaz".as_ref())).is_none());
+        assert!(array.get_by_id(array.id(2).unwrap()) == Some("bar".as_ref()));
+        assert!(array.get_by_id(0).is_none());
+        let mut values = Vec::new();
+        array.for_each_id(|id, value| values.push((id, value.map(|v| v.to_vec()))));
+        assert!(values == vec![(0, None), (1, Some(b"foo".to_vec())), (2, Some(b"bar".to_vec()))]);
+    }
+
     #[test]
     fn test_primitive_array() {
         let mut array = PrimitiveArray::new();
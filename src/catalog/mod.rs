@@ -31,6 +31,11 @@ impl Catalog {
     pub fn get_default(&self) -> Arc<Schema> {
         self.schemas.read().unwrap().get(DEFAULT_RESOURCE_NAME).unwrap().clone()
     }
+
+    #[inline]
+    pub fn insert(&self, name: String, schema: Arc<Schema>) -> Option<Arc<Schema>> {
+        self.schemas.write().unwrap().insert(name, schema)
+    }
 }
 
 #[derive(Debug)]
@@ -61,4 +66,9 @@ impl CatalogList {
             .unwrap()
             .clone()
     }
+
+    #[inline]
+    pub fn insert(&self, name: String, catalog: Arc<Catalog>) -> Option<Arc<Catalog>> {
+        self.catalogs.write().unwrap().insert(name, catalog)
+    }
 }
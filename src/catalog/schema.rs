@@ -20,4 +20,9 @@ impl Schema {
     pub fn get(&self, name: &str) -> Option<Arc<Table>> {
         self.tables.read().unwrap().get(name).cloned()
     }
+
+    #[inline]
+    pub fn insert(&self, table: Arc<Table>) -> Option<Arc<Table>> {
+        self.tables.write().unwrap().insert(String::from(table.name()), table)
+    }
 }
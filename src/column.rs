@@ -1,8 +1,17 @@
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use croaring::Bitmap;
+use hashbrown::HashMap;
+use snafu::Snafu;
+
+use crate::array::compress::CompressedListArray;
 use crate::array::{
     Array, ConstFixedSizedListArray, IdArray, IdentifiedArray, ListArray, NullableFixedSizedListArray, PrimitiveArray,
 };
 use crate::common::{Duration, Instant};
-use crate::index::IndexImpl;
+use crate::index::{Condition, Index, IndexImpl, IndexType};
+use crate::primitive::{Primitive, PrimitiveType};
 
 pub type UInt8Field = NullableFixedSizedListArray<u8>;
 pub type UInt16Field = NullableFixedSizedListArray<u16>;
@@ -22,29 +31,266 @@ pub type IPv6Label = IdArray<ConstFixedSizedListArray<u8, 16>>;
 pub type IntLabel = IdArray<PrimitiveArray<i64>>;
 pub type BoolLabel = IdArray<PrimitiveArray<bool>>;
 
+#[derive(Snafu, Debug)]
+pub enum InsertError {
+    #[snafu(display("label: {:?} not found", name))]
+    LabelNotFound { name: String },
+    #[snafu(display("field: {:?} not found", name))]
+    FieldNotFound { name: String },
+    #[snafu(display("mismatched type of label: {:?}, expected: {:?}", name, expected))]
+    MismatchedLabelType { name: String, expected: LabelType },
+    #[snafu(display("mismatched type of field: {:?}, expected: {:?}", name, expected))]
+    MismatchedFieldType { name: String, expected: PrimitiveType },
+    #[snafu(display("timestamp: {} out of chunk range", timestamp))]
+    OutOfRange { timestamp: Instant },
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LabelType {
+    String,
+    IPv4,
+    IPv6,
+    Int,
+    Bool,
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LabelValue<'a> {
+    String(&'a str),
+    IPv4(Ipv4Addr),
+    IPv6(Ipv6Addr),
+    Int(i64),
+    Bool(bool),
+}
+
+impl LabelValue<'_> {
+    #[inline]
+    pub fn data_type(&self) -> LabelType {
+        match self {
+            LabelValue::String(_) => LabelType::String,
+            LabelValue::IPv4(_) => LabelType::IPv4,
+            LabelValue::IPv6(_) => LabelType::IPv6,
+            LabelValue::Int(_) => LabelType::Int,
+            LabelValue::Bool(_) => LabelType::Bool,
+        }
+    }
+}
+
+impl fmt::Display for LabelValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LabelValue::String(value) => write!(f, "{}", value),
+            LabelValue::IPv4(value) => write!(f, "{}", value),
+            LabelValue::IPv6(value) => write!(f, "{}", value),
+            LabelValue::Int(value) => write!(f, "{}", value),
+            LabelValue::Bool(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FieldValue {
+    UInt8(u8),
+    UInt16(u16),
+    UInt32(u32),
+    UInt64(u64),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    Float32(f32),
+    Float64(f64),
+    Bool(bool),
+}
+
+impl FieldValue {
+    #[inline]
+    pub fn data_type(&self) -> PrimitiveType {
+        match self {
+            FieldValue::UInt8(_) => PrimitiveType::U8,
+            FieldValue::UInt16(_) => PrimitiveType::U16,
+            FieldValue::UInt32(_) => PrimitiveType::U32,
+            FieldValue::UInt64(_) => PrimitiveType::U64,
+            FieldValue::Int8(_) => PrimitiveType::I8,
+            FieldValue::Int16(_) => PrimitiveType::I16,
+            FieldValue::Int32(_) => PrimitiveType::I32,
+            FieldValue::Int64(_) => PrimitiveType::I64,
+            FieldValue::Float32(_) => PrimitiveType::F32,
+            FieldValue::Float64(_) => PrimitiveType::F64,
+            FieldValue::Bool(_) => PrimitiveType::Bool,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct LabelMeta {
+    pub name: String,
+    pub data_type: LabelType,
+    /// Indexes kept up to date for every row pushed into the column.
+    pub index: Vec<IndexType<(), u32>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FieldMeta {
+    pub name: String,
+    pub data_type: PrimitiveType,
+}
+
 #[derive(Debug, Clone)]
 pub struct LabelColumn<A: IdentifiedArray> {
+    name: String,
     array: A,
     index: Vec<IndexImpl<A::ID>>,
 }
 
 impl<A: IdentifiedArray> PartialEq for LabelColumn<A> {
     fn eq(&self, other: &Self) -> bool {
-        self.array.iter().eq(other.array.iter())
+        self.name == other.name && self.array.iter().eq(other.array.iter())
+    }
+}
+
+impl<A: IdentifiedArray> LabelColumn<A> {
+    #[inline]
+    pub fn new(name: String, array: A, index: Vec<IndexImpl<A::ID>>) -> Self {
+        Self { name, array, index }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn array(&self) -> &A {
+        &self.array
+    }
+
+    /// Appends a row, updates all indexes with it and returns the id of its value.
+    #[inline]
+    pub fn push(&mut self, value: A::ItemRef<'_>) -> A::ID {
+        self.array.push(value);
+        self.index_last()
+    }
+
+    #[inline]
+    pub fn push_zero(&mut self) -> A::ID {
+        self.array.push_zero();
+        self.index_last()
+    }
+
+    #[inline]
+    fn index_last(&mut self) -> A::ID {
+        let row = self.array.len() - 1;
+        let id = self.array.id(row).unwrap();
+        for index in &mut self.index {
+            index.insert(row as u32, id);
+        }
+        id
+    }
+
+    /// Releases the spare capacity of the array and the indexes.
+    pub fn shrink_to_fit(&mut self) {
+        self.array.shrink_to_fit();
+        for index in &mut self.index {
+            index.shrink_to_fit();
+        }
+    }
+
+    /// Drops the content of all indexes and fills them again from the array.
+    pub fn rebuild_index(&mut self) {
+        for index in &mut self.index {
+            *index = IndexImpl::new(index.data_type());
+            for row in 0..self.array.len() {
+                index.insert(row as u32, self.array.id(row).unwrap());
+            }
+        }
+    }
+
+    /// Rows whose value id satisfies `condition`. Candidates come from the indexes first,
+    /// and are only rechecked against the array when no index is exact, testing each
+    /// distinct id once.
+    pub(crate) fn select(&self, condition: &Condition<'_, A::ID>) -> Bitmap {
+        let mut candidates: Option<Bitmap> = None;
+        let mut exactly = false;
+        for index in &self.index {
+            index.lookup_where(condition, &mut candidates);
+            exactly |= index.exactly();
+        }
+        let mut tested = HashMap::new();
+        let matched = |row: &u32| match self.array.id(*row as usize) {
+            Some(id) => *tested.entry(id).or_insert_with(|| condition.test(&id)),
+            None => false,
+        };
+        match candidates {
+            Some(candidates) if exactly => candidates,
+            Some(candidates) => candidates.iter().filter(matched).collect(),
+            None => (0..self.array.len() as u32).filter(matched).collect(),
+        }
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct FieldColumn<A: Array> {
+    name: String,
     array: A,
 }
 
 impl<A: Array> PartialEq for FieldColumn<A> {
     fn eq(&self, other: &Self) -> bool {
-        self.array.iter().eq(other.array.iter())
+        self.name == other.name && self.array.iter().eq(other.array.iter())
     }
 }
 
+impl<A: Array> FieldColumn<A> {
+    #[inline]
+    pub fn new(name: String, array: A) -> Self {
+        Self { name, array }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[inline]
+    pub fn array(&self) -> &A {
+        &self.array
+    }
+}
+
+macro_rules! dispatch_label {
+    ($label:expr, $column:ident => $body:expr) => {
+        match $label {
+            LabelImpl::String($column) => $body,
+            LabelImpl::IPv4($column) => $body,
+            LabelImpl::IPv6($column) => $body,
+            LabelImpl::Int($column) => $body,
+            LabelImpl::Bool($column) => $body,
+        }
+    };
+}
+
+macro_rules! dispatch_field {
+    ($field:expr, $column:ident => $body:expr) => {
+        dispatch_field!(FieldImpl, $field, $column => $body)
+    };
+    ($impl:ident, $field:expr, $column:ident => $body:expr) => {
+        match $field {
+            $impl::UInt8($column) => $body,
+            $impl::UInt16($column) => $body,
+            $impl::UInt32($column) => $body,
+            $impl::UInt64($column) => $body,
+            $impl::Int8($column) => $body,
+            $impl::Int16($column) => $body,
+            $impl::Int32($column) => $body,
+            $impl::Int64($column) => $body,
+            $impl::Float32($column) => $body,
+            $impl::Float64($column) => $body,
+            $impl::Bool($column) => $body,
+        }
+    };
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum LabelImpl {
     String(LabelColumn<StringLabel>),
@@ -54,6 +300,119 @@ pub enum LabelImpl {
     Bool(LabelColumn<BoolLabel>),
 }
 
+impl LabelImpl {
+    pub fn new(meta: &LabelMeta) -> Self {
+        let name = meta.name.clone();
+        let index = || meta.index.iter().cloned().map(IndexImpl::new).collect();
+        match meta.data_type {
+            LabelType::String => LabelImpl::String(LabelColumn::new(name, IdArray::new(ListArray::new()), index())),
+            LabelType::IPv4 => LabelImpl::IPv4(LabelColumn::new(
+                name,
+                IdArray::new(ConstFixedSizedListArray::new()),
+                index(),
+            )),
+            LabelType::IPv6 => LabelImpl::IPv6(LabelColumn::new(
+                name,
+                IdArray::new(ConstFixedSizedListArray::new()),
+                index(),
+            )),
+            LabelType::Int => LabelImpl::Int(LabelColumn::new(name, IdArray::new(PrimitiveArray::new()), index())),
+            LabelType::Bool => LabelImpl::Bool(LabelColumn::new(name, IdArray::new(PrimitiveArray::new()), index())),
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        dispatch_label!(self, column => column.name())
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> LabelType {
+        match self {
+            LabelImpl::String(_) => LabelType::String,
+            LabelImpl::IPv4(_) => LabelType::IPv4,
+            LabelImpl::IPv6(_) => LabelType::IPv6,
+            LabelImpl::Int(_) => LabelType::Int,
+            LabelImpl::Bool(_) => LabelType::Bool,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        dispatch_label!(self, column => column.array().len())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Id of the value at `offset`, ids are only comparable within the same column.
+    #[inline]
+    pub fn id(&self, offset: usize) -> Option<usize> {
+        dispatch_label!(self, column => column.array().id(offset))
+    }
+
+    /// Value behind an id of this column, `None` for an absent label.
+    pub fn get_by_id(&self, id: usize) -> Option<LabelValue<'_>> {
+        match self {
+            LabelImpl::String(column) => column
+                .array()
+                .get_by_id(id)
+                .map(|value| LabelValue::String(std::str::from_utf8(value).unwrap())),
+            LabelImpl::IPv4(column) => column
+                .array()
+                .get_by_id(id)
+                .map(|value| LabelValue::IPv4(<[u8; 4]>::try_from(value).unwrap().into())),
+            LabelImpl::IPv6(column) => column
+                .array()
+                .get_by_id(id)
+                .map(|value| LabelValue::IPv6(<[u8; 16]>::try_from(value).unwrap().into())),
+            LabelImpl::Int(column) => column.array().get_by_id(id).map(|value| LabelValue::Int(*value)),
+            LabelImpl::Bool(column) => column.array().get_by_id(id).map(|value| LabelValue::Bool(*value)),
+        }
+    }
+
+    /// Id of `value` if the column has seen it, `None` is the id of an absent label.
+    /// The type of `value` must match the column.
+    pub(crate) fn lookup_id(&self, value: Option<&LabelValue<'_>>) -> Option<usize> {
+        match (self, value) {
+            (_, None) => Some(0),
+            (LabelImpl::String(column), Some(LabelValue::String(value))) => {
+                column.array().lookup_id(Some(value.as_bytes()))
+            }
+            (LabelImpl::IPv4(column), Some(LabelValue::IPv4(value))) => column.array().lookup_id(Some(&value.octets())),
+            (LabelImpl::IPv6(column), Some(LabelValue::IPv6(value))) => column.array().lookup_id(Some(&value.octets())),
+            (LabelImpl::Int(column), Some(LabelValue::Int(value))) => column.array().lookup_id(Some(value)),
+            (LabelImpl::Bool(column), Some(LabelValue::Bool(value))) => column.array().lookup_id(Some(value)),
+            _ => None,
+        }
+    }
+
+    /// Appends a row and returns the id of its value. The type of `value` must match the column.
+    pub(crate) fn push(&mut self, value: Option<&LabelValue<'_>>) -> usize {
+        match (self, value) {
+            (label, None) => dispatch_label!(label, column => column.push_zero()),
+            (LabelImpl::String(column), Some(LabelValue::String(value))) => column.push(Some(value.as_bytes())),
+            (LabelImpl::IPv4(column), Some(LabelValue::IPv4(value))) => column.push(Some(&value.octets())),
+            (LabelImpl::IPv6(column), Some(LabelValue::IPv6(value))) => column.push(Some(&value.octets())),
+            (LabelImpl::Int(column), Some(LabelValue::Int(value))) => column.push(Some(value)),
+            (LabelImpl::Bool(column), Some(LabelValue::Bool(value))) => column.push(Some(value)),
+            _ => unreachable!("label type should be checked before push"),
+        }
+    }
+
+    #[inline]
+    pub fn rebuild_index(&mut self) {
+        dispatch_label!(self, column => column.rebuild_index())
+    }
+
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        dispatch_label!(self, column => column.shrink_to_fit())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum FieldImpl {
     UInt8(FieldColumn<UInt8Field>),
@@ -69,6 +428,170 @@ pub enum FieldImpl {
     Bool(FieldColumn<BoolField>),
 }
 
+impl FieldImpl {
+    pub fn new(meta: &FieldMeta, series_len: u32) -> Self {
+        let (name, list_size) = (meta.name.clone(), series_len as usize);
+        match meta.data_type {
+            PrimitiveType::U8 => FieldImpl::UInt8(FieldColumn::new(name, UInt8Field::new(list_size))),
+            PrimitiveType::U16 => FieldImpl::UInt16(FieldColumn::new(name, UInt16Field::new(list_size))),
+            PrimitiveType::U32 => FieldImpl::UInt32(FieldColumn::new(name, UInt32Field::new(list_size))),
+            PrimitiveType::U64 => FieldImpl::UInt64(FieldColumn::new(name, UInt64Field::new(list_size))),
+            PrimitiveType::I8 => FieldImpl::Int8(FieldColumn::new(name, Int8Field::new(list_size))),
+            PrimitiveType::I16 => FieldImpl::Int16(FieldColumn::new(name, Int16Field::new(list_size))),
+            PrimitiveType::I32 => FieldImpl::Int32(FieldColumn::new(name, Int32Field::new(list_size))),
+            PrimitiveType::I64 => FieldImpl::Int64(FieldColumn::new(name, Int64Field::new(list_size))),
+            PrimitiveType::F32 => FieldImpl::Float32(FieldColumn::new(name, Float32Field::new(list_size))),
+            PrimitiveType::F64 => FieldImpl::Float64(FieldColumn::new(name, Float64Field::new(list_size))),
+            PrimitiveType::Bool => FieldImpl::Bool(FieldColumn::new(name, BoolField::new(list_size))),
+        }
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        dispatch_field!(self, column => column.name())
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> PrimitiveType {
+        match self {
+            FieldImpl::UInt8(_) => PrimitiveType::U8,
+            FieldImpl::UInt16(_) => PrimitiveType::U16,
+            FieldImpl::UInt32(_) => PrimitiveType::U32,
+            FieldImpl::UInt64(_) => PrimitiveType::U64,
+            FieldImpl::Int8(_) => PrimitiveType::I8,
+            FieldImpl::Int16(_) => PrimitiveType::I16,
+            FieldImpl::Int32(_) => PrimitiveType::I32,
+            FieldImpl::Int64(_) => PrimitiveType::I64,
+            FieldImpl::Float32(_) => PrimitiveType::F32,
+            FieldImpl::Float64(_) => PrimitiveType::F64,
+            FieldImpl::Bool(_) => PrimitiveType::Bool,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        dispatch_field!(self, column => column.array().len())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub(crate) fn push_zero(&mut self) {
+        dispatch_field!(self, column => column.array.push_zero())
+    }
+
+    /// Calls `f` with the slot and value of every valid sample of the series at `row`.
+    pub fn for_each_sample(&self, row: usize, mut f: impl FnMut(usize, f64)) {
+        dispatch_field!(self, column => {
+            if let Some(series) = column.array().get(row) {
+                let mut slot = 0;
+                while let Some(value) = series.get(slot) {
+                    if let Some(value) = value {
+                        f(slot, value.to_f64());
+                    }
+                    slot += 1;
+                }
+            }
+        })
+    }
+
+    /// Writes `value` into `slot` of the series at `row`. The type of `value` must match the column.
+    pub(crate) fn insert(&mut self, row: usize, slot: usize, value: FieldValue) {
+        macro_rules! insert {
+            ($($variant:ident),*) => {
+                match (self, value) {
+                    $((FieldImpl::$variant(column), FieldValue::$variant(value)) => {
+                        column.array.get_mut(row).unwrap().insert(slot, Some(value))
+                    })*
+                    _ => unreachable!("field type should be checked before insert"),
+                }
+            };
+        }
+        insert!(UInt8, UInt16, UInt32, UInt64, Int8, Int16, Int32, Int64, Float32, Float64, Bool)
+    }
+
+    /// Compresses every series of the column into its read-only form.
+    pub fn compress(&self) -> CompressedFieldImpl {
+        macro_rules! compress {
+            ($($variant:ident),*) => {
+                match self {
+                    $(FieldImpl::$variant(column) => CompressedFieldImpl::$variant(FieldColumn::new(
+                        column.name.clone(),
+                        CompressedListArray::from(&column.array),
+                    )),)*
+                }
+            };
+        }
+        compress!(UInt8, UInt16, UInt32, UInt64, Int8, Int16, Int32, Int64, Float32, Float64, Bool)
+    }
+}
+
+/// Read-only form of [`FieldImpl`], see [`FieldImpl::compress`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompressedFieldImpl {
+    UInt8(FieldColumn<CompressedListArray<u8>>),
+    UInt16(FieldColumn<CompressedListArray<u16>>),
+    UInt32(FieldColumn<CompressedListArray<u32>>),
+    UInt64(FieldColumn<CompressedListArray<u64>>),
+    Int8(FieldColumn<CompressedListArray<i8>>),
+    Int16(FieldColumn<CompressedListArray<i16>>),
+    Int32(FieldColumn<CompressedListArray<i32>>),
+    Int64(FieldColumn<CompressedListArray<i64>>),
+    Float32(FieldColumn<CompressedListArray<f32>>),
+    Float64(FieldColumn<CompressedListArray<f64>>),
+    Bool(FieldColumn<CompressedListArray<bool>>),
+}
+
+impl CompressedFieldImpl {
+    #[inline]
+    pub fn name(&self) -> &str {
+        dispatch_field!(CompressedFieldImpl, self, column => column.name())
+    }
+
+    #[inline]
+    pub fn data_type(&self) -> PrimitiveType {
+        match self {
+            CompressedFieldImpl::UInt8(_) => PrimitiveType::U8,
+            CompressedFieldImpl::UInt16(_) => PrimitiveType::U16,
+            CompressedFieldImpl::UInt32(_) => PrimitiveType::U32,
+            CompressedFieldImpl::UInt64(_) => PrimitiveType::U64,
+            CompressedFieldImpl::Int8(_) => PrimitiveType::I8,
+            CompressedFieldImpl::Int16(_) => PrimitiveType::I16,
+            CompressedFieldImpl::Int32(_) => PrimitiveType::I32,
+            CompressedFieldImpl::Int64(_) => PrimitiveType::I64,
+            CompressedFieldImpl::Float32(_) => PrimitiveType::F32,
+            CompressedFieldImpl::Float64(_) => PrimitiveType::F64,
+            CompressedFieldImpl::Bool(_) => PrimitiveType::Bool,
+        }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        dispatch_field!(CompressedFieldImpl, self, column => column.array().len())
+    }
+
+    /// Calls `f` with the slot and value of every valid sample of the series at `row`.
+    pub fn for_each_sample(&self, row: usize, mut f: impl FnMut(usize, f64)) {
+        dispatch_field!(CompressedFieldImpl, self, column => {
+            if let Some(series) = column.array().get(row) {
+                for (slot, value) in series.iter().enumerate() {
+                    if let Some(value) = value {
+                        f(slot, value.to_f64());
+                    }
+                }
+            }
+        })
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ChunkMeta {
     pub(crate) start_at: Instant,
@@ -77,9 +600,50 @@ pub struct ChunkMeta {
 }
 
 impl ChunkMeta {
+    #[inline]
+    pub fn new(start_at: Instant, time_interval: Duration, series_len: u32) -> Self {
+        Self {
+            start_at,
+            time_interval,
+            series_len,
+        }
+    }
+
+    #[inline]
+    pub fn start_at(&self) -> Instant {
+        self.start_at
+    }
+
+    #[inline]
+    pub fn time_interval(&self) -> Duration {
+        self.time_interval
+    }
+
+    #[inline]
+    pub fn series_len(&self) -> u32 {
+        self.series_len
+    }
+
+    /// Timestamp of the last slot.
     #[inline]
     pub(crate) fn end_at(&self) -> Instant {
-        self.start_at + self.time_interval * (self.series_len - 1)
+        self.start_at + self.time_interval * self.series_len.saturating_sub(1)
+    }
+
+    /// End of the time window of this chunk, exclusive.
+    #[inline]
+    pub(crate) fn window_end(&self) -> Instant {
+        self.start_at + self.time_interval * self.series_len
+    }
+
+    /// Slot of `timestamp` in the series of this chunk, samples between two slots round down.
+    #[inline]
+    pub(crate) fn slot(&self, timestamp: Instant) -> Option<usize> {
+        if timestamp < self.start_at || timestamp >= self.window_end() {
+            None
+        } else {
+            Some(((timestamp - self.start_at) / self.time_interval) as usize)
+        }
     }
 }
 
@@ -88,4 +652,336 @@ pub struct MutableChunk {
     pub label: Vec<LabelImpl>,
     pub field: Vec<FieldImpl>,
     pub meta: ChunkMeta,
+    series: HashMap<Vec<usize>, u32>,
+}
+
+impl MutableChunk {
+    pub fn new(label: Vec<LabelImpl>, field: Vec<FieldImpl>, meta: ChunkMeta) -> Self {
+        let mut chunk = Self {
+            label,
+            field,
+            meta,
+            series: HashMap::new(),
+        };
+        for row in 0..chunk.len() {
+            let ids = chunk.label.iter().map(|label| label.id(row).unwrap()).collect();
+            chunk.series.entry(ids).or_insert(row as u32);
+        }
+        chunk
+    }
+
+    #[inline]
+    pub fn get_label(&self, name: &str) -> Option<&LabelImpl> {
+        self.label.iter().find(|label| label.name() == name)
+    }
+
+    #[inline]
+    pub fn get_field(&self, name: &str) -> Option<&FieldImpl> {
+        self.field.iter().find(|field| field.name() == name)
+    }
+
+    /// Number of series stored in this chunk.
+    #[inline]
+    pub fn len(&self) -> usize {
+        match (self.label.first(), self.field.first()) {
+            (Some(label), _) => label.len(),
+            (None, Some(field)) => field.len(),
+            (None, None) => 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Rebuilds the indexes of every label column, e.g. after the columns were assembled by hand.
+    pub fn rebuild_index(&mut self) {
+        for label in &mut self.label {
+            label.rebuild_index();
+        }
+    }
+
+    /// Writes one sample of the series identified by `labels`, creating the series if it is new.
+    /// Labels missing from `labels` are absent for the series, fields missing from `fields` stay null.
+    pub fn insert(
+        &mut self,
+        labels: &[(&str, LabelValue<'_>)],
+        timestamp: Instant,
+        fields: &[(&str, FieldValue)],
+    ) -> Result<(), InsertError> {
+        let slot = self.meta.slot(timestamp).ok_or(InsertError::OutOfRange { timestamp })?;
+        let mut values = vec![None; self.label.len()];
+        for (name, value) in labels {
+            let position = self
+                .label
+                .iter()
+                .position(|label| label.name() == *name)
+                .ok_or_else(|| InsertError::LabelNotFound {
+                    name: String::from(*name),
+                })?;
+            let expected = self.label[position].data_type();
+            if value.data_type() != expected {
+                return Err(InsertError::MismatchedLabelType {
+                    name: String::from(*name),
+                    expected,
+                });
+            }
+            values[position] = Some(value);
+        }
+        let mut samples = Vec::with_capacity(fields.len());
+        for (name, value) in fields {
+            let position = self
+                .field
+                .iter()
+                .position(|field| field.name() == *name)
+                .ok_or_else(|| InsertError::FieldNotFound {
+                    name: String::from(*name),
+                })?;
+            let expected = self.field[position].data_type();
+            if value.data_type() != expected {
+                return Err(InsertError::MismatchedFieldType {
+                    name: String::from(*name),
+                    expected,
+                });
+            }
+            samples.push((position, *value));
+        }
+
+        let row = self.lookup_or_insert_series(&values);
+        for (position, value) in samples {
+            self.field[position].insert(row, slot, value);
+        }
+        Ok(())
+    }
+
+    fn lookup_or_insert_series(&mut self, values: &[Option<&LabelValue<'_>>]) -> usize {
+        let ids = self
+            .label
+            .iter()
+            .zip(values)
+            .map(|(label, value)| label.lookup_id(*value))
+            .collect::<Option<Vec<_>>>();
+        if let Some(row) = ids.and_then(|ids| self.series.get(&ids).copied()) {
+            return row as usize;
+        }
+        let row = self.len();
+        let ids = self
+            .label
+            .iter_mut()
+            .zip(values)
+            .map(|(label, value)| label.push(*value))
+            .collect();
+        for field in &mut self.field {
+            field.push_zero();
+        }
+        self.series.insert(ids, row as u32);
+        row
+    }
+
+    /// Seals the chunk, once its time window is closed nothing is written to it anymore.
+    pub fn freeze(self) -> ImmutableChunk {
+        let mut label = self.label;
+        for label in &mut label {
+            label.shrink_to_fit();
+        }
+        ImmutableChunk {
+            label,
+            field: self.field.iter().map(FieldImpl::compress).collect(),
+            meta: self.meta,
+        }
+    }
+}
+
+/// A sealed chunk with shrunk labels and indexes and compressed fields, see [`MutableChunk::freeze`].
+/// It is read-only, so it can be shared across cores behind an `Arc`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImmutableChunk {
+    pub label: Vec<LabelImpl>,
+    pub field: Vec<CompressedFieldImpl>,
+    pub meta: ChunkMeta,
+}
+
+impl ImmutableChunk {
+    #[inline]
+    pub fn get_label(&self, name: &str) -> Option<&LabelImpl> {
+        self.label.iter().find(|label| label.name() == name)
+    }
+
+    #[inline]
+    pub fn get_field(&self, name: &str) -> Option<&CompressedFieldImpl> {
+        self.field.iter().find(|field| field.name() == name)
+    }
+
+    /// Number of series stored in this chunk.
+    #[inline]
+    pub fn len(&self) -> usize {
+        match (self.label.first(), self.field.first()) {
+            (Some(label), _) => label.len(),
+            (None, Some(field)) => field.len(),
+            (None, None) => 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use croaring::Bitmap;
+
+    use crate::array::{Array, IdArray, ListArray};
+    use crate::common::{Duration, Instant};
+    use crate::index::{Condition, Index, IndexImpl, IndexType};
+    use crate::primitive::PrimitiveType;
+
+    use super::{
+        ChunkMeta, CompressedFieldImpl, FieldImpl, FieldMeta, FieldValue, LabelColumn, LabelImpl, LabelMeta, LabelType,
+        LabelValue, MutableChunk,
+    };
+
+    #[test]
+    fn test_index_on_push() {
+        let mut label = LabelImpl::new(&LabelMeta {
+            name: String::from("job"),
+            data_type: LabelType::String,
+            index: vec![IndexType::Inverted(()), IndexType::Sparse(2)],
+        });
+        for value in ["a", "b", "a"] {
+            label.push(Some(&LabelValue::String(value)));
+        }
+        label.push(None);
+        let column = match &label {
+            LabelImpl::String(column) => column,
+            _ => unreachable!(),
+        };
+        assert!(column.select(&Condition::In(&[1])) == Bitmap::of(&[0, 2]));
+        assert!(column.select(&Condition::In(&[0])) == Bitmap::of(&[3]));
+        assert!(column.select(&Condition::NotIn(&[1])) == Bitmap::of(&[1, 3]));
+        assert!(column.select(&Condition::NotMatches(&|id| *id != 0)) == Bitmap::of(&[3]));
+        let mut rows = None;
+        column.index[0].lookup(&1, &mut rows);
+        assert!(rows == Some(Bitmap::of(&[0, 2])));
+        let mut rows = None;
+        column.index[1].lookup(&1, &mut rows);
+        assert!(rows == Some(Bitmap::of(&[0, 1, 2, 3])));
+    }
+
+    #[test]
+    fn test_select_without_exact_index() {
+        let mut label = LabelImpl::new(&LabelMeta {
+            name: String::from("job"),
+            data_type: LabelType::String,
+            index: vec![IndexType::Sparse(2)],
+        });
+        for value in ["a", "b", "a", "c"] {
+            label.push(Some(&LabelValue::String(value)));
+        }
+        let column = match &label {
+            LabelImpl::String(column) => column,
+            _ => unreachable!(),
+        };
+        assert!(column.select(&Condition::In(&[1])) == Bitmap::of(&[0, 2]));
+        assert!(column.select(&Condition::NotIn(&[1])) == Bitmap::of(&[1, 3]));
+        assert!(column.select(&Condition::Matches(&|id| *id > 1)) == Bitmap::of(&[1, 3]));
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        let mut array = IdArray::new(ListArray::new());
+        for value in ["a", "b", "b"] {
+            array.push(Some(value.as_bytes()));
+        }
+        let column = LabelColumn::new(
+            String::from("job"),
+            array,
+            vec![IndexImpl::new(IndexType::Inverted(()))],
+        );
+        let mut chunk = MutableChunk::new(
+            vec![LabelImpl::String(column)],
+            vec![],
+            ChunkMeta::new(Instant::from_millis(0), Duration::SECOND, 60),
+        );
+        let select = |chunk: &MutableChunk| match chunk.get_label("job").unwrap() {
+            LabelImpl::String(column) => column.select(&Condition::In(&[2])),
+            _ => unreachable!(),
+        };
+        assert!(select(&chunk).is_empty());
+        chunk.rebuild_index();
+        assert!(select(&chunk) == Bitmap::of(&[1, 2]));
+    }
+
+    #[test]
+    fn test_compress() {
+        let mut field = FieldImpl::new(
+            &FieldMeta {
+                name: String::from("value"),
+                data_type: PrimitiveType::F64,
+            },
+            4,
+        );
+        field.push_zero();
+        field.push_zero();
+        field.insert(0, 1, FieldValue::Float64(0.5));
+        field.insert(0, 3, FieldValue::Float64(2.0));
+        let compressed = field.compress();
+        assert_eq!(compressed.name(), "value");
+        assert_eq!(compressed.data_type(), PrimitiveType::F64);
+        assert_eq!(compressed.len(), 2);
+        let array = match &compressed {
+            CompressedFieldImpl::Float64(column) => column.array(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            array.get(0).unwrap().iter().collect::<Vec<_>>(),
+            vec![None, Some(0.5), None, Some(2.0)]
+        );
+        assert!(array.get(1).unwrap().iter().all(|value| value.is_none()));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut chunk = MutableChunk::new(
+            vec![LabelImpl::new(&LabelMeta {
+                name: String::from("job"),
+                data_type: LabelType::String,
+                index: vec![IndexType::Inverted(())],
+            })],
+            vec![FieldImpl::new(
+                &FieldMeta {
+                    name: String::from("value"),
+                    data_type: PrimitiveType::I64,
+                },
+                60,
+            )],
+            ChunkMeta::new(Instant::from_millis(0), Duration::SECOND, 60),
+        );
+        for (job, second) in [("api", 0), ("node", 1), ("api", 2)] {
+            chunk
+                .insert(
+                    &[("job", LabelValue::String(job))],
+                    Instant::from_millis(second * 1000),
+                    &[("value", FieldValue::Int64(second))],
+                )
+                .unwrap();
+        }
+        let chunk = chunk.freeze();
+        assert_eq!(chunk.len(), 2);
+        let job = match chunk.get_label("job").unwrap() {
+            LabelImpl::String(column) => column,
+            _ => unreachable!(),
+        };
+        assert!(job.select(&Condition::In(&[1])) == Bitmap::of(&[0]));
+        let value = match chunk.get_field("value").unwrap() {
+            CompressedFieldImpl::Int64(column) => column.array(),
+            _ => unreachable!(),
+        };
+        let series = value.get(0).unwrap();
+        assert_eq!(series.get(0), Some(Some(0)));
+        assert_eq!(series.get(1), Some(None));
+        assert_eq!(series.get(2), Some(Some(2)));
+    }
 }
@@ -18,6 +18,11 @@ pub struct Duration {
 impl Duration {
     pub const SECOND: Self = Duration { millis: MILLIS_PER_SEC };
 
+    #[inline]
+    pub const fn from_millis(m: i64) -> Self {
+        Self { millis: m }
+    }
+
     #[inline]
     pub fn as_millis(&self) -> i64 {
         self.millis
@@ -1,33 +1,335 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use runtime::error::RuntimeError;
-use runtime::{CoreId, Runtime};
+use runtime::{CoreId, Runtime, SchedulingClass, Task};
 use snafu::{ResultExt, Snafu};
 
 use crate::catalog::CatalogList;
-use crate::expression::ExprImpl;
+use crate::column::{FieldValue, InsertError, LabelValue};
+use crate::common::Instant;
+use crate::context::Context;
+use crate::expression::error::ExprError;
+use crate::expression::{gather, ExprImpl};
+use crate::source::Table;
 
 #[derive(Snafu, Debug)]
 pub enum ExecutorError {
     #[snafu(display("executor runtime initialization failed: {source}"))]
     Runtime { source: RuntimeError },
+    #[snafu(display("evaluation task failed: {source}"))]
+    Task { source: RuntimeError },
+    #[snafu(display("evaluation failed: {source}"))]
+    Evaluate { source: ExprError },
+    #[snafu(display("insert failed: {source}"))]
+    Insert { source: InsertError },
 }
 
 #[derive(Debug)]
 pub struct Executor {
     runtime: Runtime,
     catalog_list: Arc<CatalogList>,
+    /// Core the next expression is evaluated on, expressions are spread round-robin.
+    next_core: AtomicUsize,
 }
 
 impl Executor {
+    /// Runs an executor pinned to each of `require_cores`. If they can't be pinned, as in containers
+    /// that hide cores, as many executors run unpinned. Without `require_cores` there is one per
+    /// CPU available to the process.
     pub fn new(require_cores: &[CoreId], catalog_list: Arc<CatalogList>) -> Result<Self, ExecutorError> {
-        let mut runtime = Runtime::new(require_cores).context(RuntimeSnafu {})?;
+        let mut runtime = if require_cores.is_empty() {
+            Runtime::from_available_parallelism()
+        } else {
+            match Runtime::new(require_cores) {
+                Ok(runtime) => runtime,
+                Err(RuntimeError::GetCoreError | RuntimeError::NotMuchCores { .. }) => {
+                    Runtime::unpinned(require_cores.len())
+                }
+                Err(source) => return Err(source).context(RuntimeSnafu {}),
+            }
+        };
         runtime.run();
-        Ok(Self { runtime, catalog_list })
+        Ok(Self {
+            runtime,
+            catalog_list,
+            next_core: AtomicUsize::new(0),
+        })
     }
 
-    pub fn execute(&self, _expr: Box<ExprImpl>) {
-        // self.runtime.run(f);
-        todo!()
+    #[inline]
+    pub fn catalog_list(&self) -> &Arc<CatalogList> {
+        &self.catalog_list
+    }
+
+    /// Number of cores, tables should have as many shards to spread over all of them.
+    #[inline]
+    pub fn cores(&self) -> usize {
+        self.runtime.cores()
+    }
+
+    /// Core owning `shard` of every table.
+    #[inline]
+    pub fn core_of(&self, shard: usize) -> usize {
+        shard % self.runtime.cores()
+    }
+
+    /// Runs `f` on the core owning `shard`, as a latency sensitive task that queries can't starve.
+    pub fn spawn_to_shard<T: Send + 'static, F: Future<Output = T> + 'static>(
+        &self,
+        shard: usize,
+        f: impl (FnOnce() -> F) + Send,
+    ) -> Task<Result<T, RuntimeError>> {
+        self.runtime.spawn_to(self.core_of(shard), f)
+    }
+
+    /// Writes a sample into `table` on the core owning the shard of its series, see
+    /// [`Table::shard_of`], so that every shard is only written by its core.
+    pub fn insert(
+        &self,
+        table: &Arc<Table>,
+        labels: &[(&str, LabelValue<'_>)],
+        timestamp: Instant,
+        fields: &[(&str, FieldValue)],
+    ) -> impl Future<Output = Result<(), ExecutorError>> {
+        let shard = table.shard_of(labels);
+        let table = Arc::clone(table);
+        let labels = labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), OwnedLabel::new(*value)))
+            .collect::<Vec<_>>();
+        let fields = fields
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect::<Vec<_>>();
+        let task = self.spawn_to_shard(shard, move || async move {
+            let labels = labels
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_value()))
+                .collect::<Vec<_>>();
+            let fields = fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), *value))
+                .collect::<Vec<_>>();
+            table.insert(&labels, timestamp, &fields)
+        });
+        async move { task.await.context(TaskSnafu)?.context(InsertSnafu) }
+    }
+
+    /// Evaluates `expr` without arguments in a fresh [`Context`]. A plan starting with a scan is
+    /// scattered to every core, which scans only the shards it owns, and the partial results are
+    /// gathered on one of the cores. Evaluation of a part stays on its core, so expressions holding
+    /// non-`Send` state across awaits are fine. Queries run as batch tasks, dropping the returned
+    /// future cancels them.
+    pub fn execute(&self, expr: Box<ExprImpl>) -> impl Future<Output = Result<ExprImpl, ExecutorError>> {
+        let task = self.spawn_execute(expr);
+        async move { task.await.context(TaskSnafu)? }
+    }
+
+    fn spawn_execute(&self, expr: Box<ExprImpl>) -> Task<Result<Result<ExprImpl, ExecutorError>, RuntimeError>> {
+        let core = self.next_core.fetch_add(1, Ordering::Relaxed) % self.runtime.cores();
+        let split = match gather::split(&expr) {
+            Some(split) => split,
+            None => return self.spawn_query(core, move || evaluate(*expr, vec![])),
+        };
+        let cores = self.runtime.cores();
+        let partials = (0..cores)
+            .map(|owner| {
+                let scatter = split.scatter(owner, cores);
+                self.spawn_query(owner, move || evaluate(scatter, vec![]))
+            })
+            .collect::<Vec<_>>();
+        let gather = split.gather().clone();
+        self.spawn_query(core, move || async move {
+            let mut results = Vec::with_capacity(partials.len());
+            for partial in partials {
+                results.push(partial.await.context(TaskSnafu)??);
+            }
+            evaluate(gather, results).await
+        })
+    }
+
+    fn spawn_query<F: Future<Output = Result<ExprImpl, ExecutorError>> + 'static>(
+        &self,
+        core: usize,
+        f: impl (FnOnce() -> F) + Send,
+    ) -> Task<Result<Result<ExprImpl, ExecutorError>, RuntimeError>> {
+        self.runtime.spawn_to_with_class(core, SchedulingClass::Batch, f)
+    }
+}
+
+/// A label value of a write on its way to another core.
+enum OwnedLabel {
+    String(String),
+    Value(LabelValue<'static>),
+}
+
+impl OwnedLabel {
+    fn new(value: LabelValue<'_>) -> Self {
+        match value {
+            LabelValue::String(value) => OwnedLabel::String(value.to_owned()),
+            LabelValue::IPv4(value) => OwnedLabel::Value(LabelValue::IPv4(value)),
+            LabelValue::IPv6(value) => OwnedLabel::Value(LabelValue::IPv6(value)),
+            LabelValue::Int(value) => OwnedLabel::Value(LabelValue::Int(value)),
+            LabelValue::Bool(value) => OwnedLabel::Value(LabelValue::Bool(value)),
+        }
+    }
+
+    fn as_value(&self) -> LabelValue<'_> {
+        match self {
+            OwnedLabel::String(value) => LabelValue::String(value),
+            OwnedLabel::Value(value) => *value,
+        }
+    }
+}
+
+async fn evaluate(expr: ExprImpl, args: Vec<ExprImpl>) -> Result<ExprImpl, ExecutorError> {
+    let args = args.iter().map(ExprImpl::as_impl_ref).collect::<Vec<_>>();
+    expr.evaluate(&mut Context::new(), &args).await.context(EvaluateSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_lite::future;
+    use runtime::CoreId;
+
+    use crate::catalog::CatalogList;
+    use crate::column::{FieldMeta, FieldValue, InsertError, LabelMeta, LabelType, LabelValue};
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::error::ExprError;
+    use crate::expression::project::Rows;
+    use crate::expression::scan::Scanner;
+    use crate::expression::{ExprImpl, Expression, Literal};
+    use crate::primitive::PrimitiveType;
+    use crate::query::{promql, sql};
+    use crate::source::{Table, TableMeta};
+
+    use super::{Executor, ExecutorError};
+
+    #[test]
+    fn test_execute() {
+        let catalog_list = Arc::new(CatalogList::new());
+        let executor = Executor::new(&[CoreId { id: 0 }], catalog_list.clone()).unwrap();
+        let result = future::block_on(executor.execute(Box::new(String::from("foo").as_impl_ref().to_owned())));
+        assert!(result.unwrap().as_any().downcast_ref::<String>().unwrap() == "foo");
+
+        let scanner = Scanner::new(catalog_list, Box::new(String::from("foo").as_impl_ref().to_owned()))
+            .with_resource(Literal::new(String::from("missing")));
+        let result = future::block_on(executor.execute(Box::new(scanner.as_impl_ref().to_owned())));
+        assert!(matches!(
+            result,
+            Err(ExecutorError::Evaluate {
+                source: ExprError::ResourceNotFound { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn test_unpinned() {
+        let catalog_list = Arc::new(CatalogList::new());
+        let foo = || Box::new(String::from("foo").as_impl_ref().to_owned());
+        // more cores than there are
+        let executor = Executor::new(&[CoreId { id: usize::MAX }; 2], catalog_list.clone()).unwrap();
+        assert_eq!(executor.cores(), 2);
+        assert!(future::block_on(executor.execute(foo())).is_ok());
+
+        let executor = Executor::new(&[], catalog_list).unwrap();
+        assert!(executor.cores() >= 1);
+        assert!(future::block_on(executor.execute(foo())).is_ok());
+    }
+
+    #[test]
+    fn test_execute_sharded() {
+        let catalog_list = Arc::new(CatalogList::new());
+        // two executors sharing one core still own separate shards
+        let executor = Executor::new(&[CoreId { id: 0 }, CoreId { id: 0 }], catalog_list.clone()).unwrap();
+        let table = Arc::new(
+            Table::with_shards(
+                String::from("requests"),
+                TableMeta {
+                    labels: vec![LabelMeta {
+                        name: String::from("job"),
+                        data_type: LabelType::String,
+                        index: vec![],
+                    }],
+                    fields: vec![FieldMeta {
+                        name: String::from("value"),
+                        data_type: PrimitiveType::F64,
+                    }],
+                    time_interval: Duration::SECOND,
+                    series_len: 60,
+                },
+                executor.cores() * 2,
+            )
+            .unwrap(),
+        );
+        catalog_list.get_default().get_default().insert(table.clone());
+        let writes = ["a", "b", "c", "d", "e", "f"]
+            .into_iter()
+            .flat_map(|job| (0..4).map(move |second| (job, second)))
+            .map(|(job, second)| {
+                executor.insert(
+                    &table,
+                    &[("job", LabelValue::String(job))],
+                    Instant::from_millis(second * 1000),
+                    &[("value", FieldValue::Float64(second as f64))],
+                )
+            })
+            .collect::<Vec<_>>();
+        for write in writes {
+            future::block_on(write).unwrap();
+        }
+        let unknown = executor.insert(
+            &table,
+            &[("job", LabelValue::String("a"))],
+            Instant::from_millis(0),
+            &[("latency", FieldValue::Float64(0.0))],
+        );
+        assert!(matches!(
+            future::block_on(unknown),
+            Err(ExecutorError::Insert {
+                source: InsertError::FieldNotFound { .. }
+            })
+        ));
+
+        let now = Instant::from_millis(3000);
+        let plans = [
+            sql::parse(
+                "SELECT job, time, value FROM requests WHERE time >= 2000",
+                catalog_list.clone(),
+                now,
+            )
+            .unwrap(),
+            sql::parse(
+                "SELECT AVG(value) FROM requests WHERE job <> 'a'",
+                catalog_list.clone(),
+                now,
+            )
+            .unwrap(),
+            promql::parse(
+                "sum(rate(requests[2s]))",
+                catalog_list.clone(),
+                Instant::from_millis(0)..now,
+            )
+            .unwrap(),
+        ];
+        for plan in plans {
+            // rows of one timestamp are ordered by shard
+            let sorted = |result: ExprImpl| match result.as_any().downcast_ref::<Rows>() {
+                Some(rows) => {
+                    let mut rows = rows.clone();
+                    rows.rows.sort_by_key(|row| format!("{:?}", row));
+                    rows.as_impl_ref().to_owned()
+                }
+                None => result,
+            };
+            let expected = sorted(future::block_on(plan.evaluate(&mut Context::new(), &[])).unwrap());
+            let result = sorted(future::block_on(executor.execute(Box::new(plan))).unwrap());
+            assert!(result == expected, "{:?} != {:?}", result, expected);
+        }
     }
 }
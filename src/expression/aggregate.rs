@@ -0,0 +1,346 @@
+use std::future::Future;
+
+use hashbrown::HashMap;
+
+use crate::context::Context;
+
+use super::chunk::selected_chunks;
+use super::error::ExprError;
+use super::matrix::{Gathered, Matrix, Series};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AggregateOp {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    Stddev,
+    Stdvar,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Grouping {
+    By(Vec<String>),
+    Without(Vec<String>),
+}
+
+impl Grouping {
+    #[inline]
+    fn includes(&self, name: &str) -> bool {
+        match self {
+            Grouping::By(names) => names.iter().any(|by| by == name),
+            Grouping::Without(names) => names.iter().all(|without| without != name),
+        }
+    }
+}
+
+/// Groups the series of a chunk stream by their labels, and reduces `field` of every group
+/// per timestamp slot into a [`Matrix`]. Null samples are skipped, slots without any sample stay null.
+/// A single [`Matrix`] argument, e.g. the output of a range function, is aggregated the same way.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Aggregate {
+    op: AggregateOp,
+    field: String,
+    grouping: Grouping,
+}
+
+impl Aggregate {
+    pub fn new(op: AggregateOp, field: String, grouping: Grouping) -> Self {
+        Self { op, field, grouping }
+    }
+
+    #[inline]
+    pub(crate) fn field(&self) -> &str {
+        &self.field
+    }
+
+    fn aggregate_matrix(&self, matrix: &Matrix) -> Matrix {
+        let len = matrix.series.first().map_or(0, |series| series.values.len());
+        let mut groups: HashMap<Vec<(String, String)>, Vec<Accumulator>> = HashMap::new();
+        for series in &matrix.series {
+            let labels = series
+                .labels
+                .iter()
+                .filter(|(name, _)| self.grouping.includes(name))
+                .cloned()
+                .collect::<Vec<_>>();
+            let slots = groups
+                .entry(labels)
+                .or_insert_with(|| vec![Accumulator::default(); len]);
+            for (slot, value) in slots.iter_mut().zip(&series.values) {
+                if let Some(value) = value {
+                    slot.push(*value);
+                }
+            }
+        }
+        let mut series = groups
+            .into_iter()
+            .map(|(labels, slots)| Series {
+                labels,
+                values: slots.iter().map(|slot| slot.result(self.op)).collect(),
+            })
+            .collect::<Vec<_>>();
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        Matrix {
+            start_at: matrix.start_at,
+            time_interval: matrix.time_interval,
+            series,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Accumulator {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    /// Running mean and sum of squared deviations, as in Welford's algorithm.
+    mean: f64,
+    m2: f64,
+}
+
+impl Accumulator {
+    #[inline]
+    pub(crate) fn push(&mut self, value: f64) {
+        // NaN only wins when there is nothing else, like in Prometheus
+        if self.count == 0 || value < self.min || self.min.is_nan() {
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max || self.max.is_nan() {
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    #[inline]
+    pub(crate) fn result(&self, op: AggregateOp) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        Some(match op {
+            AggregateOp::Sum => self.sum,
+            AggregateOp::Avg => self.sum / self.count as f64,
+            AggregateOp::Min => self.min,
+            AggregateOp::Max => self.max,
+            AggregateOp::Count => self.count as f64,
+            AggregateOp::Stddev => (self.m2 / self.count as f64).sqrt(),
+            AggregateOp::Stdvar => self.m2 / self.count as f64,
+        })
+    }
+}
+
+impl Expression for Aggregate {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let matrix = match args {
+                [arg] if arg.expr_type() == ExprType::Matrix => {
+                    self.aggregate_matrix(downcast::<Matrix>(*arg, ExprType::Matrix)?)
+                }
+                _ => {
+                    let chunks = selected_chunks(args)?;
+                    let gathered = Gathered::gather(
+                        &chunks,
+                        &self.field,
+                        |name| self.grouping.includes(name),
+                        Accumulator::push,
+                    )?;
+                    gathered.into_matrix(|slots| slots.iter().map(|slot| slot.result(self.op)).collect())
+                }
+            };
+            Ok(matrix.as_impl_ref().to_owned())
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Aggregate,
+            data: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::column::{
+        ChunkMeta, FieldImpl, FieldMeta, FieldValue, LabelImpl, LabelMeta, LabelType, LabelValue, MutableChunk,
+    };
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::error::ExprError;
+    use crate::expression::filter::{BinaryOp, Column, Filter, Predicate};
+    use crate::expression::matrix::Matrix;
+    use crate::expression::{ExprImpl, ExprImplRef, Expression};
+    use crate::primitive::PrimitiveType;
+
+    use super::{Aggregate, AggregateOp, Grouping};
+
+    fn chunk(start_at: i64, samples: &[(&str, &str, i64, f64)]) -> MutableChunk {
+        let label = |name: &str| {
+            LabelImpl::new(&LabelMeta {
+                name: String::from(name),
+                data_type: LabelType::String,
+                index: vec![],
+            })
+        };
+        let field = FieldImpl::new(
+            &FieldMeta {
+                name: String::from("value"),
+                data_type: PrimitiveType::F64,
+            },
+            4,
+        );
+        let mut chunk = MutableChunk::new(
+            vec![label("job"), label("instance")],
+            vec![field],
+            ChunkMeta::new(Instant::from_millis(start_at), Duration::SECOND, 4),
+        );
+        for (job, instance, timestamp, value) in samples {
+            chunk
+                .insert(
+                    &[
+                        ("job", LabelValue::String(job)),
+                        ("instance", LabelValue::String(instance)),
+                    ],
+                    Instant::from_millis(*timestamp),
+                    &[("value", FieldValue::Float64(*value))],
+                )
+                .unwrap();
+        }
+        chunk
+    }
+
+    fn chunks() -> Vec<MutableChunk> {
+        vec![
+            chunk(
+                0,
+                &[
+                    ("api", "a", 0, 1.0),
+                    ("api", "b", 0, 3.0),
+                    ("node", "a", 0, 10.0),
+                    ("api", "a", 1000, 2.0),
+                ],
+            ),
+            chunk(4000, &[("api", "b", 5000, 4.0)]),
+        ]
+    }
+
+    fn aggregate(aggregate: &ExprImpl, args: &[ExprImplRef<'_>]) -> Result<Matrix, ExprError> {
+        let result = future::block_on(aggregate.evaluate(&mut Context::new(), args))?;
+        Ok(result.as_any().downcast_ref::<Matrix>().unwrap().clone())
+    }
+
+    fn by_job(op: AggregateOp) -> ExprImpl {
+        Aggregate::new(op, String::from("value"), Grouping::By(vec![String::from("job")]))
+            .as_impl_ref()
+            .to_owned()
+    }
+
+    fn labels(labels: &[(&str, &str)]) -> Vec<(String, String)> {
+        labels
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_aggregate_ops() {
+        let chunks = chunks();
+        let args = chunks.iter().map(|chunk| chunk.as_impl_ref()).collect::<Vec<_>>();
+        let cases = [
+            (AggregateOp::Sum, Some(4.0), Some(2.0)),
+            (AggregateOp::Avg, Some(2.0), Some(2.0)),
+            (AggregateOp::Min, Some(1.0), Some(2.0)),
+            (AggregateOp::Max, Some(3.0), Some(2.0)),
+            (AggregateOp::Count, Some(2.0), Some(1.0)),
+            (AggregateOp::Stdvar, Some(1.0), Some(0.0)),
+            (AggregateOp::Stddev, Some(1.0), Some(0.0)),
+        ];
+        for (op, first, second) in cases {
+            let matrix = aggregate(&by_job(op), &args).unwrap();
+            assert!(matrix.start_at == Instant::from_millis(0));
+            assert_eq!(matrix.series.len(), 2);
+            let api = &matrix.series[0];
+            assert_eq!(api.labels, labels(&[("job", "api")]));
+            assert_eq!(api.values.len(), 8);
+            assert_eq!(api.values[0], first, "{:?}", op);
+            assert_eq!(api.values[1], second, "{:?}", op);
+            assert_eq!(api.values[2], None);
+            assert!(api.values[5].is_some());
+            assert_eq!(matrix.series[1].labels, labels(&[("job", "node")]));
+        }
+    }
+
+    #[test]
+    fn test_aggregate_without() {
+        let chunks = chunks();
+        let args = chunks.iter().map(|chunk| chunk.as_impl_ref()).collect::<Vec<_>>();
+        let without = Aggregate::new(
+            AggregateOp::Sum,
+            String::from("value"),
+            Grouping::Without(vec![String::from("job")]),
+        );
+        let matrix = aggregate(&without.as_impl_ref().to_owned(), &args).unwrap();
+        assert_eq!(matrix.series.len(), 2);
+        assert_eq!(matrix.series[0].labels, labels(&[("instance", "a")]));
+        assert_eq!(matrix.series[0].values[0], Some(11.0));
+        assert_eq!(matrix.series[1].labels, labels(&[("instance", "b")]));
+        assert_eq!(matrix.series[1].values[5], Some(4.0));
+
+        let all = Aggregate::new(AggregateOp::Count, String::from("value"), Grouping::By(vec![]));
+        let matrix = aggregate(&all.as_impl_ref().to_owned(), &args).unwrap();
+        assert_eq!(matrix.series.len(), 1);
+        assert!(matrix.series[0].labels.is_empty());
+        assert_eq!(matrix.series[0].values[0], Some(3.0));
+    }
+
+    #[test]
+    fn test_aggregate_filtered() {
+        let chunks = chunks();
+        let args = chunks.iter().map(|chunk| chunk.as_impl_ref()).collect::<Vec<_>>();
+        let predicate = Predicate::new(
+            BinaryOp::ExactMatch,
+            Box::new(Column::new(String::from("instance")).as_impl_ref().to_owned()),
+            Box::new(String::from("a").as_impl_ref().to_owned()),
+        );
+        let filter = Filter::new(
+            Box::new(predicate.as_impl_ref().to_owned()),
+            Box::new(by_job(AggregateOp::Sum)),
+        );
+        let result = future::block_on(filter.evaluate(&mut Context::new(), &args)).unwrap();
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        assert_eq!(matrix.series.len(), 2);
+        assert_eq!(matrix.series[0].values[0], Some(1.0));
+        assert_eq!(matrix.series[0].values[5], None);
+    }
+
+    #[test]
+    fn test_aggregate_errors() {
+        let chunks = chunks();
+        let args = [chunks[0].as_impl_ref()];
+        let missing = Aggregate::new(AggregateOp::Sum, String::from("count"), Grouping::By(vec![]));
+        assert!(matches!(
+            aggregate(&missing.as_impl_ref().to_owned(), &args),
+            Err(ExprError::FieldNotFound { .. })
+        ));
+        assert!(aggregate(&by_job(AggregateOp::Sum), &[]).unwrap().series.is_empty());
+
+        let mut mismatched = chunks.clone();
+        mismatched[1].meta = ChunkMeta::new(Instant::from_millis(4000), Duration::from_millis(2000), 4);
+        let args = mismatched.iter().map(|chunk| chunk.as_impl_ref()).collect::<Vec<_>>();
+        assert!(matches!(
+            aggregate(&by_job(AggregateOp::Sum), &args),
+            Err(ExprError::MismatchedInterval { .. })
+        ));
+    }
+}
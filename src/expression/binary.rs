@@ -0,0 +1,579 @@
+use std::future::Future;
+
+use hashbrown::{HashMap, HashSet};
+
+use crate::context::Context;
+
+use super::error::ExprError;
+use super::matrix::{Matrix, Series};
+use super::{ExprImpl, ExprImplRef, ExprType, Expression, Primitive};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    Atan2,
+    Eq,
+    NotEq,
+    Gt,
+    Lt,
+    GtEq,
+    LtEq,
+    And,
+    Or,
+    Unless,
+}
+
+impl BinaryOperator {
+    #[inline]
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Gt
+                | BinaryOperator::Lt
+                | BinaryOperator::GtEq
+                | BinaryOperator::LtEq
+        )
+    }
+
+    #[inline]
+    pub fn is_set(&self) -> bool {
+        matches!(self, BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Unless)
+    }
+
+    /// Comparisons return `1.0` for true and `0.0` for false.
+    fn apply(&self, lhs: f64, rhs: f64) -> f64 {
+        let compare = |result: bool| if result { 1.0 } else { 0.0 };
+        match self {
+            BinaryOperator::Add => lhs + rhs,
+            BinaryOperator::Sub => lhs - rhs,
+            BinaryOperator::Mul => lhs * rhs,
+            BinaryOperator::Div => lhs / rhs,
+            BinaryOperator::Mod => lhs % rhs,
+            BinaryOperator::Pow => lhs.powf(rhs),
+            BinaryOperator::Atan2 => lhs.atan2(rhs),
+            BinaryOperator::Eq => compare(lhs == rhs),
+            BinaryOperator::NotEq => compare(lhs != rhs),
+            BinaryOperator::Gt => compare(lhs > rhs),
+            BinaryOperator::Lt => compare(lhs < rhs),
+            BinaryOperator::GtEq => compare(lhs >= rhs),
+            BinaryOperator::LtEq => compare(lhs <= rhs),
+            BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Unless => unreachable!(),
+        }
+    }
+}
+
+/// Labels used to match series of both sides.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Matching {
+    On(Vec<String>),
+    Ignoring(Vec<String>),
+}
+
+impl Matching {
+    fn signature(&self, labels: &[(String, String)]) -> Vec<(String, String)> {
+        labels
+            .iter()
+            .filter(|(name, _)| match self {
+                Matching::On(on) => on.contains(name),
+                Matching::Ignoring(ignoring) => !ignoring.contains(name),
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+/// How many series of one side may match a series of the other, `ManyToOne` is `group_left`
+/// and `OneToMany` is `group_right`, both with the labels to copy from the "one" side.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Cardinality {
+    OneToOne,
+    ManyToOne(Vec<String>),
+    OneToMany(Vec<String>),
+}
+
+/// Applies `op` to the results of `lhs` and `rhs`, each either a [`Matrix`] or a `Primitive<f64>` scalar.
+/// Series of two matrices are matched by `matching`, and values by timestamp on the axis of `lhs`,
+/// or of both sides for `or`.
+/// Comparisons filter the values unless `return_bool` is set.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Binary {
+    op: BinaryOperator,
+    return_bool: bool,
+    matching: Matching,
+    cardinality: Cardinality,
+    lhs: Box<ExprImpl>,
+    rhs: Box<ExprImpl>,
+}
+
+enum Operand<'a> {
+    Scalar(f64),
+    Matrix(&'a Matrix),
+}
+
+impl<'a> Operand<'a> {
+    fn new(expr: &'a ExprImpl) -> Result<Self, ExprError> {
+        if let Some(matrix) = expr.as_any().downcast_ref::<Matrix>() {
+            return Ok(Operand::Matrix(matrix));
+        }
+        if let Some(scalar) = expr.as_any().downcast_ref::<Primitive<f64>>() {
+            return Ok(Operand::Scalar(*scalar.value()));
+        }
+        Err(ExprError::MismatchedType {
+            expected: ExprType::Matrix,
+            found: expr.expr_type(),
+        })
+    }
+}
+
+impl Binary {
+    pub fn new(op: BinaryOperator, lhs: Box<ExprImpl>, rhs: Box<ExprImpl>) -> Self {
+        Self {
+            op,
+            return_bool: false,
+            matching: Matching::Ignoring(vec![]),
+            cardinality: Cardinality::OneToOne,
+            lhs,
+            rhs,
+        }
+    }
+
+    #[inline]
+    pub fn with_bool(mut self) -> Self {
+        self.return_bool = true;
+        self
+    }
+
+    #[inline]
+    pub fn with_matching(mut self, matching: Matching, cardinality: Cardinality) -> Self {
+        self.matching = matching;
+        self.cardinality = cardinality;
+        self
+    }
+
+    /// Value of a vector element and a matched value, `None` if a comparison filters it out.
+    #[inline]
+    fn apply(&self, lhs: f64, rhs: f64, element: f64) -> Option<f64> {
+        let result = self.op.apply(lhs, rhs);
+        match (self.op.is_comparison(), self.return_bool) {
+            (true, false) if result == 0.0 => None,
+            (true, false) => Some(element),
+            _ => Some(result),
+        }
+    }
+
+    fn matrix_scalar(&self, matrix: &Matrix, scalar: f64, swapped: bool) -> Matrix {
+        let series = matrix
+            .series
+            .iter()
+            .map(|series| Series {
+                labels: series.labels.clone(),
+                values: series
+                    .values
+                    .iter()
+                    .map(|value| {
+                        value.and_then(|value| {
+                            let (lhs, rhs) = if swapped { (scalar, value) } else { (value, scalar) };
+                            self.apply(lhs, rhs, value)
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+        Matrix { series, ..*matrix }.without_empty_series()
+    }
+
+    fn set_operation(&self, lhs: &Matrix, rhs: &Matrix) -> Result<Matrix, ExprError> {
+        if self.op == BinaryOperator::Or {
+            return self.union(lhs, rhs);
+        }
+        let mut signatures: HashMap<Vec<(String, String)>, Vec<&Series>> = HashMap::new();
+        for series in &rhs.series {
+            signatures
+                .entry(self.matching.signature(&series.labels))
+                .or_default()
+                .push(series);
+        }
+        let matched = |series: &Series, slot: usize| {
+            let at = lhs.timestamp(slot);
+            signatures
+                .get(&self.matching.signature(&series.labels))
+                .is_some_and(|matches| matches.iter().any(|rhs_series| rhs.value_at(rhs_series, at).is_some()))
+        };
+        let series = lhs
+            .series
+            .iter()
+            .map(|series| Series {
+                labels: series.labels.clone(),
+                values: series
+                    .values
+                    .iter()
+                    .enumerate()
+                    .map(|(slot, value)| match self.op {
+                        BinaryOperator::Unless => value.filter(|_| !matched(series, slot)),
+                        _ => value.filter(|_| matched(series, slot)),
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        Ok(Matrix { series, ..*lhs }.without_empty_series())
+    }
+
+    /// Series of both sides on the time axis covering them. Series of the right side fill the slots
+    /// where no series of the left side with their signature has values.
+    fn union(&self, lhs: &Matrix, rhs: &Matrix) -> Result<Matrix, ExprError> {
+        let (start_at, time_interval, slots) = match Matrix::axis(&[lhs, rhs])? {
+            Some(axis) => axis,
+            None => return Ok(lhs.clone()),
+        };
+        let axis = Matrix {
+            start_at,
+            time_interval,
+            series: vec![],
+        };
+        let on_axis = |matrix: &Matrix, series: &Series| {
+            (0..slots)
+                .map(|slot| matrix.value_at(series, axis.timestamp(slot)))
+                .collect::<Vec<_>>()
+        };
+        // indexes of the series of the left side by signature
+        let mut lhs_signatures: HashMap<Vec<(String, String)>, Vec<usize>> = HashMap::new();
+        let mut series = Vec::new();
+        for lhs_series in &lhs.series {
+            lhs_signatures
+                .entry(self.matching.signature(&lhs_series.labels))
+                .or_default()
+                .push(series.len());
+            series.push(Series {
+                labels: lhs_series.labels.clone(),
+                values: on_axis(lhs, lhs_series),
+            });
+        }
+        for rhs_series in &rhs.series {
+            let matches = lhs_signatures.get(&self.matching.signature(&rhs_series.labels));
+            let mut values = on_axis(rhs, rhs_series);
+            for (slot, value) in values.iter_mut().enumerate() {
+                let occupied =
+                    matches.is_some_and(|matches| matches.iter().any(|index| series[*index].values[slot].is_some()));
+                if occupied {
+                    *value = None;
+                }
+            }
+            series.push(Series {
+                labels: rhs_series.labels.clone(),
+                values,
+            });
+        }
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        Ok(Matrix { series, ..axis }.without_empty_series())
+    }
+
+    fn vector_matching(&self, lhs: &Matrix, rhs: &Matrix) -> Result<Matrix, ExprError> {
+        // series of the "many" side are matched against unique series of the "one" side
+        let (many, one, swapped, include) = match &self.cardinality {
+            Cardinality::OneToOne => (lhs, rhs, false, None),
+            Cardinality::ManyToOne(include) => (lhs, rhs, false, Some(include)),
+            Cardinality::OneToMany(include) => (rhs, lhs, true, Some(include)),
+        };
+        let mut signatures: HashMap<Vec<(String, String)>, &Series> = HashMap::new();
+        for series in &one.series {
+            let signature = self.matching.signature(&series.labels);
+            if signatures.insert(signature.clone(), series).is_some() {
+                return Err(ExprError::ManyToManyMatching { labels: signature });
+            }
+        }
+        if include.is_none() {
+            let mut seen = HashSet::new();
+            for series in &many.series {
+                let signature = self.matching.signature(&series.labels);
+                if !seen.insert(signature.clone()) {
+                    return Err(ExprError::ManyToManyMatching { labels: signature });
+                }
+            }
+        }
+        let len = lhs.series.first().map_or(0, |series| series.values.len());
+        let mut series = Vec::new();
+        for many_series in &many.series {
+            let one_series = match signatures.get(&self.matching.signature(&many_series.labels)) {
+                Some(series) => *series,
+                None => continue,
+            };
+            let values = (0..len)
+                .map(|slot| {
+                    let at = lhs.timestamp(slot);
+                    let (many_value, one_value) = (many.value_at(many_series, at)?, one.value_at(one_series, at)?);
+                    let (lhs_value, rhs_value) = if swapped {
+                        (one_value, many_value)
+                    } else {
+                        (many_value, one_value)
+                    };
+                    self.apply(lhs_value, rhs_value, lhs_value)
+                })
+                .collect();
+            series.push(Series {
+                labels: self.result_labels(&many_series.labels, &one_series.labels, include),
+                values,
+            });
+        }
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        Ok(Matrix { series, ..*lhs }.without_empty_series())
+    }
+
+    fn result_labels(
+        &self,
+        many: &[(String, String)],
+        one: &[(String, String)],
+        include: Option<&Vec<String>>,
+    ) -> Vec<(String, String)> {
+        let include = match include {
+            Some(include) => include,
+            None => return self.matching.signature(many),
+        };
+        let mut labels = many
+            .iter()
+            .filter(|(name, _)| !include.contains(name))
+            .chain(one.iter().filter(|(name, _)| include.contains(name)))
+            .cloned()
+            .collect::<Vec<_>>();
+        labels.sort();
+        labels
+    }
+}
+
+impl Expression for Binary {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let lhs = self.lhs.evaluate(context, args).await?;
+            let rhs = self.rhs.evaluate(context, args).await?;
+            let result = match (Operand::new(&lhs)?, Operand::new(&rhs)?) {
+                (Operand::Scalar(lhs), Operand::Scalar(rhs)) if !self.op.is_set() => {
+                    Primitive::new(self.op.apply(lhs, rhs)).as_impl_ref().to_owned()
+                }
+                (Operand::Matrix(matrix), Operand::Scalar(scalar)) if !self.op.is_set() => {
+                    self.matrix_scalar(matrix, scalar, false).as_impl_ref().to_owned()
+                }
+                (Operand::Scalar(scalar), Operand::Matrix(matrix)) if !self.op.is_set() => {
+                    self.matrix_scalar(matrix, scalar, true).as_impl_ref().to_owned()
+                }
+                (Operand::Matrix(lhs), Operand::Matrix(rhs)) if self.op.is_set() => {
+                    self.set_operation(lhs, rhs)?.as_impl_ref().to_owned()
+                }
+                (Operand::Matrix(lhs), Operand::Matrix(rhs)) => {
+                    self.vector_matching(lhs, rhs)?.as_impl_ref().to_owned()
+                }
+                // set operators are only defined between matrices
+                (Operand::Scalar(_), _) => {
+                    return Err(ExprError::MismatchedType {
+                        expected: ExprType::Matrix,
+                        found: lhs.expr_type(),
+                    })
+                }
+                (_, Operand::Scalar(_)) => {
+                    return Err(ExprError::MismatchedType {
+                        expected: ExprType::Matrix,
+                        found: rhs.expr_type(),
+                    })
+                }
+            };
+            Ok(result)
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Binary,
+            data: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::error::ExprError;
+    use crate::expression::matrix::{Matrix, Series};
+    use crate::expression::{ExprImpl, Expression, Primitive};
+
+    use super::{Binary, BinaryOperator, Cardinality, Matching};
+
+    fn series(labels: &[(&str, &str)], values: &[Option<f64>]) -> Series {
+        Series {
+            labels: labels
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            values: values.to_vec(),
+        }
+    }
+
+    fn matrix(start_at: i64, series: Vec<Series>) -> Box<ExprImpl> {
+        Box::new(
+            Matrix {
+                start_at: Instant::from_millis(start_at),
+                time_interval: Duration::SECOND,
+                series,
+            }
+            .as_impl_ref()
+            .to_owned(),
+        )
+    }
+
+    fn scalar(value: f64) -> Box<ExprImpl> {
+        Box::new(Primitive::new(value).as_impl_ref().to_owned())
+    }
+
+    fn requests() -> Box<ExprImpl> {
+        matrix(
+            0,
+            vec![
+                series(&[("instance", "a"), ("job", "api")], &[Some(1.0), Some(2.0)]),
+                series(&[("instance", "a"), ("job", "node")], &[Some(5.0), Some(6.0)]),
+                series(&[("instance", "b"), ("job", "api")], &[Some(3.0), None]),
+            ],
+        )
+    }
+
+    fn evaluate(binary: Binary) -> Result<ExprImpl, ExprError> {
+        future::block_on(binary.evaluate(&mut Context::new(), &[]))
+    }
+
+    fn values(result: &ExprImpl) -> Vec<Vec<Option<f64>>> {
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        matrix.series.iter().map(|series| series.values.clone()).collect()
+    }
+
+    #[test]
+    fn test_scalar_operations() {
+        let result = evaluate(Binary::new(BinaryOperator::Pow, scalar(2.0), scalar(3.0))).unwrap();
+        assert_eq!(result.as_any().downcast_ref::<Primitive<f64>>().unwrap().value(), &8.0);
+
+        let result = evaluate(Binary::new(BinaryOperator::Sub, scalar(10.0), requests())).unwrap();
+        assert_eq!(
+            values(&result),
+            vec![
+                vec![Some(9.0), Some(8.0)],
+                vec![Some(5.0), Some(4.0)],
+                vec![Some(7.0), None]
+            ]
+        );
+
+        let result = evaluate(Binary::new(BinaryOperator::Gt, requests(), scalar(2.0))).unwrap();
+        assert_eq!(values(&result), vec![vec![Some(5.0), Some(6.0)], vec![Some(3.0), None]]);
+
+        let result = evaluate(Binary::new(BinaryOperator::Gt, requests(), scalar(2.0)).with_bool()).unwrap();
+        assert_eq!(values(&result)[0], vec![Some(0.0), Some(0.0)]);
+
+        assert!(matches!(
+            evaluate(Binary::new(BinaryOperator::And, requests(), scalar(1.0))),
+            Err(ExprError::MismatchedType { .. })
+        ));
+    }
+
+    #[test]
+    fn test_vector_matching() {
+        // shifted by one second, so only the second slot of `requests` matches the first of `limits`
+        let limits = || {
+            matrix(
+                1000,
+                vec![
+                    series(&[("instance", "a"), ("job", "api")], &[Some(4.0)]),
+                    series(&[("instance", "b"), ("job", "api")], &[Some(4.0)]),
+                ],
+            )
+        };
+        let result = evaluate(Binary::new(BinaryOperator::Div, requests(), limits())).unwrap();
+        assert_eq!(values(&result), vec![vec![None, Some(0.5)]]);
+
+        let totals = || {
+            matrix(
+                0,
+                vec![series(&[("job", "api"), ("zone", "x")], &[Some(4.0), Some(8.0)])],
+            )
+        };
+        let on_job = Binary::new(BinaryOperator::Div, requests(), totals())
+            .with_matching(Matching::On(vec![String::from("job")]), Cardinality::OneToOne);
+        assert!(matches!(evaluate(on_job), Err(ExprError::ManyToManyMatching { .. })));
+
+        let group_left = Binary::new(BinaryOperator::Div, requests(), totals()).with_matching(
+            Matching::On(vec![String::from("job")]),
+            Cardinality::ManyToOne(vec![String::from("zone")]),
+        );
+        let result = evaluate(group_left).unwrap();
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        assert_eq!(matrix.series.len(), 2);
+        assert_eq!(
+            matrix.series[0],
+            series(
+                &[("instance", "a"), ("job", "api"), ("zone", "x")],
+                &[Some(0.25), Some(0.25)]
+            )
+        );
+        assert_eq!(matrix.series[1].values, vec![Some(0.75), None]);
+
+        let group_right = Binary::new(BinaryOperator::Sub, totals(), requests())
+            .with_matching(Matching::On(vec![String::from("job")]), Cardinality::OneToMany(vec![]));
+        let result = evaluate(group_right).unwrap();
+        assert_eq!(values(&result), vec![vec![Some(3.0), Some(6.0)], vec![Some(1.0), None]]);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let api = || {
+            matrix(
+                0,
+                vec![
+                    series(&[("instance", "a"), ("job", "api")], &[Some(0.0), None]),
+                    series(&[("instance", "c"), ("job", "api")], &[Some(7.0), Some(7.0)]),
+                ],
+            )
+        };
+        let result = evaluate(Binary::new(BinaryOperator::And, requests(), api())).unwrap();
+        assert_eq!(values(&result), vec![vec![Some(1.0), None]]);
+
+        let result = evaluate(Binary::new(BinaryOperator::Unless, requests(), api())).unwrap();
+        assert_eq!(
+            values(&result),
+            vec![vec![None, Some(2.0)], vec![Some(5.0), Some(6.0)], vec![Some(3.0), None]]
+        );
+
+        let result = evaluate(Binary::new(BinaryOperator::Or, requests(), api())).unwrap();
+        let union = result.as_any().downcast_ref::<Matrix>().unwrap();
+        assert_eq!(union.series.len(), 4);
+        assert_eq!(
+            union.series[3],
+            series(&[("instance", "c"), ("job", "api")], &[Some(7.0), Some(7.0)])
+        );
+
+        // the time axis covers both sides, either may be empty
+        let result = evaluate(Binary::new(BinaryOperator::Or, matrix(0, vec![]), api())).unwrap();
+        assert_eq!(values(&result), values(&api()));
+        let later = matrix(
+            1000,
+            vec![series(&[("instance", "a"), ("job", "api")], &[Some(8.0), Some(9.0)])],
+        );
+        let result = evaluate(Binary::new(BinaryOperator::Or, later, api())).unwrap();
+        assert_eq!(
+            values(&result),
+            vec![
+                vec![None, Some(8.0), Some(9.0)],
+                vec![Some(0.0), None, None],
+                vec![Some(7.0), Some(7.0), None]
+            ]
+        );
+
+        let on_job = Binary::new(BinaryOperator::Unless, requests(), api())
+            .with_matching(Matching::On(vec![String::from("job")]), Cardinality::OneToOne);
+        assert_eq!(values(&evaluate(on_job).unwrap()), vec![vec![Some(5.0), Some(6.0)]]);
+    }
+}
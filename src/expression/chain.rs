@@ -0,0 +1,121 @@
+use std::future::Future;
+
+use crate::context::Context;
+
+use super::error::ExprError;
+use super::{ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// Evaluates its first stage with the arguments, and every following stage with the result of the
+/// one before. The result of the last stage is returned.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Chain {
+    first: Box<ExprImpl>,
+    then: Vec<ExprImpl>,
+}
+
+impl Chain {
+    pub fn new(first: Box<ExprImpl>) -> Self {
+        Self { first, then: vec![] }
+    }
+
+    #[inline]
+    pub fn then(mut self, stage: ExprImpl) -> Self {
+        self.then.push(stage);
+        self
+    }
+
+    /// `first` followed by the stages of `then`, which are appended if `first` is a chain itself.
+    /// Just `first` without any stage.
+    pub fn append(first: ExprImpl, then: impl IntoIterator<Item = ExprImpl>) -> ExprImpl {
+        let mut then = then.into_iter().peekable();
+        if then.peek().is_none() {
+            return first;
+        }
+        let chain = match first.as_any().downcast_ref::<Chain>() {
+            Some(chain) => chain.clone(),
+            None => Chain::new(Box::new(first)),
+        };
+        then.fold(chain, Chain::then).as_impl_ref().to_owned()
+    }
+
+    #[inline]
+    pub(crate) fn first(&self) -> &ExprImpl {
+        &self.first
+    }
+
+    /// Stages after the first one.
+    #[inline]
+    pub(crate) fn rest(&self) -> &[ExprImpl] {
+        &self.then
+    }
+}
+
+impl Expression for Chain {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let mut result = self.first.evaluate(context, args).await?;
+            for stage in &self.then {
+                result = stage.evaluate(context, &[result.as_impl_ref()]).await?;
+            }
+            Ok(result)
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Chain,
+            data: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::function::{Function, MathOp};
+    use crate::expression::matrix::{Matrix, Series};
+    use crate::expression::Expression;
+
+    use super::Chain;
+
+    #[test]
+    fn test_chain() {
+        let matrix = |values: Vec<Option<f64>>| Matrix {
+            start_at: Instant::from_millis(0),
+            time_interval: Duration::SECOND,
+            series: vec![Series { labels: vec![], values }],
+        };
+        let chain = Chain::new(Box::new(matrix(vec![Some(-1.5), None]).as_impl_ref().to_owned()))
+            .then(Function::new(MathOp::Abs).as_impl_ref().to_owned())
+            .then(Function::new(MathOp::Ceil).as_impl_ref().to_owned());
+        let result = future::block_on(chain.evaluate(&mut Context::new(), &[])).unwrap();
+        assert_eq!(
+            result.as_any().downcast_ref::<Matrix>().unwrap(),
+            &matrix(vec![Some(2.0), None])
+        );
+
+        let first = matrix(vec![]).as_impl_ref().to_owned();
+        assert_eq!(Chain::append(first.clone(), None), first);
+        let abs = Function::new(MathOp::Abs).as_impl_ref().to_owned();
+        let appended = Chain::append(
+            Chain::new(Box::new(first.clone()))
+                .then(abs.clone())
+                .as_impl_ref()
+                .to_owned(),
+            [abs.clone()],
+        );
+        assert_eq!(
+            appended,
+            Chain::new(Box::new(first))
+                .then(abs.clone())
+                .then(abs)
+                .as_impl_ref()
+                .to_owned()
+        );
+    }
+}
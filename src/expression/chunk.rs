@@ -1,16 +1,121 @@
 use std::future::Future;
 
-use crate::column::MutableChunk;
+use croaring::Bitmap;
+
+use crate::column::{ChunkMeta, ImmutableChunk, LabelImpl, MutableChunk};
 use crate::context::Context;
 
 use super::error::ExprError;
-use super::{ExprImplRef, ExprType, Expression};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// Either kind of chunk, as handed over by a scan.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum ChunkRef<'a> {
+    Mutable(&'a MutableChunk),
+    Immutable(&'a ImmutableChunk),
+}
+
+impl<'a> ChunkRef<'a> {
+    pub(crate) fn new(expr: ExprImplRef<'a>) -> Result<Self, ExprError> {
+        let any = expr.as_any();
+        if let Some(chunk) = any.downcast_ref::<MutableChunk>() {
+            return Ok(ChunkRef::Mutable(chunk));
+        }
+        any.downcast_ref::<ImmutableChunk>()
+            .map(ChunkRef::Immutable)
+            .ok_or(ExprError::MismatchedType {
+                expected: ExprType::Chunk,
+                found: expr.expr_type(),
+            })
+    }
+
+    #[inline]
+    pub(crate) fn meta(&self) -> &'a ChunkMeta {
+        match self {
+            ChunkRef::Mutable(chunk) => &chunk.meta,
+            ChunkRef::Immutable(chunk) => &chunk.meta,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn get_label(&self, name: &str) -> Option<&'a LabelImpl> {
+        match self {
+            ChunkRef::Mutable(chunk) => chunk.get_label(name),
+            ChunkRef::Immutable(chunk) => chunk.get_label(name),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn labels(&self) -> &'a [LabelImpl] {
+        match self {
+            ChunkRef::Mutable(chunk) => &chunk.label,
+            ChunkRef::Immutable(chunk) => &chunk.label,
+        }
+    }
+
+    /// Calls `f` with the slot and value of every valid sample of `field` in the series at `row`,
+    /// returns `false` if the chunk has no such field.
+    pub(crate) fn for_each_sample(&self, field: &str, row: usize, f: impl FnMut(usize, f64)) -> bool {
+        match self {
+            ChunkRef::Mutable(chunk) => chunk.get_field(field).map(|field| field.for_each_sample(row, f)),
+            ChunkRef::Immutable(chunk) => chunk.get_field(field).map(|field| field.for_each_sample(row, f)),
+        }
+        .is_some()
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            ChunkRef::Mutable(chunk) => chunk.len(),
+            ChunkRef::Immutable(chunk) => chunk.len(),
+        }
+    }
+}
+
+/// Splits a chunk stream into chunks and their selected rows. Every chunk may be followed
+/// by a `Bitmap` of the rows it selects, as a filter hands them over, otherwise all rows are selected.
+pub(crate) fn selected_chunks<'a>(
+    args: &[ExprImplRef<'a>],
+) -> Result<Vec<(ChunkRef<'a>, Option<&'a Bitmap>)>, ExprError> {
+    let mut chunks = Vec::with_capacity(args.len());
+    for arg in args {
+        if arg.expr_type() != ExprType::Bitmap {
+            chunks.push((ChunkRef::new(*arg)?, None));
+            continue;
+        }
+        match chunks.last_mut() {
+            Some((_, selection @ None)) => *selection = Some(downcast::<Bitmap>(*arg, ExprType::Bitmap)?),
+            _ => {
+                return Err(ExprError::MismatchedType {
+                    expected: ExprType::Chunk,
+                    found: ExprType::Bitmap,
+                })
+            }
+        }
+    }
+    Ok(chunks)
+}
 
 impl Expression for MutableChunk {
-    type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>>;
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
+
+    fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
+        async { Ok(self.as_impl_ref().to_owned()) }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Chunk,
+            data: self,
+        }
+    }
+}
+
+impl Expression for ImmutableChunk {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
 
     fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
-        async { Ok(self.as_impl_ref()) }
+        async { Ok(self.as_impl_ref().to_owned()) }
     }
 
     fn as_impl_ref(&self) -> ExprImplRef<'_> {
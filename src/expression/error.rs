@@ -1,11 +1,26 @@
 use snafu::Snafu;
 
-use super::Literal;
+use crate::common::Duration;
+
+use super::{ExprType, Literal};
 
 #[derive(Snafu, Debug)]
+#[snafu(visibility(pub(crate)))]
 pub enum ExprError {
     #[snafu(display("invalid resource: {:?}", resource))]
     InvalidResource { resource: Literal },
     #[snafu(display("resource: {:?} not found", resource))]
     ResourceNotFound { resource: String },
+    #[snafu(display("missing argument at position {}", position))]
+    MissingArgument { position: usize },
+    #[snafu(display("mismatched type, expected: {:?}, found: {:?}", expected, found))]
+    MismatchedType { expected: ExprType, found: ExprType },
+    #[snafu(display("many-to-many matching not allowed, duplicate series for: {:?}", labels))]
+    ManyToManyMatching { labels: Vec<(String, String)> },
+    #[snafu(display("field: {:?} not found", name))]
+    FieldNotFound { name: String },
+    #[snafu(display("mismatched time interval, expected: {:?}, found: {:?}", expected, found))]
+    MismatchedInterval { expected: Duration, found: Duration },
+    #[snafu(display("invalid regex: {}", source))]
+    InvalidRegex { source: regex::Error },
 }
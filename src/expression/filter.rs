@@ -1,12 +1,72 @@
-use super::error::ExpressionError;
-use super::Expression;
+use std::future::Future;
+use std::hash::Hash;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
-#[derive(Debug)]
+use croaring::Bitmap;
+use regex::Regex;
+use snafu::ResultExt;
+
+use crate::array::scalar::Scalar;
+use crate::array::{Array, IdArray, IdentifiedArray};
+use crate::column::{LabelColumn, LabelImpl};
+use crate::context::Context;
+use crate::index::Condition;
+
+use super::chunk::ChunkRef;
+use super::error::{ExprError, InvalidRegexSnafu};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// Evaluates `predicate` against every chunk in `args`, then hands each chunk
+/// followed by its selected rows to `output`.
+#[derive(Debug, PartialEq, Clone)]
 pub struct Filter {
-    predicate: Box<dyn Expression>,
+    predicate: Box<ExprImpl>,
+    output: Box<ExprImpl>,
+}
+
+impl Filter {
+    pub fn new(predicate: Box<ExprImpl>, output: Box<ExprImpl>) -> Self {
+        Self { predicate, output }
+    }
+
+    #[inline]
+    pub(crate) fn predicate(&self) -> &ExprImpl {
+        &self.predicate
+    }
+
+    #[inline]
+    pub(crate) fn output(&self) -> &ExprImpl {
+        &self.output
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl Expression for Filter {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let mut selections = Vec::with_capacity(args.len());
+            for chunk in args {
+                selections.push(self.predicate.evaluate(context, std::slice::from_ref(chunk)).await?);
+            }
+            let args = args
+                .iter()
+                .zip(selections.iter())
+                .flat_map(|(chunk, selection)| [*chunk, selection.as_impl_ref()])
+                .collect::<Vec<_>>();
+            self.output.evaluate(context, &args).await
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Filter,
+            data: self,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum BinaryOp {
     ExactMatch,
     ExactNotMatch,
@@ -16,94 +76,374 @@ pub enum BinaryOp {
     Or,
 }
 
-#[derive(Debug)]
+impl BinaryOp {
+    #[inline]
+    fn is_negative(&self) -> bool {
+        matches!(self, BinaryOp::ExactNotMatch | BinaryOp::RegexNotMatch)
+    }
+}
+
+/// Selects the rows of a chunk. Matchers take a [`Column`] on the left and a
+/// `String` on the right, `And`/`Or` combine two nested predicates.
+#[derive(Debug, PartialEq, Clone)]
 pub struct Predicate {
     op: BinaryOp,
-    lhs: Box<dyn Expression>,
-    rhs: Box<dyn Expression>,
+    lhs: Box<ExprImpl>,
+    rhs: Box<ExprImpl>,
 }
 
-impl Expression for Predicate {
-    #[inline]
-    fn evaluate(
-        &self,
-        context: &mut crate::context::Context,
-        args: &[&dyn Expression],
-    ) -> Result<&dyn Expression, ExpressionError> {
-        let array = args[0].evaluate(context, &[]);
-        todo!()
+impl Predicate {
+    pub fn new(op: BinaryOp, lhs: Box<ExprImpl>, rhs: Box<ExprImpl>) -> Self {
+        Self { op, lhs, rhs }
     }
 
-    #[inline]
-    fn equal(&self, other: &dyn Expression) -> bool {
-        other.as_any().downcast_ref::<Self>().map_or(false, |e| self == e)
+    async fn evaluate_matcher(&self, context: &mut Context, chunk: ChunkRef<'_>) -> Result<Bitmap, ExprError> {
+        let column = downcast::<Column>(self.lhs.as_impl_ref(), ExprType::Column)?;
+        let value = self.rhs.evaluate(context, &[]).await?;
+        let value = downcast::<String>(value.as_impl_ref(), ExprType::String)?;
+        let matcher = Matcher::new(self.op, value)?;
+        let negative = self.op.is_negative();
+        Ok(match chunk.get_label(&column.name) {
+            Some(label) => select(label, &matcher, negative),
+            None if matcher.matches("") != negative => (0..chunk.len() as u32).collect(),
+            None => Bitmap::create(),
+        })
     }
+}
 
-    #[inline]
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+impl Expression for Predicate {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let chunk = args.first().ok_or(ExprError::MissingArgument { position: 0 })?;
+            let bitmap = match self.op {
+                BinaryOp::And | BinaryOp::Or => {
+                    let lhs = self.lhs.evaluate(context, args).await?;
+                    let mut lhs = downcast::<Bitmap>(lhs.as_impl_ref(), ExprType::Bitmap)?.clone();
+                    let rhs = self.rhs.evaluate(context, args).await?;
+                    let rhs = downcast::<Bitmap>(rhs.as_impl_ref(), ExprType::Bitmap)?;
+                    if self.op == BinaryOp::And {
+                        lhs.and_inplace(rhs);
+                    } else {
+                        lhs.or_inplace(rhs);
+                    }
+                    lhs
+                }
+                _ => self.evaluate_matcher(context, ChunkRef::new(*chunk)?).await?,
+            };
+            Ok(bitmap.as_impl_ref().to_owned())
+        }
     }
-}
 
-impl PartialEq for Predicate {
-    #[inline]
-    fn eq(&self, other: &Self) -> bool {
-        &other.op == &self.op && &other.lhs == &self.lhs && &other.rhs == &self.rhs
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Predicate,
+            data: self,
+        }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Column {
     name: String,
 }
 
+impl Column {
+    pub fn new(name: String) -> Self {
+        Self { name }
+    }
+}
+
 impl Expression for Column {
-    #[inline]
-    fn evaluate(
-        &self,
-        _context: &mut crate::context::Context,
-        _args: &[&dyn Expression],
-    ) -> Result<&dyn Expression, ExpressionError> {
-        todo!()
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
+
+    fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
+        async { Ok(self.as_impl_ref().to_owned()) }
     }
 
-    #[inline]
-    fn equal(&self, other: &dyn Expression) -> bool {
-        other.as_any().downcast_ref::<Self>().map_or(false, |e| self == e)
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Column,
+            data: self,
+        }
+    }
+}
+
+impl Expression for Bitmap {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
+
+    fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
+        async { Ok(self.as_impl_ref().to_owned()) }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Bitmap,
+            data: self,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Matcher<'a> {
+    Exact(&'a str),
+    Regex(Regex),
+}
+
+impl<'a> Matcher<'a> {
+    fn new(op: BinaryOp, value: &'a str) -> Result<Self, ExprError> {
+        match op {
+            BinaryOp::RegexMatch | BinaryOp::RegexNotMatch => {
+                // label matchers are fully anchored, like in Prometheus
+                let regex = Regex::new(&format!("^(?:{})$", value)).context(InvalidRegexSnafu)?;
+                Ok(Matcher::Regex(regex))
+            }
+            _ => Ok(Matcher::Exact(value)),
+        }
     }
 
     #[inline]
-    fn as_any(&self) -> &dyn std::any::Any {
-        self
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Matcher::Exact(expected) => *expected == value,
+            Matcher::Regex(regex) => regex.is_match(value),
+        }
+    }
+}
+
+fn select(label: &LabelImpl, matcher: &Matcher<'_>, negative: bool) -> Bitmap {
+    match label {
+        LabelImpl::String(column) => select_column(
+            column,
+            matcher,
+            negative,
+            |value| Some(value.as_bytes().to_vec()),
+            |value| String::from_utf8_lossy(value).into_owned(),
+        ),
+        LabelImpl::IPv4(column) => select_column(
+            column,
+            matcher,
+            negative,
+            |value| value.parse::<Ipv4Addr>().ok().map(|ip| ip.octets().to_vec()),
+            |value| Ipv4Addr::from(<[u8; 4]>::try_from(value).unwrap()).to_string(),
+        ),
+        LabelImpl::IPv6(column) => select_column(
+            column,
+            matcher,
+            negative,
+            |value| value.parse::<Ipv6Addr>().ok().map(|ip| ip.octets().to_vec()),
+            |value| Ipv6Addr::from(<[u8; 16]>::try_from(value).unwrap()).to_string(),
+        ),
+        LabelImpl::Int(column) => select_column(
+            column,
+            matcher,
+            negative,
+            |value| value.parse::<i64>().ok(),
+            |value| value.to_string(),
+        ),
+        LabelImpl::Bool(column) => select_column(
+            column,
+            matcher,
+            negative,
+            |value| value.parse::<bool>().ok(),
+            |value| value.to_string(),
+        ),
+    }
+}
+
+/// An absent label value matches like an empty string.
+fn select_column<A, P, F>(
+    column: &LabelColumn<IdArray<A>>,
+    matcher: &Matcher<'_>,
+    negative: bool,
+    parse: P,
+    format: F,
+) -> Bitmap
+where
+    A: Array + Default,
+    for<'a, 'b> A::ItemRef<'a>: PartialEq<A::ItemRef<'b>> + Hash,
+    P: Fn(&str) -> Option<A::Item>,
+    F: Fn(A::ItemRef<'_>) -> String,
+{
+    let array = column.array();
+    match matcher {
+        Matcher::Exact(value) => {
+            let mut ids = Vec::new();
+            if value.is_empty() {
+                ids.extend(array.lookup_id(None));
+            }
+            if let Some(value) = parse(value) {
+                ids.extend(array.lookup_id(Some(Scalar::as_ref(&value))));
+            }
+            column.select(&if negative {
+                Condition::NotIn(&ids)
+            } else {
+                Condition::In(&ids)
+            })
+        }
+        Matcher::Regex(_) => {
+            let matches = |id: &usize| matcher.matches(&array.get_by_id(*id).map_or_else(String::new, &format));
+            column.select(&if negative {
+                Condition::NotMatches(&matches)
+            } else {
+                Condition::Matches(&matches)
+            })
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::future::Future;
+
+    use croaring::Bitmap;
+    use futures_lite::future;
+
+    use crate::column::{ChunkMeta, LabelImpl, LabelMeta, LabelType, LabelValue, MutableChunk};
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::error::ExprError;
+    use crate::expression::{ExprImpl, ExprImplRef, ExprType, Expression, Primitive};
+    use crate::index::IndexType;
+
     use super::{BinaryOp, Column, Filter, Predicate};
 
-    #[test]
-    fn express_filter() {
-        let predicate_1 = Box::new(Predicate {
-            op: BinaryOp::ExactMatch,
-            lhs: Box::new(Column {
-                name: String::from("job"),
-            }),
-            rhs: Box::new(String::from("prometheus")),
-        });
-        let predicate_2 = Box::new(Predicate {
-            op: BinaryOp::ExactMatch,
-            lhs: Box::new(Column {
-                name: String::from("foo"),
-            }),
-            rhs: Box::new(String::from("bar")),
-        });
-        let predicate = Box::new(Predicate {
-            op: BinaryOp::And,
-            lhs: predicate_1,
-            rhs: predicate_2,
+    #[derive(Debug, PartialEq, Clone)]
+    struct CountSelected;
+
+    impl Expression for CountSelected {
+        type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+        fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+            async move {
+                let count = args
+                    .iter()
+                    .filter_map(|arg| arg.as_any().downcast_ref::<Bitmap>())
+                    .map(|bitmap| bitmap.cardinality())
+                    .sum::<u64>();
+                Ok(Primitive(count).as_impl_ref().to_owned())
+            }
+        }
+
+        fn as_impl_ref(&self) -> ExprImplRef<'_> {
+            ExprImplRef {
+                expr_type: ExprType::Filter,
+                data: self,
+            }
+        }
+    }
+
+    fn string_label(name: &str, values: &[Option<&str>], index: IndexType<(), u32>) -> LabelImpl {
+        let mut label = LabelImpl::new(&LabelMeta {
+            name: String::from(name),
+            data_type: LabelType::String,
+            index: vec![index],
         });
-        let filter = Filter { predicate };
-        println!("{:?}", filter);
+        for value in values {
+            label.push(value.map(LabelValue::String).as_ref());
+        }
+        label
+    }
+
+    fn chunk() -> MutableChunk {
+        MutableChunk::new(
+            vec![
+                string_label(
+                    "job",
+                    &[Some("prometheus"), Some("api-1"), Some("api-2"), Some("prometheus")],
+                    IndexType::Inverted(()),
+                ),
+                string_label(
+                    "instance",
+                    &[Some("a"), Some("b"), None, Some("b")],
+                    IndexType::Sparse(2),
+                ),
+            ],
+            vec![],
+            ChunkMeta::new(Instant::from_millis(0), Duration::SECOND, 60),
+        )
+    }
+
+    fn matcher(op: BinaryOp, name: &str, value: &str) -> Box<ExprImpl> {
+        Box::new(
+            Predicate::new(
+                op,
+                Box::new(Column::new(String::from(name)).as_impl_ref().to_owned()),
+                Box::new(String::from(value).as_impl_ref().to_owned()),
+            )
+            .as_impl_ref()
+            .to_owned(),
+        )
+    }
+
+    fn evaluate(predicate: &ExprImpl, chunk: ExprImplRef<'_>) -> Bitmap {
+        let result = future::block_on(predicate.evaluate(&mut Context::new(), &[chunk])).unwrap();
+        result.as_any().downcast_ref::<Bitmap>().unwrap().clone()
+    }
+
+    #[test]
+    fn test_matchers() {
+        let chunk = chunk();
+        let cases = [
+            (BinaryOp::ExactMatch, "job", "prometheus", vec![0, 3]),
+            (BinaryOp::ExactNotMatch, "job", "prometheus", vec![1, 2]),
+            (BinaryOp::RegexMatch, "job", "api-.*", vec![1, 2]),
+            (BinaryOp::RegexNotMatch, "job", "api-.*", vec![0, 3]),
+            (BinaryOp::ExactMatch, "instance", "b", vec![1, 3]),
+            (BinaryOp::ExactMatch, "instance", "", vec![2]),
+            (BinaryOp::ExactMatch, "job", "unknown", vec![]),
+            (BinaryOp::ExactMatch, "env", "", vec![0, 1, 2, 3]),
+            (BinaryOp::RegexMatch, "env", ".+", vec![]),
+        ];
+        let frozen = chunk.clone().freeze();
+        for (op, name, value, expect) in cases {
+            let predicate = matcher(op, name, value);
+            assert_eq!(evaluate(&predicate, chunk.as_impl_ref()).to_vec(), expect);
+            assert_eq!(evaluate(&predicate, frozen.as_impl_ref()).to_vec(), expect);
+        }
+    }
+
+    #[test]
+    fn test_logical() {
+        let chunk = chunk();
+        let and = Predicate::new(
+            BinaryOp::And,
+            matcher(BinaryOp::ExactMatch, "job", "prometheus"),
+            matcher(BinaryOp::ExactMatch, "instance", "b"),
+        );
+        assert_eq!(
+            evaluate(&and.as_impl_ref().to_owned(), chunk.as_impl_ref()).to_vec(),
+            vec![3]
+        );
+        let or = Predicate::new(
+            BinaryOp::Or,
+            matcher(BinaryOp::RegexMatch, "job", "api-1"),
+            matcher(BinaryOp::ExactMatch, "instance", "a"),
+        );
+        assert_eq!(
+            evaluate(&or.as_impl_ref().to_owned(), chunk.as_impl_ref()).to_vec(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let chunks = [chunk(), chunk()];
+        let filter = Filter::new(
+            matcher(BinaryOp::ExactMatch, "job", "prometheus"),
+            Box::new(CountSelected.as_impl_ref().to_owned()),
+        );
+        let args = chunks.iter().map(|chunk| chunk.as_impl_ref()).collect::<Vec<_>>();
+        let result = future::block_on(filter.evaluate(&mut Context::new(), &args)).unwrap();
+        assert!(result.as_any().downcast_ref::<Primitive<u64>>() == Some(&Primitive(4)));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let chunk = chunk();
+        let predicate = matcher(BinaryOp::RegexMatch, "job", "(");
+        let result = future::block_on(predicate.evaluate(&mut Context::new(), &[chunk.as_impl_ref()]));
+        assert!(result.is_err());
     }
 }
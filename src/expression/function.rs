@@ -0,0 +1,88 @@
+use std::future::Future;
+
+use crate::context::Context;
+
+use super::error::ExprError;
+use super::matrix::{Matrix, Series};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MathOp {
+    Abs,
+    Ceil,
+    Floor,
+    Exp,
+    Ln,
+    Log2,
+    Log10,
+    Sqrt,
+    Sgn,
+    /// Rounds to the nearest multiple of the parameter.
+    Round(f64),
+    ClampMin(f64),
+    ClampMax(f64),
+}
+
+impl MathOp {
+    fn apply(&self, value: f64) -> f64 {
+        match self {
+            MathOp::Abs => value.abs(),
+            MathOp::Ceil => value.ceil(),
+            MathOp::Floor => value.floor(),
+            MathOp::Exp => value.exp(),
+            MathOp::Ln => value.ln(),
+            MathOp::Log2 => value.log2(),
+            MathOp::Log10 => value.log10(),
+            MathOp::Sqrt => value.sqrt(),
+            MathOp::Sgn if value == 0.0 || value.is_nan() => value,
+            MathOp::Sgn => value.signum(),
+            // ties round up, like Prometheus
+            MathOp::Round(nearest) => (value / nearest + 0.5).floor() * nearest,
+            MathOp::ClampMin(min) => value.max(*min),
+            MathOp::ClampMax(max) => value.min(*max),
+        }
+    }
+}
+
+/// Applies `op` to every value of its [`Matrix`] argument.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    op: MathOp,
+}
+
+impl Function {
+    pub fn new(op: MathOp) -> Self {
+        Self { op }
+    }
+}
+
+impl Expression for Function {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let arg = *args.first().ok_or(ExprError::MissingArgument { position: 0 })?;
+            let matrix = downcast::<Matrix>(arg, ExprType::Matrix)?;
+            let series = matrix
+                .series
+                .iter()
+                .map(|series| Series {
+                    labels: series.labels.clone(),
+                    values: series
+                        .values
+                        .iter()
+                        .map(|value| value.map(|value| self.op.apply(value)))
+                        .collect(),
+                })
+                .collect();
+            Ok(Matrix { series, ..*matrix }.as_impl_ref().to_owned())
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Function,
+            data: self,
+        }
+    }
+}
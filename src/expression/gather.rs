@@ -0,0 +1,253 @@
+use std::future::Future;
+
+use crate::context::Context;
+
+use super::aggregate::{Aggregate, AggregateOp, Grouping};
+use super::chain::Chain;
+use super::error::ExprError;
+use super::filter::Filter;
+use super::matrix::Matrix;
+use super::project::{Datum, Project, Rows};
+use super::range::RangeFunction;
+use super::scan::Scanner;
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// Merges the partial results of every shard, either all [`Matrix`] or all [`Rows`], an empty
+/// [`Matrix`] without any. Rows are ordered by their first timestamp column.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Gather {
+    /// Whether the last column is only the key of [`Project::with_time_key`], dropped once merged.
+    time_key: bool,
+}
+
+impl Gather {
+    pub fn new() -> Self {
+        Self { time_key: false }
+    }
+
+    /// Gathers rows of [`Project::with_time_key`].
+    pub fn with_time_key() -> Self {
+        Self { time_key: true }
+    }
+
+    fn merge(&self, args: &[ExprImplRef<'_>]) -> Result<ExprImpl, ExprError> {
+        let (first, rest) = match args.split_first() {
+            Some((first, rest)) if first.expr_type() == ExprType::Rows => (first, rest),
+            _ => {
+                let matrices = args
+                    .iter()
+                    .map(|arg| downcast::<Matrix>(*arg, ExprType::Matrix))
+                    .collect::<Result<Vec<_>, _>>()?;
+                return Ok(Matrix::merge(matrices)?.as_impl_ref().to_owned());
+            }
+        };
+        let mut merged = downcast::<Rows>(*first, ExprType::Rows)?.clone();
+        for arg in rest {
+            merged
+                .rows
+                .extend(downcast::<Rows>(*arg, ExprType::Rows)?.rows.iter().cloned());
+        }
+        // stable, so rows of one timestamp keep the order of their shards
+        merged.rows.sort_by_key(|row| {
+            row.iter().find_map(|datum| match datum {
+                Datum::Time(timestamp) => Some(timestamp.as_millis()),
+                _ => None,
+            })
+        });
+        if self.time_key {
+            merged.columns.pop();
+            for row in &mut merged.rows {
+                row.pop();
+            }
+        }
+        Ok(merged.as_impl_ref().to_owned())
+    }
+}
+
+impl Expression for Gather {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move { self.merge(args) }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Gather,
+            data: self,
+        }
+    }
+}
+
+/// A plan split at its scan into a shard-local part and a [`Gather`] of the partial results,
+/// followed by the rest of the plan.
+#[derive(Debug, Clone)]
+pub struct Split {
+    scanner: Scanner,
+    gather: ExprImpl,
+}
+
+impl Split {
+    /// The shard-local part, scanning only the shards owned by `core`.
+    pub fn scatter(&self, core: usize, cores: usize) -> ExprImpl {
+        self.scanner.clone().with_owner(core, cores).as_impl_ref().to_owned()
+    }
+
+    #[inline]
+    pub fn gather(&self) -> &ExprImpl {
+        &self.gather
+    }
+}
+
+/// Splits a plan starting with a [`Scanner`], or a [`Chain`] starting with one. Filters and
+/// per-series work stay shard-local, which is correct as every series lives in exactly one shard.
+/// Aggregations are gathered, their shards only hand over the samples of each series. `None` if
+/// the plan can't be split.
+pub fn split(plan: &ExprImpl) -> Option<Split> {
+    let (scanner, rest) = match plan.as_any().downcast_ref::<Chain>() {
+        Some(chain) => (chain.first(), chain.rest()),
+        None => (plan, &[][..]),
+    };
+    let scanner = scanner.as_any().downcast_ref::<Scanner>()?;
+    let (local, gather, after) = split_output(scanner.output())?;
+    Some(Split {
+        scanner: scanner.clone().with_output(Box::new(local)),
+        gather: Chain::append(
+            gather.as_impl_ref().to_owned(),
+            after.into_iter().chain(rest.iter().cloned()),
+        ),
+    })
+}
+
+/// The shard-local part of the chunk stream consumer `expr`, how to gather it, and the stages to
+/// evaluate after gathering.
+fn split_output(expr: &ExprImpl) -> Option<(ExprImpl, Gather, Vec<ExprImpl>)> {
+    let any = expr.as_any();
+    if let Some(chain) = any.downcast_ref::<Chain>() {
+        let (local, gather, mut after) = split_output(chain.first())?;
+        after.extend(chain.rest().iter().cloned());
+        return Some((local, gather, after));
+    }
+    if let Some(filter) = any.downcast_ref::<Filter>() {
+        let (local, gather, after) = split_output(filter.output())?;
+        let filter = Filter::new(Box::new(filter.predicate().clone()), Box::new(local));
+        return Some((filter.as_impl_ref().to_owned(), gather, after));
+    }
+    if let Some(aggregate) = any.downcast_ref::<Aggregate>() {
+        // every series is its own group, so the sum of a slot is its only sample
+        let series = Aggregate::new(
+            AggregateOp::Sum,
+            aggregate.field().to_owned(),
+            Grouping::Without(vec![]),
+        );
+        return Some((series.as_impl_ref().to_owned(), Gather::new(), vec![expr.clone()]));
+    }
+    if let Some(project) = any.downcast_ref::<Project>() {
+        if !project.has_time() {
+            let local = project.clone().with_time_key();
+            return Some((local.as_impl_ref().to_owned(), Gather::with_time_key(), vec![]));
+        }
+    }
+    if any.is::<RangeFunction>() || any.is::<Project>() {
+        return Some((expr.clone(), Gather::new(), vec![]));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::error::ExprError;
+    use crate::expression::matrix::{Matrix, Series};
+    use crate::expression::project::{Datum, Rows};
+    use crate::expression::Expression;
+
+    use super::Gather;
+
+    #[test]
+    fn test_gather() {
+        let series = |name: &str, values: Vec<Option<f64>>| Series {
+            labels: vec![(String::from("job"), String::from(name))],
+            values,
+        };
+        let matrix = |start_at: i64, series: Vec<Series>| Matrix {
+            start_at: Instant::from_millis(start_at),
+            time_interval: Duration::SECOND,
+            series,
+        };
+        let partials = [
+            matrix(1000, vec![series("b", vec![Some(1.0), Some(2.0)])]),
+            matrix(0, vec![]),
+            matrix(0, vec![series("a", vec![Some(3.0)])]),
+        ];
+        let args = partials.iter().map(Expression::as_impl_ref).collect::<Vec<_>>();
+        let result = future::block_on(Gather::new().evaluate(&mut Context::new(), &args)).unwrap();
+        assert_eq!(
+            result.as_any().downcast_ref::<Matrix>().unwrap(),
+            &matrix(
+                0,
+                vec![
+                    series("a", vec![Some(3.0), None, None]),
+                    series("b", vec![None, Some(1.0), Some(2.0)]),
+                ]
+            )
+        );
+
+        let rows = |rows: Vec<(i64, f64)>| Rows {
+            columns: vec![String::from("time"), String::from("value")],
+            rows: rows
+                .into_iter()
+                .map(|(millis, value)| vec![Datum::Time(Instant::from_millis(millis)), Datum::Float(value)])
+                .collect(),
+        };
+        let partials = [rows(vec![(0, 1.0), (2000, 2.0)]), rows(vec![(1000, 3.0)])];
+        let args = partials.iter().map(Expression::as_impl_ref).collect::<Vec<_>>();
+        let result = future::block_on(Gather::new().evaluate(&mut Context::new(), &args)).unwrap();
+        assert_eq!(
+            result.as_any().downcast_ref::<Rows>().unwrap(),
+            &rows(vec![(0, 1.0), (1000, 3.0), (2000, 2.0)])
+        );
+
+        // rows of a projection without time are merged by their key, which is dropped
+        let keyed = |rows: Vec<(i64, f64)>| Rows {
+            columns: vec![String::from("value"), String::new()],
+            rows: rows
+                .into_iter()
+                .map(|(millis, value)| vec![Datum::Float(value), Datum::Time(Instant::from_millis(millis))])
+                .collect(),
+        };
+        let partials = [keyed(vec![(0, 1.0), (2000, 2.0)]), keyed(vec![(1000, 3.0)])];
+        let args = partials.iter().map(Expression::as_impl_ref).collect::<Vec<_>>();
+        let result = future::block_on(Gather::with_time_key().evaluate(&mut Context::new(), &args)).unwrap();
+        assert_eq!(
+            result.as_any().downcast_ref::<Rows>().unwrap(),
+            &Rows {
+                columns: vec![String::from("value")],
+                rows: vec![
+                    vec![Datum::Float(1.0)],
+                    vec![Datum::Float(3.0)],
+                    vec![Datum::Float(2.0)]
+                ],
+            }
+        );
+
+        let result = future::block_on(Gather::new().evaluate(&mut Context::new(), &[])).unwrap();
+        assert_eq!(result.as_any().downcast_ref::<Matrix>().unwrap(), &matrix(0, vec![]));
+
+        let partials = [
+            matrix(0, vec![series("a", vec![Some(1.0)])]),
+            Matrix {
+                time_interval: Duration::from_millis(500),
+                ..matrix(0, vec![series("b", vec![Some(2.0)])])
+            },
+        ];
+        let args = partials.iter().map(Expression::as_impl_ref).collect::<Vec<_>>();
+        assert!(matches!(
+            future::block_on(Gather::new().evaluate(&mut Context::new(), &args)),
+            Err(ExprError::MismatchedInterval { .. })
+        ));
+    }
+}
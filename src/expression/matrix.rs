@@ -0,0 +1,267 @@
+use std::future::Future;
+
+use croaring::Bitmap;
+use hashbrown::HashMap;
+
+use crate::column::LabelValue;
+use crate::common::{Duration, Instant};
+use crate::context::Context;
+
+use super::chunk::ChunkRef;
+use super::error::ExprError;
+use super::{ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// One series of a [`Matrix`], `values[i]` is sampled at `start_at + i * time_interval`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Series {
+    /// Label names and values, sorted by name. Absent labels are left out.
+    pub labels: Vec<(String, String)>,
+    pub values: Vec<Option<f64>>,
+}
+
+/// Series sharing one time axis, the result of aggregations and range functions.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Matrix {
+    pub start_at: Instant,
+    pub time_interval: Duration,
+    pub series: Vec<Series>,
+}
+
+impl Matrix {
+    /// Timestamp of the `slot`th value of every series.
+    #[inline]
+    pub fn timestamp(&self, slot: usize) -> Instant {
+        self.start_at + self.time_interval * slot as i64
+    }
+
+    /// Drops the series without any value.
+    pub fn without_empty_series(mut self) -> Self {
+        self.series.retain(|series| series.values.iter().any(Option::is_some));
+        self
+    }
+
+    /// Start, interval and number of slots of the time axis covering all `matrices` with any series,
+    /// `None` without any. The matrices must share their time interval and axes must be aligned to it.
+    pub(crate) fn axis(matrices: &[&Matrix]) -> Result<Option<(Instant, Duration, usize)>, ExprError> {
+        let matrices = matrices
+            .iter()
+            .filter(|matrix| !matrix.series.is_empty())
+            .collect::<Vec<_>>();
+        let time_interval = match matrices.first() {
+            Some(first) => first.time_interval,
+            None => return Ok(None),
+        };
+        check_interval(time_interval, matrices.iter().map(|matrix| matrix.time_interval))?;
+        let len = |matrix: &Matrix| {
+            matrix
+                .series
+                .iter()
+                .map(|series| series.values.len())
+                .max()
+                .unwrap_or(0)
+        };
+        let start_at = matrices
+            .iter()
+            .map(|matrix| matrix.start_at.as_millis())
+            .min()
+            .map(Instant::from_millis)
+            .unwrap();
+        let end_at = matrices
+            .iter()
+            .map(|matrix| matrix.timestamp(len(matrix)).as_millis())
+            .max()
+            .map(Instant::from_millis)
+            .unwrap();
+        Ok(Some((
+            start_at,
+            time_interval,
+            ((end_at - start_at) / time_interval) as usize,
+        )))
+    }
+
+    /// Series of all `matrices` on one time axis covering them, ordered by labels. The matrices
+    /// must share their time interval and axes must be aligned to it.
+    pub fn merge<'a>(matrices: impl IntoIterator<Item = &'a Matrix>) -> Result<Matrix, ExprError> {
+        let matrices = matrices.into_iter().collect::<Vec<_>>();
+        let (start_at, time_interval, slots) = match Matrix::axis(&matrices)? {
+            Some(axis) => axis,
+            None => (Instant::from_millis(0), Duration::SECOND, 0),
+        };
+        let mut series = Vec::new();
+        for matrix in matrices {
+            let offset = ((matrix.start_at - start_at) / time_interval) as usize;
+            series.extend(matrix.series.iter().map(|series| {
+                let mut values = vec![None; slots];
+                values[offset..offset + series.values.len()].copy_from_slice(&series.values);
+                Series {
+                    labels: series.labels.clone(),
+                    values,
+                }
+            }));
+        }
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        Ok(Matrix {
+            start_at,
+            time_interval,
+            series,
+        })
+    }
+
+    /// Value of `series` at the last slot not after `at`.
+    pub fn value_at(&self, series: &Series, at: Instant) -> Option<f64> {
+        let offset = (at - self.start_at).as_millis();
+        if offset < 0 {
+            return None;
+        }
+        let slot = offset / self.time_interval.as_millis();
+        series.values.get(slot as usize).copied().flatten()
+    }
+}
+
+impl Expression for Matrix {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
+
+    fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
+        async { Ok(self.as_impl_ref().to_owned()) }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Matrix,
+            data: self,
+        }
+    }
+}
+
+/// Fails unless every interval of `intervals` is `expected`, as series on one time axis must be.
+fn check_interval(expected: Duration, intervals: impl IntoIterator<Item = Duration>) -> Result<(), ExprError> {
+    match intervals.into_iter().find(|found| *found != expected) {
+        Some(found) => Err(ExprError::MismatchedInterval { expected, found }),
+        None => Ok(()),
+    }
+}
+
+/// Start, interval and number of slots of the time axis covering all chunks, `None` without any.
+fn time_axis(chunks: &[(ChunkRef<'_>, Option<&Bitmap>)]) -> Result<Option<(Instant, Duration, usize)>, ExprError> {
+    let time_interval = match chunks.first() {
+        Some((chunk, _)) => chunk.meta().time_interval(),
+        None => return Ok(None),
+    };
+    check_interval(
+        time_interval,
+        chunks.iter().map(|(chunk, _)| chunk.meta().time_interval()),
+    )?;
+    let start_at = chunks
+        .iter()
+        .map(|(chunk, _)| chunk.meta().start_at.as_millis())
+        .min()
+        .map(Instant::from_millis)
+        .unwrap();
+    let end_at = chunks
+        .iter()
+        .map(|(chunk, _)| chunk.meta().end_at().as_millis())
+        .max()
+        .map(Instant::from_millis)
+        .unwrap();
+    Ok(Some((
+        start_at,
+        time_interval,
+        ((end_at - start_at) / time_interval) as usize + 1,
+    )))
+}
+
+type GroupKey<'a> = Vec<(&'a str, LabelValue<'a>)>;
+
+/// Samples of one field of a chunk stream, gathered per group of series on the time axis
+/// covering all chunks. Series of different chunks with the same labels fall into the same group.
+#[derive(Debug)]
+pub(crate) struct Gathered<'a, T> {
+    start_at: Instant,
+    time_interval: Duration,
+    groups: Vec<(GroupKey<'a>, Vec<T>)>,
+}
+
+impl<'a, T: Clone + Default> Gathered<'a, T> {
+    /// Groups the selected rows by the labels `include` accepts, and calls `push` with the slot
+    /// of their group for every valid sample.
+    pub(crate) fn gather(
+        chunks: &[(ChunkRef<'a>, Option<&'a Bitmap>)],
+        field: &str,
+        include: impl Fn(&str) -> bool,
+        mut push: impl FnMut(&mut T, f64),
+    ) -> Result<Self, ExprError> {
+        let (start_at, time_interval, len) = match time_axis(chunks)? {
+            Some(axis) => axis,
+            None => (Instant::from_millis(0), Duration::SECOND, 0),
+        };
+        // group keys are label values, ids are only used to find the group of a row within a chunk
+        let mut keys: HashMap<GroupKey<'a>, usize> = HashMap::new();
+        let mut groups: Vec<(GroupKey<'a>, Vec<T>)> = Vec::new();
+        for (chunk, selection) in chunks {
+            let labels = chunk
+                .labels()
+                .iter()
+                .filter(|label| include(label.name()))
+                .collect::<Vec<_>>();
+            let offset = ((chunk.meta().start_at - start_at) / time_interval) as usize;
+            let rows = selection.map_or_else(|| (0..chunk.len() as u32).collect(), Bitmap::to_vec);
+            let mut local_groups: HashMap<Vec<usize>, usize> = HashMap::new();
+            for row in rows {
+                let row = row as usize;
+                let ids = labels.iter().map(|label| label.id(row).unwrap()).collect::<Vec<_>>();
+                let group = match local_groups.get(&ids) {
+                    Some(group) => *group,
+                    None => {
+                        let mut key = labels
+                            .iter()
+                            .zip(&ids)
+                            .filter_map(|(label, id)| label.get_by_id(*id).map(|value| (label.name(), value)))
+                            .collect::<Vec<_>>();
+                        key.sort_by_key(|(name, _)| *name);
+                        let group = *keys.entry(key.clone()).or_insert(groups.len());
+                        if group == groups.len() {
+                            groups.push((key, vec![T::default(); len]));
+                        }
+                        local_groups.insert(ids, group);
+                        group
+                    }
+                };
+                let slots = &mut groups[group].1[offset..];
+                if !chunk.for_each_sample(field, row, |slot, value| push(&mut slots[slot], value)) {
+                    return Err(ExprError::FieldNotFound { name: field.to_owned() });
+                }
+            }
+        }
+        Ok(Self {
+            start_at,
+            time_interval,
+            groups,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn time_interval(&self) -> Duration {
+        self.time_interval
+    }
+
+    /// Computes the values of every group from its slots, series are ordered by labels.
+    pub(crate) fn into_matrix(self, mut values: impl FnMut(&[T]) -> Vec<Option<f64>>) -> Matrix {
+        let mut series = self
+            .groups
+            .into_iter()
+            .map(|(key, slots)| Series {
+                labels: key
+                    .into_iter()
+                    .map(|(name, value)| (name.to_owned(), value.to_string()))
+                    .collect(),
+                values: values(&slots),
+            })
+            .collect::<Vec<_>>();
+        series.sort_by(|a, b| a.labels.cmp(&b.labels));
+        Matrix {
+            start_at: self.start_at,
+            time_interval: self.time_interval,
+            series,
+        }
+    }
+}
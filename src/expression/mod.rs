@@ -1,18 +1,26 @@
+pub mod aggregate;
+pub mod binary;
+pub mod chain;
 pub mod chunk;
 pub mod error;
-// pub mod filter;
+pub mod filter;
+pub mod function;
+pub mod gather;
+pub mod matrix;
+pub mod project;
+pub mod range;
 pub mod scan;
+pub mod shift;
 pub mod source;
+pub mod trim;
 
 use std::any::Any;
 use std::fmt::Debug;
 use std::future::Future;
-use std::sync::Arc;
+use std::pin::Pin;
 
-use crate::column::MutableChunk;
 use crate::context::Context;
 use crate::primitive::{Primitive as PrimitiveData, PrimitiveType};
-use crate::source::Table;
 
 use self::error::ExprError;
 
@@ -23,12 +31,31 @@ pub enum ExprType {
     Primitive(PrimitiveType),
     Chunk,
     Table,
+    Bitmap,
+    Column,
+    Predicate,
+    Filter,
+    Scan,
+    Aggregate,
+    Matrix,
+    Range,
+    Binary,
+    Function,
+    Shift,
+    Project,
+    Rows,
+    Gather,
+    Chain,
+    Trim,
 }
 
+pub type BoxEvalFut<'a> = Pin<Box<dyn Future<Output = Result<ExprImpl, ExprError>> + 'a>>;
+
 pub trait AnyExpr: Any + Debug + Send + 'static {
     fn clone_box(&self) -> Box<dyn AnyExpr>;
     fn equal(&self, other: &dyn AnyExpr) -> bool;
     fn as_any(&self) -> &dyn Any;
+    fn evaluate_box<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> BoxEvalFut<'a>;
 }
 
 impl<T: Expression> AnyExpr for T {
@@ -44,6 +71,11 @@ impl<T: Expression> AnyExpr for T {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    #[inline]
+    fn evaluate_box<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> BoxEvalFut<'a> {
+        Box::pin(self.evaluate(context, args))
+    }
 }
 
 impl PartialEq for &dyn AnyExpr {
@@ -73,13 +105,38 @@ impl PartialEq for ExprImpl {
     }
 }
 
+impl ExprImpl {
+    #[inline]
+    pub fn expr_type(&self) -> ExprType {
+        self.expr_type
+    }
+
+    #[inline]
+    pub fn as_any(&self) -> &dyn Any {
+        self.data.as_any()
+    }
+
+    #[inline]
+    pub fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: self.expr_type,
+            data: &*self.data,
+        }
+    }
+
+    #[inline]
+    pub fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> BoxEvalFut<'a> {
+        self.data.evaluate_box(context, args)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub struct ExprImplRef<'a> {
     expr_type: ExprType,
     data: &'a dyn AnyExpr,
 }
 
-impl ExprImplRef<'_> {
+impl<'r> ExprImplRef<'r> {
     pub fn to_owned(&self) -> ExprImpl {
         ExprImpl {
             expr_type: self.expr_type.clone(),
@@ -87,26 +144,47 @@ impl ExprImplRef<'_> {
         }
     }
 
-    pub fn as_any(&self) -> &dyn Any {
+    #[inline]
+    pub fn expr_type(&self) -> ExprType {
+        self.expr_type
+    }
+
+    pub fn as_any(&self) -> &'r dyn Any {
         self.data.as_any()
     }
+
+    #[inline]
+    pub fn evaluate<'a>(&self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> BoxEvalFut<'a>
+    where
+        'r: 'a,
+    {
+        self.data.evaluate_box(context, args)
+    }
+}
+
+#[inline]
+pub(crate) fn downcast<'a, T: 'static>(expr: ExprImplRef<'a>, expected: ExprType) -> Result<&'a T, ExprError> {
+    expr.as_any().downcast_ref::<T>().ok_or(ExprError::MismatchedType {
+        expected,
+        found: expr.expr_type(),
+    })
 }
 
 pub trait Expression: 'static + Send + Debug + Clone + PartialEq {
-    type EvalFut<'a>: Future<Output = Result<ExprImplRef<'a>, ExprError>> + 'a
+    type EvalFut<'a>: Future<Output = Result<ExprImpl, ExprError>> + 'a
     where
         Self: 'a;
 
-    fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'_>]) -> Self::EvalFut<'_>;
+    fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a>;
     fn as_impl_ref(&self) -> ExprImplRef<'_>;
 }
 
 impl Expression for String {
-    type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>>;
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
 
     #[inline]
     fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
-        async { Ok(self.as_impl_ref()) }
+        async { Ok(self.as_impl_ref().to_owned()) }
     }
 
     #[inline]
@@ -122,12 +200,24 @@ impl Expression for String {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Primitive<T: PrimitiveData>(T);
 
+impl<T: PrimitiveData> Primitive<T> {
+    #[inline]
+    pub fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    #[inline]
+    pub fn value(&self) -> &T {
+        &self.0
+    }
+}
+
 impl<T: PrimitiveData> Expression for Primitive<T> {
-    type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>>;
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
 
     #[inline]
     fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
-        async { Ok(self.as_impl_ref()) }
+        async { Ok(self.as_impl_ref().to_owned()) }
     }
 
     #[inline]
@@ -143,6 +233,13 @@ impl<T: PrimitiveData> Expression for Primitive<T> {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Literal(String);
 
+impl Literal {
+    #[inline]
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+}
+
 impl AsRef<str> for Literal {
     #[inline]
     fn as_ref(&self) -> &str {
@@ -151,11 +248,11 @@ impl AsRef<str> for Literal {
 }
 
 impl Expression for Literal {
-    type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>>;
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
 
     #[inline]
     fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
-        async { Ok(self.as_impl_ref()) }
+        async { Ok(self.as_impl_ref().to_owned()) }
     }
 
     #[inline]
@@ -190,9 +287,9 @@ mod tests {
     }
 
     impl Expression for Transfer {
-        type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>> + 'a;
+        type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
 
-        fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'_>]) -> Self::EvalFut<'_> {
+        fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
             async { self.inner.evaluate(context, args).await }
         }
 
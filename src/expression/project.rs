@@ -0,0 +1,252 @@
+use std::future::Future;
+use std::ops::Range;
+
+use crate::common::Instant;
+use crate::context::Context;
+
+use super::chunk::{selected_chunks, ChunkRef};
+use super::error::ExprError;
+use super::matrix::Matrix;
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// A cell of [`Rows`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum Datum {
+    Null,
+    String(String),
+    Time(Instant),
+    Float(f64),
+}
+
+/// A table of named columns, the result of a projection.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Rows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Datum>>,
+}
+
+impl Expression for Rows {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
+
+    fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
+        async { Ok(self.as_impl_ref().to_owned()) }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Rows,
+            data: self,
+        }
+    }
+}
+
+/// What a column of [`Project`] is computed from.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Projection {
+    /// Value of the label, null if the series has none.
+    Label(String),
+    /// Timestamp of the sample.
+    Time,
+    /// Sample of the field, or the value of the series when projecting a [`Matrix`].
+    Field(String),
+}
+
+/// Unnests a chunk stream or a [`Matrix`] into rows, one per series and timestamp that has a value.
+/// Rows are ordered by time, and only timestamps within `range` are kept.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Project {
+    columns: Vec<(String, Projection)>,
+    range: Option<Range<Instant>>,
+}
+
+impl Project {
+    pub fn new(columns: Vec<(String, Projection)>) -> Self {
+        Self { columns, range: None }
+    }
+
+    #[inline]
+    pub fn with_range(mut self, range: Range<Instant>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    /// Whether a column is the timestamp, rows of every shard can be merged by it.
+    pub(crate) fn has_time(&self) -> bool {
+        self.columns
+            .iter()
+            .any(|(_, projection)| *projection == Projection::Time)
+    }
+
+    /// Adds an unnamed timestamp column last, for [`Gather`](super::gather::Gather) to merge rows by.
+    pub(crate) fn with_time_key(mut self) -> Self {
+        self.columns.push((String::new(), Projection::Time));
+        self
+    }
+
+    #[inline]
+    fn contains(&self, timestamp: Instant) -> bool {
+        self.range.as_ref().is_none_or(|range| range.contains(&timestamp))
+    }
+
+    fn project_chunks(&self, args: &[ExprImplRef<'_>]) -> Result<Vec<(Instant, Vec<Datum>)>, ExprError> {
+        let fields = self
+            .columns
+            .iter()
+            .filter_map(|(_, projection)| match projection {
+                Projection::Field(field) => Some(field.as_str()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let mut rows = Vec::new();
+        for (chunk, selection) in selected_chunks(args)? {
+            let series = selection.map_or_else(|| (0..chunk.len() as u32).collect(), |selection| selection.to_vec());
+            let series_len = chunk.meta().series_len() as usize;
+            for row in series {
+                let row = row as usize;
+                // samples[i][slot] is the sample of fields[i]
+                let mut samples = vec![vec![None; series_len]; fields.len()];
+                for (field, samples) in fields.iter().zip(&mut samples) {
+                    if !chunk.for_each_sample(field, row, |slot, value| samples[slot] = Some(value)) {
+                        return Err(ExprError::FieldNotFound {
+                            name: (*field).to_owned(),
+                        });
+                    }
+                }
+                for slot in 0..series_len {
+                    let timestamp = chunk.meta().start_at + chunk.meta().time_interval() * slot as i64;
+                    if !samples.iter().any(|samples| samples[slot].is_some()) || !self.contains(timestamp) {
+                        continue;
+                    }
+                    let mut values = samples.iter().map(|samples| samples[slot]);
+                    let datums = self
+                        .columns
+                        .iter()
+                        .map(|(_, projection)| match projection {
+                            Projection::Label(label) => label_datum(&chunk, label, row),
+                            Projection::Time => Datum::Time(timestamp),
+                            Projection::Field(_) => values.next().flatten().map_or(Datum::Null, Datum::Float),
+                        })
+                        .collect();
+                    rows.push((timestamp, datums));
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    fn project_matrix(&self, matrix: &Matrix) -> Vec<(Instant, Vec<Datum>)> {
+        let mut rows = Vec::new();
+        for series in &matrix.series {
+            for (slot, value) in series.values.iter().enumerate() {
+                let timestamp = matrix.timestamp(slot);
+                let value = match value {
+                    Some(value) if self.contains(timestamp) => *value,
+                    _ => continue,
+                };
+                let datums = self
+                    .columns
+                    .iter()
+                    .map(|(_, projection)| match projection {
+                        Projection::Label(label) => series
+                            .labels
+                            .iter()
+                            .find(|(name, _)| name == label)
+                            .map_or(Datum::Null, |(_, value)| Datum::String(value.clone())),
+                        Projection::Time => Datum::Time(timestamp),
+                        Projection::Field(_) => Datum::Float(value),
+                    })
+                    .collect();
+                rows.push((timestamp, datums));
+            }
+        }
+        rows
+    }
+}
+
+fn label_datum(chunk: &ChunkRef<'_>, label: &str, row: usize) -> Datum {
+    chunk
+        .get_label(label)
+        .and_then(|label| label.get_by_id(label.id(row)?))
+        .map_or(Datum::Null, |value| Datum::String(value.to_string()))
+}
+
+impl Expression for Project {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let mut rows = match args {
+                [arg] if arg.expr_type() == ExprType::Matrix => {
+                    self.project_matrix(downcast::<Matrix>(*arg, ExprType::Matrix)?)
+                }
+                _ => self.project_chunks(args)?,
+            };
+            // stable, so rows of one timestamp keep the order of their series
+            rows.sort_by_key(|(timestamp, _)| timestamp.as_millis());
+            Ok(Rows {
+                columns: self.columns.iter().map(|(name, _)| name.clone()).collect(),
+                rows: rows.into_iter().map(|(_, row)| row).collect(),
+            }
+            .as_impl_ref()
+            .to_owned())
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Project,
+            data: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::matrix::{Matrix, Series};
+    use crate::expression::Expression;
+
+    use super::{Datum, Project, Projection, Rows};
+
+    #[test]
+    fn test_project_matrix() {
+        let matrix = Matrix {
+            start_at: Instant::from_millis(0),
+            time_interval: Duration::SECOND,
+            series: vec![
+                Series {
+                    labels: vec![(String::from("job"), String::from("api"))],
+                    values: vec![Some(1.0), None, Some(3.0)],
+                },
+                Series {
+                    labels: vec![],
+                    values: vec![None, Some(2.0), Some(4.0)],
+                },
+            ],
+        };
+        let project = Project::new(vec![
+            (String::from("job"), Projection::Label(String::from("job"))),
+            (String::from("time"), Projection::Time),
+            (String::from("sum(value)"), Projection::Field(String::from("value"))),
+        ])
+        .with_range(Instant::from_millis(1000)..Instant::from_millis(3000));
+        let result = future::block_on(project.evaluate(&mut Context::new(), &[matrix.as_impl_ref()])).unwrap();
+        let rows = result.as_any().downcast_ref::<Rows>().unwrap();
+        assert_eq!(rows.columns, vec!["job", "time", "sum(value)"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![Datum::Null, Datum::Time(Instant::from_millis(1000)), Datum::Float(2.0)],
+                vec![
+                    Datum::String(String::from("api")),
+                    Datum::Time(Instant::from_millis(2000)),
+                    Datum::Float(3.0)
+                ],
+                vec![Datum::Null, Datum::Time(Instant::from_millis(2000)), Datum::Float(4.0)],
+            ]
+        );
+    }
+}
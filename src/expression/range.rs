@@ -0,0 +1,283 @@
+use std::future::Future;
+
+use crate::common::Duration;
+use crate::context::Context;
+
+use super::aggregate::{Accumulator, AggregateOp};
+use super::chunk::selected_chunks;
+use super::error::ExprError;
+use super::matrix::Gathered;
+use super::{ExprImpl, ExprImplRef, ExprType, Expression};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RangeOp {
+    Rate,
+    Increase,
+    Delta,
+    Irate,
+    AvgOverTime,
+    MinOverTime,
+    MaxOverTime,
+    SumOverTime,
+    CountOverTime,
+    LastOverTime,
+}
+
+impl RangeOp {
+    /// Applies the function to the samples of one window `(end - range, end]`, timestamps are in seconds.
+    fn apply(&self, samples: &[(f64, f64)], end: f64, range: f64) -> Option<f64> {
+        let over_time = |op| {
+            let mut accumulator = Accumulator::default();
+            samples.iter().for_each(|(_, value)| accumulator.push(*value));
+            accumulator.result(op)
+        };
+        match self {
+            RangeOp::Rate => extrapolated_delta(samples, end - range, end, true, true),
+            RangeOp::Increase => extrapolated_delta(samples, end - range, end, true, false),
+            RangeOp::Delta => extrapolated_delta(samples, end - range, end, false, false),
+            RangeOp::Irate => match samples {
+                [.., (previous_at, previous), (last_at, last)] => {
+                    let delta = if last < previous { *last } else { last - previous };
+                    Some(delta / (last_at - previous_at))
+                }
+                _ => None,
+            },
+            RangeOp::AvgOverTime => over_time(AggregateOp::Avg),
+            RangeOp::MinOverTime => over_time(AggregateOp::Min),
+            RangeOp::MaxOverTime => over_time(AggregateOp::Max),
+            RangeOp::SumOverTime => over_time(AggregateOp::Sum),
+            RangeOp::CountOverTime => over_time(AggregateOp::Count),
+            RangeOp::LastOverTime => samples.last().map(|(_, value)| *value),
+        }
+    }
+}
+
+/// Difference between the first and last sample, extrapolated to the window bounds like Prometheus does.
+/// Counters are corrected for resets, and never extrapolated below zero.
+fn extrapolated_delta(samples: &[(f64, f64)], start: f64, end: f64, is_counter: bool, is_rate: bool) -> Option<f64> {
+    let ((first_at, first), (last_at, last)) = match samples {
+        [first, .., last] => (*first, *last),
+        _ => return None,
+    };
+    let mut result = last - first;
+    if is_counter {
+        result += samples
+            .windows(2)
+            .filter(|pair| pair[1].1 < pair[0].1)
+            .map(|pair| pair[0].1)
+            .sum::<f64>();
+    }
+    let sampled_interval = last_at - first_at;
+    let average_interval = sampled_interval / (samples.len() - 1) as f64;
+    let mut to_start = first_at - start;
+    let to_end = end - last_at;
+    if is_counter && result > 0.0 && first >= 0.0 {
+        to_start = to_start.min(sampled_interval * (first / result));
+    }
+    // only extrapolate to a bound when it is about one interval away, otherwise series likely starts or ends
+    let threshold = average_interval * 1.1;
+    let extrapolate = |duration: f64| {
+        if duration < threshold {
+            duration
+        } else {
+            average_interval / 2.0
+        }
+    };
+    result *= (sampled_interval + extrapolate(to_start) + extrapolate(to_end)) / sampled_interval;
+    if is_rate {
+        result /= end - start;
+    }
+    Some(result)
+}
+
+/// Evaluates `op` over a sliding window of `range` on `field` of every series in a chunk stream,
+/// one value per timestamp slot. Series are identified by all of their labels, and stitched across chunks.
+/// Windows are sized in slots of [`ChunkMeta::time_interval`](crate::column::ChunkMeta::time_interval),
+/// so the stream should start `range` before the first timestamp of interest.
+#[derive(Debug, PartialEq, Clone)]
+pub struct RangeFunction {
+    op: RangeOp,
+    field: String,
+    range: Duration,
+}
+
+impl RangeFunction {
+    pub fn new(op: RangeOp, field: String, range: Duration) -> Self {
+        Self { op, field, range }
+    }
+
+    #[inline]
+    pub fn range(&self) -> Duration {
+        self.range
+    }
+
+    fn evaluate_series(&self, samples: &[Option<f64>], time_interval: Duration) -> Vec<Option<f64>> {
+        let width = (self.range / time_interval).max(0) as usize;
+        let seconds = |slot: usize| (time_interval * slot as i64).as_millis() as f64 / 1000.0;
+        let range = self.range.as_millis() as f64 / 1000.0;
+        let mut window = Vec::with_capacity(width);
+        (0..samples.len())
+            .map(|slot| {
+                window.clear();
+                window.extend(
+                    ((slot + 1).saturating_sub(width)..=slot)
+                        .filter_map(|slot| samples[slot].map(|value| (seconds(slot), value))),
+                );
+                self.op.apply(&window, seconds(slot), range)
+            })
+            .collect()
+    }
+}
+
+impl Expression for RangeFunction {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let chunks = selected_chunks(args)?;
+            let gathered = Gathered::gather(
+                &chunks,
+                &self.field,
+                |_| true,
+                |slot: &mut Option<f64>, value| *slot = Some(value),
+            )?;
+            let time_interval = gathered.time_interval();
+            let matrix = gathered.into_matrix(|samples| self.evaluate_series(samples, time_interval));
+            Ok(matrix.as_impl_ref().to_owned())
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Range,
+            data: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::column::{
+        ChunkMeta, FieldImpl, FieldMeta, FieldValue, LabelImpl, LabelMeta, LabelType, LabelValue, MutableChunk,
+    };
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::error::ExprError;
+    use crate::expression::matrix::Matrix;
+    use crate::expression::Expression;
+    use crate::primitive::PrimitiveType;
+
+    use super::{RangeFunction, RangeOp};
+
+    fn chunk(start_at: i64, samples: &[(&str, i64, f64)]) -> MutableChunk {
+        let label = LabelImpl::new(&LabelMeta {
+            name: String::from("instance"),
+            data_type: LabelType::String,
+            index: vec![],
+        });
+        let field = FieldImpl::new(
+            &FieldMeta {
+                name: String::from("requests"),
+                data_type: PrimitiveType::F64,
+            },
+            4,
+        );
+        let mut chunk = MutableChunk::new(
+            vec![label],
+            vec![field],
+            ChunkMeta::new(Instant::from_millis(start_at), Duration::SECOND, 4),
+        );
+        for (instance, timestamp, value) in samples {
+            chunk
+                .insert(
+                    &[("instance", LabelValue::String(instance))],
+                    Instant::from_millis(*timestamp),
+                    &[("requests", FieldValue::Float64(*value))],
+                )
+                .unwrap();
+        }
+        chunk
+    }
+
+    fn chunks() -> Vec<MutableChunk> {
+        vec![
+            chunk(
+                0,
+                &[
+                    ("a", 0, 1.0),
+                    ("a", 1000, 2.0),
+                    ("a", 2000, 3.0),
+                    ("a", 3000, 0.0),
+                    ("b", 2000, 5.0),
+                ],
+            ),
+            chunk(4000, &[("a", 4000, 1.0)]),
+        ]
+    }
+
+    fn range(op: RangeOp, field: &str, chunks: &[MutableChunk]) -> Result<Matrix, ExprError> {
+        let function = RangeFunction::new(op, String::from(field), Duration::SECOND * 4i64);
+        let args = chunks.iter().map(|chunk| chunk.as_impl_ref()).collect::<Vec<_>>();
+        let result = future::block_on(function.evaluate(&mut Context::new(), &args))?;
+        Ok(result.as_any().downcast_ref::<Matrix>().unwrap().clone())
+    }
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap();
+        assert!((actual - expected).abs() < 1e-9, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_counter_functions() {
+        let chunks = chunks();
+        // window of slot 4 holds 2, 3, 0, 1: one reset, extrapolated by one interval towards the start
+        let rate = range(RangeOp::Rate, "requests", &chunks).unwrap();
+        assert_eq!(rate.series.len(), 2);
+        assert_eq!(
+            rate.series[0].labels,
+            vec![(String::from("instance"), String::from("a"))]
+        );
+        assert_eq!(rate.series[0].values.len(), 8);
+        assert_eq!(rate.series[0].values[0], None);
+        assert_close(rate.series[0].values[4], 2.0 / 3.0);
+        assert!(rate.series[1].values.iter().all(Option::is_none));
+
+        let increase = range(RangeOp::Increase, "requests", &chunks).unwrap();
+        assert_close(increase.series[0].values[4], 8.0 / 3.0);
+        // first sample is 1 on a slope of 1, so the extrapolation stops at zero
+        assert_close(increase.series[0].values[1], 2.0);
+
+        let delta = range(RangeOp::Delta, "requests", &chunks).unwrap();
+        assert_close(delta.series[0].values[4], -4.0 / 3.0);
+
+        let irate = range(RangeOp::Irate, "requests", &chunks).unwrap();
+        assert_close(irate.series[0].values[3], 0.0);
+        assert_close(irate.series[0].values[4], 1.0);
+        assert_eq!(irate.series[0].values[7], None);
+    }
+
+    #[test]
+    fn test_over_time() {
+        let chunks = chunks();
+        let cases = [
+            (RangeOp::AvgOverTime, 1.5, 5.0),
+            (RangeOp::MinOverTime, 0.0, 5.0),
+            (RangeOp::MaxOverTime, 3.0, 5.0),
+            (RangeOp::SumOverTime, 6.0, 5.0),
+            (RangeOp::CountOverTime, 4.0, 1.0),
+            (RangeOp::LastOverTime, 1.0, 5.0),
+        ];
+        for (op, a, b) in cases {
+            let matrix = range(op, "requests", &chunks).unwrap();
+            assert_close(matrix.series[0].values[4], a);
+            assert_close(matrix.series[1].values[5], b);
+            assert_eq!(matrix.series[1].values[6], None, "{:?}", op);
+        }
+        assert!(matches!(
+            range(RangeOp::Rate, "latency", &chunks),
+            Err(ExprError::FieldNotFound { .. })
+        ));
+    }
+}
@@ -1,92 +1,295 @@
 use std::future::Future;
+use std::ops::Range;
 use std::sync::Arc;
 
 use crate::catalog::CatalogList;
+use crate::column::ChunkMeta;
+use crate::common::Instant;
 use crate::context::Context;
 use crate::source::Table;
 
 use super::error::ExprError;
-use super::{ExprImpl, ExprImplRef, ExprType, Expression, Literal};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression, Literal};
 
+/// Resolves `catalog.schema.table` from `resource`, or its first argument if not set, and evaluates
+/// `output` with every chunk of the table that overlaps `range`, sealed or not, ordered by start.
+/// With an owner, only the shards owned by that core are scanned.
 #[derive(Debug, Clone)]
 pub struct Scanner {
     catalog_list: Arc<CatalogList>,
+    resource: Option<Literal>,
+    range: Option<Range<Instant>>,
+    /// Core and number of cores, shard `i` is owned by core `i % cores`.
+    owner: Option<(usize, usize)>,
     output: Box<ExprImpl>,
 }
 
 impl Scanner {
     pub fn new(catalog_list: Arc<CatalogList>, output: Box<ExprImpl>) -> Self {
-        Self { catalog_list, output }
+        Self {
+            catalog_list,
+            resource: None,
+            range: None,
+            owner: None,
+            output,
+        }
+    }
+
+    #[inline]
+    pub fn with_resource(mut self, resource: Literal) -> Self {
+        self.resource = Some(resource);
+        self
+    }
+
+    #[inline]
+    pub fn with_range(mut self, range: Range<Instant>) -> Self {
+        self.range = Some(range);
+        self
+    }
+
+    #[inline]
+    pub fn with_owner(mut self, core: usize, cores: usize) -> Self {
+        self.owner = Some((core, cores));
+        self
+    }
+
+    #[inline]
+    pub(crate) fn output(&self) -> &ExprImpl {
+        &self.output
+    }
+
+    #[inline]
+    pub(crate) fn with_output(mut self, output: Box<ExprImpl>) -> Self {
+        self.output = output;
+        self
+    }
+
+    #[inline]
+    fn owns(&self, shard: usize) -> bool {
+        self.owner.is_none_or(|(core, cores)| shard % cores == core)
+    }
+
+    #[inline]
+    fn overlaps(&self, meta: &ChunkMeta) -> bool {
+        match &self.range {
+            Some(range) => meta.start_at < range.end && meta.end_at() >= range.start,
+            None => true,
+        }
     }
 }
 
+/// Resolves `catalog.schema.table`, missing parts default to the default catalog and schema.
+pub(crate) fn resolve(catalog_list: &CatalogList, literal: &Literal) -> Result<Arc<Table>, ExprError> {
+    let resource = literal.as_ref().rsplit('.').collect::<Vec<_>>();
+    if resource.len() > 3 || resource.iter().any(|name| name.is_empty()) {
+        return Err(ExprError::InvalidResource {
+            resource: literal.clone(),
+        });
+    }
+    let (catalog, schema, table) = (resource.get(2).cloned(), resource.get(1).cloned(), resource[0]);
+    let catalog = match catalog {
+        Some(catalog) => catalog_list.get(catalog).ok_or_else(|| ExprError::ResourceNotFound {
+            resource: catalog.to_owned(),
+        })?,
+        None => catalog_list.get_default(),
+    };
+    let schema = match schema {
+        Some(schema) => catalog.get(schema).ok_or_else(|| ExprError::ResourceNotFound {
+            resource: schema.to_owned(),
+        })?,
+        None => catalog.get_default(),
+    };
+    schema.get(table).ok_or_else(|| ExprError::ResourceNotFound {
+        resource: table.to_owned(),
+    })
+}
+
 impl Expression for Scanner {
-    type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>> + 'a;
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
 
     fn evaluate<'a>(&'a self, context: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
-        async {
-            let literal = args.get(0).unwrap().evaluate(context, &[]).await?;
-            let literal = literal.as_any().downcast_ref::<Literal>().unwrap();
-            let resource = literal.as_ref().rsplit('.').collect::<Vec<_>>();
-            if resource.len() < 1 {
-                return Err(ExprError::InvalidResource {
-                    resource: literal.clone(),
-                });
-            }
-            let (catalog, schema, table) = (resource.get(2).cloned(), resource.get(1).cloned(), resource[0]);
-            let catalog = match catalog {
-                Some(catalog) => self
-                    .catalog_list
-                    .get(catalog)
-                    .ok_or_else(|| ExprError::ResourceNotFound {
-                        resource: catalog.to_owned(),
-                    })?,
-                None => self.catalog_list.get_default(),
-            };
-            let schema = match schema {
-                Some(schema) => catalog.get(schema).ok_or_else(|| ExprError::ResourceNotFound {
-                    resource: schema.to_owned(),
-                })?,
-                None => catalog.get_default(),
+        async move {
+            let literal = match &self.resource {
+                Some(resource) => resource.as_impl_ref().to_owned(),
+                None => {
+                    args.first()
+                        .ok_or(ExprError::MissingArgument { position: 0 })?
+                        .evaluate(context, &[])
+                        .await?
+                }
             };
-            let table = schema.get(table).ok_or_else(|| ExprError::ResourceNotFound {
-                resource: table.to_owned(),
-            })?;
-            let args = vec![table.as_impl_ref()];
-            let eval = self.output.evaluate(context, &args).await;
-            todo!()
-            // eval
+            let table = resolve(
+                &self.catalog_list,
+                downcast::<Literal>(literal.as_impl_ref(), ExprType::Literal)?,
+            )?;
+            let shards = table
+                .shards()
+                .iter()
+                .enumerate()
+                .filter(|(shard, _)| self.owns(*shard))
+                .map(|(_, shard)| shard.read().unwrap())
+                .collect::<Vec<_>>();
+            let mut chunks = shards
+                .iter()
+                .flat_map(|shard| {
+                    let immutable = shard
+                        .immutable_chunks
+                        .iter()
+                        .map(|chunk| (&chunk.meta, chunk.as_impl_ref()));
+                    let mutable = shard
+                        .mutable_chunks
+                        .iter()
+                        .map(|chunk| (&chunk.meta, chunk.as_impl_ref()));
+                    immutable.chain(mutable)
+                })
+                .filter(|(meta, _)| self.overlaps(meta))
+                .collect::<Vec<_>>();
+            chunks.sort_by_key(|(meta, _)| meta.start_at.as_millis());
+            let chunks = chunks.into_iter().map(|(_, chunk)| chunk).collect::<Vec<_>>();
+            self.output.evaluate(context, &chunks).await
         }
     }
 
-    fn as_impl_ref(&self) -> super::ExprImplRef<'_> {
-        todo!()
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Scan,
+            data: self,
+        }
     }
 }
 
 impl PartialEq for Scanner {
-    fn eq(&self, _other: &Self) -> bool {
-        true
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.catalog_list, &other.catalog_list)
+            && self.resource == other.resource
+            && self.range == other.range
+            && self.owner == other.owner
+            && self.output == other.output
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use std::sync::Arc;
-
-//     use crate::catalog::CatalogList;
-//     use crate::context::Context;
-//     use crate::expression::Literal;
-
-//     use super::Scanner;
-
-//     #[test]
-//     fn test_scan() {
-//         let catalog_list = CatalogList::new();
-//         let scanner = Scanner {
-//             catalog_list: Arc::new(catalog_list),
-//             output: Box::new(Literal(String::from("hello"))),
-//         };
-//         scanner.evaluate(&mut Context::new(), &[&Literal(String::from("hello"))]);
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::sync::Arc;
+
+    use futures_lite::future;
+
+    use crate::catalog::schema::Schema;
+    use crate::catalog::{Catalog, CatalogList};
+    use crate::column::{FieldMeta, FieldValue};
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::chunk::ChunkRef;
+    use crate::expression::error::ExprError;
+    use crate::expression::{ExprImpl, ExprImplRef, ExprType, Expression, Literal};
+    use crate::primitive::PrimitiveType;
+    use crate::source::{Table, TableMeta};
+
+    use super::Scanner;
+
+    #[derive(Debug, PartialEq, Clone)]
+    struct ChunkStarts;
+
+    impl Expression for ChunkStarts {
+        type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+        fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+            async move {
+                let starts = args
+                    .iter()
+                    .map(|arg| ChunkRef::new(*arg).unwrap())
+                    .map(|chunk| chunk.meta().start_at.as_millis().to_string())
+                    .collect::<Vec<_>>();
+                Ok(starts.join(",").as_impl_ref().to_owned())
+            }
+        }
+
+        fn as_impl_ref(&self) -> ExprImplRef<'_> {
+            ExprImplRef {
+                expr_type: ExprType::Scan,
+                data: self,
+            }
+        }
+    }
+
+    fn catalog_list() -> Arc<CatalogList> {
+        let table = Table::new(
+            String::from("http_requests_total"),
+            TableMeta {
+                labels: vec![],
+                fields: vec![FieldMeta {
+                    name: String::from("value"),
+                    data_type: PrimitiveType::F64,
+                }],
+                time_interval: Duration::SECOND,
+                series_len: 60,
+            },
+        )
+        .unwrap();
+        for timestamp in [0, 60_000, 150_000] {
+            table
+                .insert(
+                    &[],
+                    Instant::from_millis(timestamp),
+                    &[("value", FieldValue::Float64(1.0))],
+                )
+                .unwrap();
+        }
+        let schema = Arc::new(Schema::new());
+        schema.insert(Arc::new(table));
+        let catalog = Arc::new(Catalog::new());
+        catalog.insert(String::from("metrics"), schema);
+        let catalog_list = Arc::new(CatalogList::new());
+        catalog_list.insert(String::from("prometheus"), catalog);
+        catalog_list
+    }
+
+    fn scan(scanner: &Scanner, resource: &str) -> Result<ExprImpl, ExprError> {
+        let resource = Literal(String::from(resource));
+        future::block_on(scanner.evaluate(&mut Context::new(), &[resource.as_impl_ref()]))
+    }
+
+    #[test]
+    fn test_scan() {
+        let catalog_list = catalog_list();
+        let scanner = Scanner::new(catalog_list.clone(), Box::new(ChunkStarts.as_impl_ref().to_owned()));
+        let result = scan(&scanner, "prometheus.metrics.http_requests_total").unwrap();
+        assert!(result.as_any().downcast_ref::<String>().unwrap() == "0,60000,120000");
+
+        let scanner = scanner.with_range(Instant::from_millis(70_000)..Instant::from_millis(130_000));
+        let result = scan(&scanner, "prometheus.metrics.http_requests_total").unwrap();
+        assert!(result.as_any().downcast_ref::<String>().unwrap() == "60000,120000");
+
+        let table = catalog_list
+            .get("prometheus")
+            .unwrap()
+            .get("metrics")
+            .unwrap()
+            .get("http_requests_total")
+            .unwrap();
+        table.seal(Instant::from_millis(120_000));
+        let result = scan(&scanner, "prometheus.metrics.http_requests_total").unwrap();
+        assert!(result.as_any().downcast_ref::<String>().unwrap() == "60000,120000");
+    }
+
+    #[test]
+    fn test_scan_not_found() {
+        let scanner = Scanner::new(
+            Arc::new(CatalogList::new()),
+            Box::new(ChunkStarts.as_impl_ref().to_owned()),
+        );
+        assert!(matches!(
+            scan(&scanner, "prometheus.metrics.http_requests_total"),
+            Err(ExprError::ResourceNotFound { .. })
+        ));
+        assert!(matches!(
+            scan(&scanner, "http_requests_total"),
+            Err(ExprError::ResourceNotFound { .. })
+        ));
+        assert!(matches!(
+            scan(&scanner, "a.b.c.d"),
+            Err(ExprError::InvalidResource { .. })
+        ));
+    }
+}
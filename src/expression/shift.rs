@@ -0,0 +1,78 @@
+use std::future::Future;
+use std::ops::Range;
+
+use crate::common::{Duration, Instant};
+use crate::context::Context;
+
+use super::error::ExprError;
+use super::matrix::{Matrix, Series};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// Moves its [`Matrix`] argument forward in time by `offset`, so values computed from older samples
+/// line up with the current timestamps. With `at`, the values at `at - offset` are instead repeated
+/// over every slot of `range`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TimeShift {
+    offset: Duration,
+    at: Option<(Instant, Range<Instant>)>,
+}
+
+impl TimeShift {
+    pub fn new(offset: Duration) -> Self {
+        Self { offset, at: None }
+    }
+
+    #[inline]
+    pub fn with_at(mut self, at: Instant, range: Range<Instant>) -> Self {
+        self.at = Some((at, range));
+        self
+    }
+
+    fn shift(&self, matrix: &Matrix) -> Matrix {
+        let (at, range) = match &self.at {
+            Some(at) => at,
+            None => {
+                return Matrix {
+                    start_at: matrix.start_at + self.offset,
+                    ..matrix.clone()
+                }
+            }
+        };
+        let len = ((range.end - range.start) / matrix.time_interval).max(0) as usize + 1;
+        let series = matrix
+            .series
+            .iter()
+            .map(|series| Series {
+                labels: series.labels.clone(),
+                values: vec![matrix.value_at(series, *at - self.offset); len],
+            })
+            .collect();
+        Matrix {
+            start_at: range.start,
+            time_interval: matrix.time_interval,
+            series,
+        }
+        .without_empty_series()
+    }
+}
+
+impl Expression for TimeShift {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let arg = *args.first().ok_or(ExprError::MissingArgument { position: 0 })?;
+            Ok(self
+                .shift(downcast::<Matrix>(arg, ExprType::Matrix)?)
+                .as_impl_ref()
+                .to_owned())
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Shift,
+            data: self,
+        }
+    }
+}
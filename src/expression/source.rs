@@ -5,13 +5,13 @@ use crate::context::Context;
 use crate::source::Table;
 
 use super::error::ExprError;
-use super::{ExprImplRef, ExprType, Expression};
+use super::{ExprImpl, ExprImplRef, ExprType, Expression};
 
 impl Expression for Arc<Table> {
-    type EvalFut<'a> = impl Future<Output = Result<ExprImplRef<'a>, ExprError>>;
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>>;
 
     fn evaluate(&self, _: &mut Context, _: &[ExprImplRef<'_>]) -> Self::EvalFut<'_> {
-        async { Ok(self.as_impl_ref()) }
+        async { Ok(self.as_impl_ref().to_owned()) }
     }
 
     fn as_impl_ref(&self) -> ExprImplRef<'_> {
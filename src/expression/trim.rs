@@ -0,0 +1,117 @@
+use std::future::Future;
+use std::ops::Range;
+
+use crate::common::Instant;
+use crate::context::Context;
+
+use super::error::ExprError;
+use super::matrix::{Matrix, Series};
+use super::{downcast, ExprImpl, ExprImplRef, ExprType, Expression};
+
+/// Keeps the slots of its [`Matrix`] argument whose timestamps are within `range`, and the series
+/// with any value left.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Trim {
+    range: Range<Instant>,
+}
+
+impl Trim {
+    pub fn new(range: Range<Instant>) -> Self {
+        Self { range }
+    }
+
+    fn trim(&self, matrix: &Matrix) -> Matrix {
+        // first slot not before `at`
+        let slot = |at: Instant| {
+            let offset = at.as_millis().saturating_sub(matrix.start_at.as_millis());
+            let interval = matrix.time_interval.as_millis();
+            (offset.max(0).saturating_add(interval - 1) / interval) as usize
+        };
+        let (start, end) = (slot(self.range.start), slot(self.range.end));
+        let series = matrix
+            .series
+            .iter()
+            .map(|series| {
+                let len = series.values.len();
+                Series {
+                    labels: series.labels.clone(),
+                    values: series.values[start.min(len)..end.max(start).min(len)].to_vec(),
+                }
+            })
+            .collect();
+        Matrix {
+            start_at: matrix.timestamp(start),
+            time_interval: matrix.time_interval,
+            series,
+        }
+        .without_empty_series()
+    }
+}
+
+impl Expression for Trim {
+    type EvalFut<'a> = impl Future<Output = Result<ExprImpl, ExprError>> + 'a;
+
+    fn evaluate<'a>(&'a self, _: &'a mut Context, args: &'a [ExprImplRef<'a>]) -> Self::EvalFut<'a> {
+        async move {
+            let arg = *args.first().ok_or(ExprError::MissingArgument { position: 0 })?;
+            Ok(self
+                .trim(downcast::<Matrix>(arg, ExprType::Matrix)?)
+                .as_impl_ref()
+                .to_owned())
+        }
+    }
+
+    fn as_impl_ref(&self) -> ExprImplRef<'_> {
+        ExprImplRef {
+            expr_type: ExprType::Trim,
+            data: self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::matrix::{Matrix, Series};
+    use crate::expression::Expression;
+
+    use super::Trim;
+
+    #[test]
+    fn test_trim() {
+        let series = |name: &str, values: Vec<Option<f64>>| Series {
+            labels: vec![(String::from("job"), String::from(name))],
+            values,
+        };
+        let matrix = |start_at: i64, series: Vec<Series>| Matrix {
+            start_at: Instant::from_millis(start_at),
+            time_interval: Duration::SECOND,
+            series,
+        };
+        let trim = |start: i64, end: i64| {
+            let trim = Trim::new(Instant::from_millis(start)..Instant::from_millis(end));
+            let input = matrix(
+                0,
+                vec![
+                    series("a", vec![Some(0.0), Some(1.0), Some(2.0), Some(3.0)]),
+                    series("b", vec![Some(0.0), None, None, Some(3.0)]),
+                ],
+            );
+            let result = future::block_on(trim.evaluate(&mut Context::new(), &[input.as_impl_ref()])).unwrap();
+            result.as_any().downcast_ref::<Matrix>().unwrap().clone()
+        };
+        assert_eq!(
+            trim(500, 3000),
+            matrix(1000, vec![series("a", vec![Some(1.0), Some(2.0)])])
+        );
+        assert_eq!(
+            trim(-5000, 1000),
+            matrix(0, vec![series("a", vec![Some(0.0)]), series("b", vec![Some(0.0)])])
+        );
+        assert_eq!(trim(5000, 9000), matrix(5000, vec![]));
+        assert_eq!(trim(2000, 1000), matrix(2000, vec![]));
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt::{self, Debug};
 use std::hash::Hash;
 
 use croaring::Bitmap;
@@ -9,16 +10,74 @@ pub trait Index {
     type Value;
 
     fn lookup(&self, value: &Self::Value, superset: &mut Option<Bitmap>);
+    /// Narrows `superset` to the rows that may satisfy `condition`. An index that can not
+    /// tell leaves `superset` untouched.
+    fn lookup_where(&self, condition: &Condition<'_, Self::Value>, superset: &mut Option<Bitmap>);
     fn insert(&mut self, row: u32, value: Self::Value);
     fn exactly(&self) -> bool;
 }
 
+/// A condition on indexed values, negative conditions hold on the rows the positive one does not.
+pub enum Condition<'a, V> {
+    In(&'a [V]),
+    NotIn(&'a [V]),
+    Matches(&'a dyn Fn(&V) -> bool),
+    NotMatches(&'a dyn Fn(&V) -> bool),
+}
+
+impl<'a, V> Condition<'a, V> {
+    #[inline]
+    pub fn is_negative(&self) -> bool {
+        matches!(self, Condition::NotIn(_) | Condition::NotMatches(_))
+    }
+
+    /// Whether `value` satisfies the positive form of the condition.
+    #[inline]
+    fn test_positive(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        match self {
+            Condition::In(values) | Condition::NotIn(values) => values.contains(value),
+            Condition::Matches(matcher) | Condition::NotMatches(matcher) => matcher(value),
+        }
+    }
+
+    #[inline]
+    pub fn test(&self, value: &V) -> bool
+    where
+        V: PartialEq,
+    {
+        self.test_positive(value) != self.is_negative()
+    }
+}
+
+impl<'a, V: Debug> Debug for Condition<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::In(values) => f.debug_tuple("In").field(values).finish(),
+            Condition::NotIn(values) => f.debug_tuple("NotIn").field(values).finish(),
+            Condition::Matches(_) => f.write_str("Matches(..)"),
+            Condition::NotMatches(_) => f.write_str("NotMatches(..)"),
+        }
+    }
+}
+
+#[inline]
+fn intersect(superset: &mut Option<Bitmap>, rows: Bitmap) {
+    match superset {
+        Some(s) => s.and_inplace(&rows),
+        None => *superset = Some(rows),
+    }
+}
+
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct InvertedIndex<V>
 where
     V: Eq + Hash,
 {
     data: HashMap<V, Bitmap>,
+    rows: Bitmap,
 }
 
 impl<V> InvertedIndex<V>
@@ -27,7 +86,19 @@ where
 {
     #[inline]
     pub fn new() -> Self {
-        Self { data: HashMap::new() }
+        Self {
+            data: HashMap::new(),
+            rows: Bitmap::create(),
+        }
+    }
+
+    /// Compacts the postings, for an index that will not be inserted into anymore.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        for set in self.data.values_mut().chain(std::iter::once(&mut self.rows)) {
+            set.run_optimize();
+            set.shrink_to_fit();
+        }
     }
 }
 
@@ -39,16 +110,42 @@ where
 
     #[inline]
     fn lookup(&self, value: &Self::Value, superset: &mut Option<Bitmap>) {
-        self.data.get(value).map(|set| match superset {
-            Some(s) => s.and_inplace(set),
-            None => *superset = Some(set.clone()),
-        });
+        match (self.data.get(value), superset.as_mut()) {
+            (Some(set), Some(s)) => s.and_inplace(set),
+            (Some(set), None) => *superset = Some(set.clone()),
+            (None, _) => *superset = Some(Bitmap::create()),
+        }
+    }
+
+    /// Every distinct value is tested once and the postings of those satisfying the positive
+    /// condition are or-ed, negative conditions subtract them from all indexed rows.
+    fn lookup_where(&self, condition: &Condition<'_, Self::Value>, superset: &mut Option<Bitmap>) {
+        let mut rows = Bitmap::create();
+        match condition {
+            Condition::In(values) | Condition::NotIn(values) => {
+                for set in values.iter().filter_map(|value| self.data.get(value)) {
+                    rows.or_inplace(set);
+                }
+            }
+            Condition::Matches(matcher) | Condition::NotMatches(matcher) => {
+                for (_, set) in self.data.iter().filter(|(value, _)| matcher(value)) {
+                    rows.or_inplace(set);
+                }
+            }
+        }
+        if condition.is_negative() {
+            let mut all = self.rows.clone();
+            all.andnot_inplace(&rows);
+            rows = all;
+        }
+        intersect(superset, rows);
     }
 
     #[inline]
     fn insert(&mut self, row: u32, value: Self::Value) {
-        let bitmap = self.data.entry(value).or_insert_with(|| Bitmap::create());
+        let bitmap = self.data.entry(value).or_insert_with(Bitmap::create);
         bitmap.add(row);
+        self.rows.add(row);
     }
 
     #[inline]
@@ -71,6 +168,11 @@ impl<T: Hash> SparseIndex<T> {
             block_size,
         }
     }
+
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        self.seens.shrink_to_fit();
+    }
 }
 
 impl<V: Hash> Index for SparseIndex<V> {
@@ -91,6 +193,20 @@ impl<V: Hash> Index for SparseIndex<V> {
         }
     }
 
+    /// Only `In` can be answered, a bloom filter neither enumerates its values nor proves
+    /// a block holds nothing else.
+    fn lookup_where(&self, condition: &Condition<'_, Self::Value>, superset: &mut Option<Bitmap>) {
+        if let Condition::In(values) = condition {
+            let mut rows = Bitmap::create();
+            for value in values.iter() {
+                let mut candidates = None;
+                self.lookup(value, &mut candidates);
+                rows.or_inplace(&candidates.unwrap());
+            }
+            intersect(superset, rows);
+        }
+    }
+
     #[inline]
     fn insert(&mut self, row: u32, value: Self::Value) {
         let block = (row / self.block_size) as usize;
@@ -126,6 +242,61 @@ where
             IndexType::Sparse(block_size) => IndexImpl::Sparse(SparseIndex::new(block_size)),
         }
     }
+
+    #[inline]
+    pub fn data_type(&self) -> IndexType<(), u32> {
+        match self {
+            IndexImpl::Inverted(_) => IndexType::Inverted(()),
+            IndexImpl::Sparse(index) => IndexType::Sparse(index.block_size),
+        }
+    }
+
+    #[inline]
+    pub fn shrink_to_fit(&mut self) {
+        match self {
+            IndexImpl::Inverted(index) => index.shrink_to_fit(),
+            IndexImpl::Sparse(index) => index.shrink_to_fit(),
+        }
+    }
+}
+
+impl<V> Index for IndexImpl<V>
+where
+    V: Eq + Hash,
+{
+    type Value = V;
+
+    #[inline]
+    fn lookup(&self, value: &Self::Value, superset: &mut Option<Bitmap>) {
+        match self {
+            IndexImpl::Inverted(index) => index.lookup(value, superset),
+            IndexImpl::Sparse(index) => index.lookup(value, superset),
+        }
+    }
+
+    #[inline]
+    fn lookup_where(&self, condition: &Condition<'_, Self::Value>, superset: &mut Option<Bitmap>) {
+        match self {
+            IndexImpl::Inverted(index) => index.lookup_where(condition, superset),
+            IndexImpl::Sparse(index) => index.lookup_where(condition, superset),
+        }
+    }
+
+    #[inline]
+    fn insert(&mut self, row: u32, value: Self::Value) {
+        match self {
+            IndexImpl::Inverted(index) => index.insert(row, value),
+            IndexImpl::Sparse(index) => index.insert(row, value),
+        }
+    }
+
+    #[inline]
+    fn exactly(&self) -> bool {
+        match self {
+            IndexImpl::Inverted(index) => index.exactly(),
+            IndexImpl::Sparse(index) => index.exactly(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -135,7 +306,7 @@ mod tests {
 
     use crate::index::InvertedIndex;
 
-    use super::{Index, SparseIndex};
+    use super::{Condition, Index, SparseIndex};
 
     #[test]
     fn test_bloom_filter() {
@@ -152,7 +323,7 @@ mod tests {
             seen.k(),
         );
         const BOUND_SIZE_BYTES: usize = 15_000_000;
-        let size_bytes = (seen.m() + 7) / 8;
+        let size_bytes = seen.m().div_ceil(8);
         assert!(
             size_bytes <= BOUND_SIZE_BYTES,
             "size of bloom filter should be <= {} bytes but is {} bytes",
@@ -184,6 +355,44 @@ mod tests {
         expect.add(0);
         expect.add(1001);
         assert!(result == Some(expect));
+        let mut result = None;
+        index.lookup(&2, &mut result);
+        assert!(result == Some(Bitmap::create()));
+    }
+
+    #[test]
+    fn test_inverted_index_condition() {
+        let mut index = InvertedIndex::<usize>::new();
+        for (row, value) in [1, 2, 3, 1, 4].into_iter().enumerate() {
+            index.insert(row as u32, value);
+        }
+        let cases: [(Condition<'_, usize>, &[u32]); 4] = [
+            (Condition::In(&[1, 4, 5]), &[0, 3, 4]),
+            (Condition::NotIn(&[1]), &[1, 2, 4]),
+            (Condition::Matches(&|value| value % 2 == 0), &[1, 4]),
+            (Condition::NotMatches(&|value| value % 2 == 0), &[0, 2, 3]),
+        ];
+        for (condition, expect) in cases {
+            let mut result = None;
+            index.lookup_where(&condition, &mut result);
+            assert!(result == Some(Bitmap::of(expect)));
+        }
+        let mut result = Some(Bitmap::of(&[0, 1]));
+        index.lookup_where(&Condition::NotIn(&[2]), &mut result);
+        assert!(result == Some(Bitmap::of(&[0])));
+    }
+
+    #[test]
+    fn test_sparse_index_condition() {
+        let mut index = SparseIndex::<usize>::new(2);
+        index.insert(0, 1);
+        index.insert(1, 1);
+        index.insert(2, 2);
+        let mut result = None;
+        index.lookup_where(&Condition::NotIn(&[1]), &mut result);
+        assert!(result.is_none());
+        index.lookup_where(&Condition::In(&[2]), &mut result);
+        assert!(result == Some(Bitmap::of(&[2, 3])));
     }
 
     #[test]
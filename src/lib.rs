@@ -12,4 +12,5 @@ pub mod executor;
 pub mod expression;
 pub mod index;
 pub mod primitive;
+pub mod query;
 pub mod source;
@@ -2,17 +2,28 @@ use std::fmt::{Debug, Display};
 
 pub trait Primitive: 'static + Send + Sync + Sized + Debug + Display + PartialEq + Default + Clone {
     const TYPE: PrimitiveType;
+
+    /// The value as a sample, `true` is `1.0`.
+    fn to_f64(&self) -> f64;
 }
 
 macro_rules! native_type {
     ($type:ty, $data_type:expr) => {
+        native_type!($type, $data_type, |value: &$type| *value as f64);
+    };
+    ($type:ty, $data_type:expr, $to_f64:expr) => {
         impl Primitive for $type {
             const TYPE: PrimitiveType = $data_type;
+
+            #[inline]
+            fn to_f64(&self) -> f64 {
+                ($to_f64)(self)
+            }
         }
     };
 }
 
-native_type!(bool, PrimitiveType::Bool);
+native_type!(bool, PrimitiveType::Bool, |value: &bool| if *value { 1.0 } else { 0.0 });
 native_type!(u8, PrimitiveType::U8);
 native_type!(u16, PrimitiveType::U16);
 native_type!(u32, PrimitiveType::U32);
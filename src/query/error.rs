@@ -0,0 +1,50 @@
+use snafu::Snafu;
+
+use super::lexer::{Position, Token};
+
+#[derive(Snafu, Debug)]
+#[snafu(visibility(pub(crate)))]
+pub enum QueryError {
+    #[snafu(display("unexpected character {:?} at {}", character, position))]
+    UnexpectedCharacter { character: char, position: Position },
+    #[snafu(display("unterminated string at {}", position))]
+    UnterminatedString { position: Position },
+    #[snafu(display("invalid number {:?} at {}", literal, position))]
+    InvalidNumber { literal: String, position: Position },
+    #[snafu(display("invalid duration {:?} at {}", literal, position))]
+    InvalidDuration { literal: String, position: Position },
+    #[snafu(display("expected {}, found {} at {}", expected, found, position))]
+    UnexpectedToken {
+        expected: String,
+        found: Token,
+        position: Position,
+    },
+    #[snafu(display("unknown function {:?} at {}", name, position))]
+    UnknownFunction { name: String, position: Position },
+    #[snafu(display("unknown metric {:?} at {}", name, position))]
+    UnknownMetric { name: String, position: Position },
+    #[snafu(display("unknown table {:?} at {}", name, position))]
+    UnknownTable { name: String, position: Position },
+    #[snafu(display("unknown column {:?} at {}", name, position))]
+    UnknownColumn { name: String, position: Position },
+    #[snafu(display("{} at {}", reason, position))]
+    InvalidExpression { reason: String, position: Position },
+}
+
+impl QueryError {
+    /// Where in the query text the error occurred.
+    pub fn position(&self) -> Position {
+        match self {
+            QueryError::UnexpectedCharacter { position, .. }
+            | QueryError::UnterminatedString { position }
+            | QueryError::InvalidNumber { position, .. }
+            | QueryError::InvalidDuration { position, .. }
+            | QueryError::UnexpectedToken { position, .. }
+            | QueryError::UnknownFunction { position, .. }
+            | QueryError::UnknownMetric { position, .. }
+            | QueryError::UnknownTable { position, .. }
+            | QueryError::UnknownColumn { position, .. }
+            | QueryError::InvalidExpression { position, .. } => *position,
+        }
+    }
+}
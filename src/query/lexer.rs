@@ -0,0 +1,473 @@
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::common::{Duration, Instant};
+
+use super::error::QueryError;
+
+/// 1-based line and column of a character in the query text.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Token {
+    /// Identifiers and keywords, keywords are matched case-insensitively by the parser.
+    Ident(String),
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Duration(Duration),
+    LeftParen,
+    RightParen,
+    LeftBracket,
+    RightBracket,
+    LeftBrace,
+    RightBrace,
+    Comma,
+    Dot,
+    Eq,
+    EqEq,
+    NotEq,
+    RegexMatch,
+    RegexNotMatch,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    At,
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "{:?}", ident),
+            Token::String(string) => write!(f, "string {:?}", string),
+            Token::Integer(integer) => write!(f, "integer {}", integer),
+            Token::Float(float) => write!(f, "number {}", float),
+            Token::Duration(duration) => write!(f, "duration {}ms", duration.as_millis()),
+            Token::LeftParen => f.write_str("'('"),
+            Token::RightParen => f.write_str("')'"),
+            Token::LeftBracket => f.write_str("'['"),
+            Token::RightBracket => f.write_str("']'"),
+            Token::LeftBrace => f.write_str("'{'"),
+            Token::RightBrace => f.write_str("'}'"),
+            Token::Comma => f.write_str("','"),
+            Token::Dot => f.write_str("'.'"),
+            Token::Eq => f.write_str("'='"),
+            Token::EqEq => f.write_str("'=='"),
+            Token::NotEq => f.write_str("'!='"),
+            Token::RegexMatch => f.write_str("'=~'"),
+            Token::RegexNotMatch => f.write_str("'!~'"),
+            Token::Lt => f.write_str("'<'"),
+            Token::LtEq => f.write_str("'<='"),
+            Token::Gt => f.write_str("'>'"),
+            Token::GtEq => f.write_str("'>='"),
+            Token::Plus => f.write_str("'+'"),
+            Token::Minus => f.write_str("'-'"),
+            Token::Star => f.write_str("'*'"),
+            Token::Slash => f.write_str("'/'"),
+            Token::Percent => f.write_str("'%'"),
+            Token::Caret => f.write_str("'^'"),
+            Token::At => f.write_str("'@'"),
+            Token::Eof => f.write_str("end of query"),
+        }
+    }
+}
+
+/// Splits a query into tokens, each with the position of its first character.
+#[derive(Debug)]
+pub struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    position: Position,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self {
+            chars: input.chars().peekable(),
+            position: Position { line: 1, column: 1 },
+        }
+    }
+
+    /// All tokens of `input`, always terminated by [`Token::Eof`].
+    pub fn tokenize(input: &'a str) -> Result<Vec<(Token, Position)>, QueryError> {
+        let mut lexer = Self::new(input);
+        let mut tokens = Vec::new();
+        loop {
+            let (token, position) = lexer.next_token()?;
+            let eof = token == Token::Eof;
+            tokens.push((token, position));
+            if eof {
+                return Ok(tokens);
+            }
+        }
+    }
+
+    #[inline]
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.position.line += 1;
+            self.position.column = 1;
+        } else {
+            self.position.column += 1;
+        }
+        Some(c)
+    }
+
+    #[inline]
+    fn bump_if(&mut self, expected: char) -> bool {
+        if self.chars.peek() == Some(&expected) {
+            self.bump();
+            return true;
+        }
+        false
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(c) = self.chars.peek().copied().filter(|c| f(*c)) {
+            taken.push(c);
+            self.bump();
+        }
+        taken
+    }
+
+    pub fn next_token(&mut self) -> Result<(Token, Position), QueryError> {
+        self.take_while(char::is_whitespace);
+        while self.bump_if('#') {
+            self.take_while(|c| c != '\n');
+            self.take_while(char::is_whitespace);
+        }
+        let position = self.position;
+        let c = match self.bump() {
+            Some(c) => c,
+            None => return Ok((Token::Eof, position)),
+        };
+        let token = match c {
+            '(' => Token::LeftParen,
+            ')' => Token::RightParen,
+            '[' => Token::LeftBracket,
+            ']' => Token::RightBracket,
+            '{' => Token::LeftBrace,
+            '}' => Token::RightBrace,
+            ',' => Token::Comma,
+            '.' => Token::Dot,
+            '<' if self.bump_if('=') => Token::LtEq,
+            '<' => Token::Lt,
+            '>' if self.bump_if('=') => Token::GtEq,
+            '>' => Token::Gt,
+            '+' => Token::Plus,
+            '-' => Token::Minus,
+            '*' => Token::Star,
+            '/' => Token::Slash,
+            '%' => Token::Percent,
+            '^' => Token::Caret,
+            '@' => Token::At,
+            '=' if self.bump_if('~') => Token::RegexMatch,
+            '=' if self.bump_if('=') => Token::EqEq,
+            '=' => Token::Eq,
+            '!' if self.bump_if('=') => Token::NotEq,
+            '!' if self.bump_if('~') => Token::RegexNotMatch,
+            '"' | '\'' | '`' => self.string(c, position)?,
+            c if c.is_ascii_digit() => self.number(c, position)?,
+            // colons are allowed for PromQL recording rule names like `job:requests:rate5m`
+            c if c.is_alphabetic() || c == '_' || c == ':' => {
+                let mut ident = String::from(c);
+                ident.push_str(&self.take_while(|c| c.is_alphanumeric() || c == '_' || c == ':'));
+                Token::Ident(ident)
+            }
+            character => return Err(QueryError::UnexpectedCharacter { character, position }),
+        };
+        Ok((token, position))
+    }
+
+    fn string(&mut self, quote: char, position: Position) -> Result<Token, QueryError> {
+        let mut string = String::new();
+        loop {
+            match self.bump() {
+                Some(c) if c == quote => return Ok(Token::String(string)),
+                // raw strings have no escapes
+                Some('\\') if quote != '`' => match self.bump() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some(c) => string.push(c),
+                    None => break,
+                },
+                Some(c) => string.push(c),
+                None => break,
+            }
+        }
+        Err(QueryError::UnterminatedString { position })
+    }
+
+    #[inline]
+    fn peek_second(&self) -> Option<char> {
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next()
+    }
+
+    /// An integer, a float such as `1.5` or `2e-3`, or a duration such as `5m` or `1h30m`
+    /// when units follow the digits.
+    fn number(&mut self, first: char, position: Position) -> Result<Token, QueryError> {
+        let mut literal = String::from(first);
+        literal.push_str(&self.take_while(|c| c.is_ascii_digit()));
+        let mut is_float = false;
+        if self.chars.peek() == Some(&'.') && self.peek_second().is_some_and(|c| c.is_ascii_digit()) {
+            self.bump();
+            literal.push('.');
+            literal.push_str(&self.take_while(|c| c.is_ascii_digit()));
+            is_float = true;
+        }
+        if matches!(self.chars.peek(), Some('e' | 'E'))
+            && self
+                .peek_second()
+                .is_some_and(|c| c.is_ascii_digit() || c == '+' || c == '-')
+        {
+            literal.extend(self.bump());
+            literal.extend(self.bump());
+            literal.push_str(&self.take_while(|c| c.is_ascii_digit()));
+            is_float = true;
+        }
+        if is_float {
+            return literal
+                .parse()
+                .map(Token::Float)
+                .map_err(|_| QueryError::InvalidNumber { literal, position });
+        }
+        if !self.chars.peek().is_some_and(|c| c.is_alphabetic()) {
+            return literal
+                .parse()
+                .map(Token::Integer)
+                .map_err(|_| QueryError::InvalidNumber { literal, position });
+        }
+        literal.push_str(&self.take_while(char::is_alphanumeric));
+        parse_duration(&literal)
+            .map(Token::Duration)
+            .ok_or(QueryError::InvalidDuration { literal, position })
+    }
+}
+
+/// Cursor over the tokens of a query, with the helpers shared by the parsers.
+#[derive(Debug)]
+pub(crate) struct TokenStream {
+    tokens: Vec<(Token, Position)>,
+    cursor: usize,
+}
+
+impl TokenStream {
+    pub(crate) fn new(input: &str) -> Result<Self, QueryError> {
+        Ok(Self {
+            tokens: Lexer::tokenize(input)?,
+            cursor: 0,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn peek(&self) -> &Token {
+        &self.tokens[self.cursor].0
+    }
+
+    /// The token `n` tokens after the current one, if any.
+    #[inline]
+    pub(crate) fn peek_nth(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.cursor + n).map(|(token, _)| token)
+    }
+
+    #[inline]
+    pub(crate) fn position(&self) -> Position {
+        self.tokens[self.cursor].1
+    }
+
+    #[inline]
+    pub(crate) fn next(&mut self) -> (Token, Position) {
+        let token = self.tokens[self.cursor].clone();
+        // the last token is always Eof, keep returning it
+        self.cursor = (self.cursor + 1).min(self.tokens.len() - 1);
+        token
+    }
+
+    pub(crate) fn unexpected<T>(&self, expected: &str) -> Result<T, QueryError> {
+        Err(QueryError::UnexpectedToken {
+            expected: expected.to_owned(),
+            found: self.peek().clone(),
+            position: self.position(),
+        })
+    }
+
+    pub(crate) fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Ident(ident) if ident.eq_ignore_ascii_case(keyword))
+    }
+
+    pub(crate) fn keyword(&mut self, keyword: &str) -> Result<(), QueryError> {
+        if !self.is_keyword(keyword) {
+            return self.unexpected(keyword);
+        }
+        self.next();
+        Ok(())
+    }
+
+    pub(crate) fn consume(&mut self, expected: Token) -> bool {
+        if *self.peek() == expected {
+            self.next();
+            return true;
+        }
+        false
+    }
+
+    pub(crate) fn expect(&mut self, expected: Token) -> Result<(), QueryError> {
+        if !self.consume(expected.clone()) {
+            return self.unexpected(&expected.to_string());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn ident(&mut self) -> Result<(String, Position), QueryError> {
+        match self.next() {
+            (Token::Ident(ident), position) => Ok((ident, position)),
+            (found, position) => Err(QueryError::UnexpectedToken {
+                expected: String::from("identifier"),
+                found,
+                position,
+            }),
+        }
+    }
+
+    pub(crate) fn duration(&mut self) -> Result<Duration, QueryError> {
+        match self.next() {
+            (Token::Duration(duration), _) => Ok(duration),
+            (found, position) => Err(QueryError::UnexpectedToken {
+                expected: String::from("duration"),
+                found,
+                position,
+            }),
+        }
+    }
+
+    /// A point in time, `now` is the value of the `now` keyword and integers are epoch milliseconds.
+    pub(crate) fn time(&mut self, now: Instant) -> Result<Instant, QueryError> {
+        let mut time = match self.peek() {
+            Token::LeftParen => {
+                self.next();
+                let time = self.time(now)?;
+                self.expect(Token::RightParen)?;
+                time
+            }
+            Token::Integer(millis) => {
+                let time = Instant::from_millis(*millis);
+                self.next();
+                time
+            }
+            _ if self.is_keyword("now") => {
+                self.next();
+                // SQL spells it as a function call
+                if self.peek_nth(1) == Some(&Token::RightParen) && self.consume(Token::LeftParen) {
+                    self.next();
+                }
+                now
+            }
+            _ => return self.unexpected("time"),
+        };
+        loop {
+            let negative = match self.peek() {
+                Token::Plus => false,
+                Token::Minus => true,
+                _ => return Ok(time),
+            };
+            self.next();
+            let duration = self.duration()?;
+            time = if negative { time - duration } else { time + duration };
+        }
+    }
+}
+
+fn parse_duration(literal: &str) -> Option<Duration> {
+    let mut millis = 0i64;
+    let mut rest = literal;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit())?;
+        let units = rest[digits..]
+            .find(|c: char| c.is_ascii_digit())
+            .map_or(rest.len(), |i| i + digits);
+        let value = rest[..digits].parse::<i64>().ok()?;
+        let unit = match &rest[digits..units] {
+            "ms" => 1,
+            "s" => 1_000,
+            "m" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            "w" => 604_800_000,
+            "y" => 31_536_000_000,
+            _ => return None,
+        };
+        millis = millis.checked_add(value.checked_mul(unit)?)?;
+        rest = &rest[units..];
+    }
+    Some(Duration::from_millis(millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::common::Duration;
+    use crate::query::error::QueryError;
+
+    use super::{Lexer, Position, Token};
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = Lexer::tokenize("FROM a.b\n  WHERE job !~ \"x\\\"y\" RANGE 1h30m (").unwrap();
+        let expected = [
+            (Token::Ident(String::from("FROM")), 1, 1),
+            (Token::Ident(String::from("a")), 1, 6),
+            (Token::Dot, 1, 7),
+            (Token::Ident(String::from("b")), 1, 8),
+            (Token::Ident(String::from("WHERE")), 2, 3),
+            (Token::Ident(String::from("job")), 2, 9),
+            (Token::RegexNotMatch, 2, 13),
+            (Token::String(String::from("x\"y")), 2, 16),
+            (Token::Ident(String::from("RANGE")), 2, 23),
+            (Token::Duration(Duration::from_millis(5_400_000)), 2, 29),
+            (Token::LeftParen, 2, 35),
+            (Token::Eof, 2, 36),
+        ];
+        assert_eq!(tokens.len(), expected.len());
+        for ((token, position), (expected, line, column)) in tokens.into_iter().zip(expected) {
+            assert_eq!(token, expected);
+            assert_eq!(position, Position { line, column });
+        }
+        assert_eq!(Lexer::tokenize("300").unwrap()[0].0, Token::Integer(300));
+    }
+
+    #[test]
+    fn test_tokenize_errors() {
+        let error = Lexer::tokenize("FROM t\nWHERE job = \"x").unwrap_err();
+        assert!(matches!(error, QueryError::UnterminatedString { .. }));
+        assert_eq!(error.position(), Position { line: 2, column: 13 });
+
+        let error = Lexer::tokenize("RANGE 5x").unwrap_err();
+        assert!(matches!(error, QueryError::InvalidDuration { .. }));
+        assert_eq!(error.position(), Position { line: 1, column: 7 });
+
+        let error = Lexer::tokenize("a ; b").unwrap_err();
+        assert!(matches!(error, QueryError::UnexpectedCharacter { character: ';', .. }));
+        assert_eq!(error.to_string(), "unexpected character ';' at 1:3");
+    }
+}
@@ -0,0 +1,36 @@
+pub mod error;
+pub mod lexer;
+pub mod parser;
+pub mod promql;
+pub mod sql;
+#[cfg(test)]
+mod testing;
+
+use std::sync::Arc;
+
+use crate::catalog::CatalogList;
+use crate::common::Instant;
+use crate::context::Context;
+use crate::expression::error::ExprError;
+use crate::expression::{ExprImpl, Expression, Literal};
+
+use self::error::QueryError;
+use self::parser::Parser;
+
+/// A parsed query, `plan` is a [`Scanner`](crate::expression::scan::Scanner) evaluated with `resource`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Query {
+    pub resource: Literal,
+    pub plan: ExprImpl,
+}
+
+impl Query {
+    pub async fn evaluate(&self, context: &mut Context) -> Result<ExprImpl, ExprError> {
+        self.plan.evaluate(context, &[self.resource.as_impl_ref()]).await
+    }
+}
+
+/// Parses `input` against `catalog_list`, `now` is the value of the `now` keyword.
+pub fn parse(input: &str, catalog_list: Arc<CatalogList>, now: Instant) -> Result<Query, QueryError> {
+    Parser::new(input, catalog_list, now)?.parse()
+}
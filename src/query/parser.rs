@@ -0,0 +1,425 @@
+use std::sync::Arc;
+
+use crate::catalog::CatalogList;
+use crate::common::{Duration, Instant};
+use crate::expression::aggregate::{Aggregate, AggregateOp, Grouping};
+use crate::expression::chain::Chain;
+use crate::expression::filter::{BinaryOp, Column, Filter, Predicate};
+use crate::expression::range::{RangeFunction, RangeOp};
+use crate::expression::scan::Scanner;
+use crate::expression::trim::Trim;
+use crate::expression::{ExprImpl, Expression, Literal};
+
+use super::error::QueryError;
+use super::lexer::{Token, TokenStream};
+use super::Query;
+
+/// Recursive descent parser of
+///
+/// ```text
+/// query      := FROM resource [WHERE condition] [RANGE time '<' TIME '<' time] projection
+/// resource   := ident ['.' ident ['.' ident]]
+/// condition  := conjunction {OR conjunction}
+/// conjunction:= matcher {AND matcher}
+/// matcher    := '(' condition ')' | ident ('=' | '!=' | '=~' | '!~') string
+/// time       := (NOW ['(' ')'] | integer | '(' time ')') {('+' | '-') duration}
+/// projection := aggregate '(' (ident | range) ')' [(BY | WITHOUT) labels] | range
+/// range      := function '(' ident '[' duration ']' ')'
+/// labels     := '(' [ident {',' ident}] ')' | ident {',' ident}
+/// ```
+///
+/// Keywords and function names are case-insensitive, integers in time bounds are epoch milliseconds.
+#[derive(Debug)]
+pub struct Parser {
+    tokens: TokenStream,
+    catalog_list: Arc<CatalogList>,
+    now: Instant,
+}
+
+struct Projection {
+    aggregate: Option<(AggregateOp, Grouping)>,
+    range: Option<(RangeOp, Duration)>,
+    field: String,
+}
+
+impl Parser {
+    pub fn new(input: &str, catalog_list: Arc<CatalogList>, now: Instant) -> Result<Self, QueryError> {
+        Ok(Self {
+            tokens: TokenStream::new(input)?,
+            catalog_list,
+            now,
+        })
+    }
+
+    pub fn parse(mut self) -> Result<Query, QueryError> {
+        self.tokens.keyword("FROM")?;
+        let resource = self.resource()?;
+        let predicate = if self.tokens.is_keyword("WHERE") {
+            self.tokens.next();
+            Some(self.condition()?)
+        } else {
+            None
+        };
+        let range = if self.tokens.is_keyword("RANGE") {
+            self.tokens.next();
+            Some(self.range()?)
+        } else {
+            None
+        };
+        let projection = self.projection()?;
+        if *self.tokens.peek() != Token::Eof {
+            return self.tokens.unexpected(&Token::Eof.to_string());
+        }
+
+        let aggregate = projection.aggregate.map(|(op, grouping)| {
+            Aggregate::new(op, projection.field.clone(), grouping)
+                .as_impl_ref()
+                .to_owned()
+        });
+        // a range function consumes the chunks and is aggregated afterwards, an aggregate alone consumes them
+        let (mut plan, then) = match projection.range {
+            Some((op, range)) => (
+                RangeFunction::new(op, projection.field, range).as_impl_ref().to_owned(),
+                aggregate,
+            ),
+            None => (aggregate.unwrap(), None),
+        };
+        if let Some(predicate) = predicate {
+            plan = Filter::new(Box::new(predicate), Box::new(plan))
+                .as_impl_ref()
+                .to_owned();
+        }
+        let mut scanner = Scanner::new(self.catalog_list, Box::new(plan));
+        let mut then = then.into_iter().collect::<Vec<_>>();
+        if let Some(range) = range {
+            // range functions look back from the first timestamp
+            let lookback = projection
+                .range
+                .map_or(Duration::from_millis(0), |(_, lookback)| lookback);
+            scanner = scanner.with_range(range.start - lookback..range.end);
+            then.push(Trim::new(range).as_impl_ref().to_owned());
+        }
+        Ok(Query {
+            resource,
+            plan: Chain::append(scanner.as_impl_ref().to_owned(), then),
+        })
+    }
+
+    fn resource(&mut self) -> Result<Literal, QueryError> {
+        let (mut resource, _) = self.tokens.ident()?;
+        for _ in 0..2 {
+            if !self.tokens.consume(Token::Dot) {
+                break;
+            }
+            resource.push('.');
+            resource.push_str(&self.tokens.ident()?.0);
+        }
+        Ok(Literal::new(resource))
+    }
+
+    fn condition(&mut self) -> Result<ExprImpl, QueryError> {
+        let mut lhs = self.conjunction()?;
+        while self.tokens.is_keyword("OR") {
+            self.tokens.next();
+            let rhs = self.conjunction()?;
+            lhs = Predicate::new(BinaryOp::Or, Box::new(lhs), Box::new(rhs))
+                .as_impl_ref()
+                .to_owned();
+        }
+        Ok(lhs)
+    }
+
+    fn conjunction(&mut self) -> Result<ExprImpl, QueryError> {
+        let mut lhs = self.matcher()?;
+        while self.tokens.is_keyword("AND") {
+            self.tokens.next();
+            let rhs = self.matcher()?;
+            lhs = Predicate::new(BinaryOp::And, Box::new(lhs), Box::new(rhs))
+                .as_impl_ref()
+                .to_owned();
+        }
+        Ok(lhs)
+    }
+
+    fn matcher(&mut self) -> Result<ExprImpl, QueryError> {
+        if self.tokens.consume(Token::LeftParen) {
+            let condition = self.condition()?;
+            self.tokens.expect(Token::RightParen)?;
+            return Ok(condition);
+        }
+        let (label, _) = self.tokens.ident()?;
+        let op = match self.tokens.peek() {
+            Token::Eq => BinaryOp::ExactMatch,
+            Token::NotEq => BinaryOp::ExactNotMatch,
+            Token::RegexMatch => BinaryOp::RegexMatch,
+            Token::RegexNotMatch => BinaryOp::RegexNotMatch,
+            _ => return self.tokens.unexpected("matcher"),
+        };
+        self.tokens.next();
+        let value = match self.tokens.next() {
+            (Token::String(value), _) => value,
+            (found, position) => {
+                return Err(QueryError::UnexpectedToken {
+                    expected: String::from("string"),
+                    found,
+                    position,
+                })
+            }
+        };
+        Ok(Predicate::new(
+            op,
+            Box::new(Column::new(label).as_impl_ref().to_owned()),
+            Box::new(value.as_impl_ref().to_owned()),
+        )
+        .as_impl_ref()
+        .to_owned())
+    }
+
+    fn range(&mut self) -> Result<std::ops::Range<Instant>, QueryError> {
+        let start = self.tokens.time(self.now)?;
+        self.tokens.expect(Token::Lt)?;
+        self.tokens.keyword("time")?;
+        self.tokens.expect(Token::Lt)?;
+        let end = self.tokens.time(self.now)?;
+        // both bounds are exclusive
+        Ok(start + Duration::from_millis(1)..end)
+    }
+
+    fn projection(&mut self) -> Result<Projection, QueryError> {
+        if !matches!(self.tokens.peek(), Token::Ident(_)) {
+            return self.tokens.unexpected("aggregation or range function");
+        }
+        let (name, position) = self.tokens.ident()?;
+        if let Some(op) = aggregate_op(&name) {
+            self.tokens.expect(Token::LeftParen)?;
+            let (field, range) = match self.tokens.peek_nth(1) {
+                Some(Token::LeftParen) => {
+                    let (name, position) = self.tokens.ident()?;
+                    let op = range_op(&name).ok_or(QueryError::UnknownFunction { name, position })?;
+                    let (field, range) = self.range_arguments()?;
+                    (field, Some((op, range)))
+                }
+                _ => (self.tokens.ident()?.0, None),
+            };
+            self.tokens.expect(Token::RightParen)?;
+            let grouping = if self.tokens.is_keyword("BY") {
+                self.tokens.next();
+                Grouping::By(self.labels()?)
+            } else if self.tokens.is_keyword("WITHOUT") {
+                self.tokens.next();
+                Grouping::Without(self.labels()?)
+            } else {
+                Grouping::By(vec![])
+            };
+            return Ok(Projection {
+                aggregate: Some((op, grouping)),
+                range,
+                field,
+            });
+        }
+        if let Some(op) = range_op(&name) {
+            let (field, range) = self.range_arguments()?;
+            return Ok(Projection {
+                aggregate: None,
+                range: Some((op, range)),
+                field,
+            });
+        }
+        Err(QueryError::UnknownFunction { name, position })
+    }
+
+    /// `'(' field '[' duration ']' ')'` of a range function.
+    fn range_arguments(&mut self) -> Result<(String, Duration), QueryError> {
+        self.tokens.expect(Token::LeftParen)?;
+        let (field, _) = self.tokens.ident()?;
+        self.tokens.expect(Token::LeftBracket)?;
+        let range = self.tokens.duration()?;
+        self.tokens.expect(Token::RightBracket)?;
+        self.tokens.expect(Token::RightParen)?;
+        Ok((field, range))
+    }
+
+    fn labels(&mut self) -> Result<Vec<String>, QueryError> {
+        let parenthesized = self.tokens.consume(Token::LeftParen);
+        let mut labels = Vec::new();
+        if !(parenthesized && self.tokens.consume(Token::RightParen)) {
+            loop {
+                labels.push(self.tokens.ident()?.0);
+                if !self.tokens.consume(Token::Comma) {
+                    break;
+                }
+            }
+            if parenthesized {
+                self.tokens.expect(Token::RightParen)?;
+            }
+        }
+        Ok(labels)
+    }
+}
+
+pub(crate) fn aggregate_op(name: &str) -> Option<AggregateOp> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "sum" => AggregateOp::Sum,
+        "avg" => AggregateOp::Avg,
+        "min" => AggregateOp::Min,
+        "max" => AggregateOp::Max,
+        "count" => AggregateOp::Count,
+        "stddev" => AggregateOp::Stddev,
+        "stdvar" => AggregateOp::Stdvar,
+        _ => return None,
+    })
+}
+
+pub(crate) fn range_op(name: &str) -> Option<RangeOp> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "rate" => RangeOp::Rate,
+        "increase" => RangeOp::Increase,
+        "delta" => RangeOp::Delta,
+        "irate" => RangeOp::Irate,
+        "avg_over_time" => RangeOp::AvgOverTime,
+        "min_over_time" => RangeOp::MinOverTime,
+        "max_over_time" => RangeOp::MaxOverTime,
+        "sum_over_time" => RangeOp::SumOverTime,
+        "count_over_time" => RangeOp::CountOverTime,
+        "last_over_time" => RangeOp::LastOverTime,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::aggregate::{Aggregate, AggregateOp, Grouping};
+    use crate::expression::chain::Chain;
+    use crate::expression::filter::{BinaryOp, Filter, Predicate};
+    use crate::expression::matrix::Matrix;
+    use crate::expression::range::{RangeFunction, RangeOp};
+    use crate::expression::scan::Scanner;
+    use crate::expression::trim::Trim;
+    use crate::expression::{Expression, Literal};
+    use crate::query::error::QueryError;
+    use crate::query::lexer::{Position, Token};
+    use crate::query::parse;
+    use crate::query::testing::{boxed, catalog_list, matcher};
+
+    #[test]
+    fn test_parse() {
+        let catalog_list = catalog_list();
+        let now = Instant::from_millis(600_000);
+        let query = parse(
+            "FROM prometheus.metrics.http_requests_total\n\
+             WHERE job = \"api\" AND (instance =~ 'a|b' OR instance != \"c\")\n\
+             RANGE (now - 5m) < time < now\n\
+             SUM(value) BY job",
+            catalog_list.clone(),
+            now,
+        )
+        .unwrap();
+        assert_eq!(
+            query.resource,
+            Literal::new(String::from("prometheus.metrics.http_requests_total"))
+        );
+        let predicate = Predicate::new(
+            BinaryOp::And,
+            matcher(BinaryOp::ExactMatch, "job", "api"),
+            boxed(Predicate::new(
+                BinaryOp::Or,
+                matcher(BinaryOp::RegexMatch, "instance", "a|b"),
+                matcher(BinaryOp::ExactNotMatch, "instance", "c"),
+            )),
+        );
+        let aggregate = Aggregate::new(
+            AggregateOp::Sum,
+            String::from("value"),
+            Grouping::By(vec![String::from("job")]),
+        );
+        let range = Instant::from_millis(300_001)..now;
+        let scanner = Scanner::new(
+            catalog_list.clone(),
+            boxed(Filter::new(boxed(predicate), boxed(aggregate))),
+        )
+        .with_range(range.clone());
+        let expected = Chain::new(boxed(scanner)).then(Trim::new(range).as_impl_ref().to_owned());
+        assert!(query.plan == expected.as_impl_ref().to_owned());
+
+        let query = parse(
+            "from http_requests_total range 0 < time < 60000 sum(rate(value[5m])) without (instance)",
+            catalog_list.clone(),
+            now,
+        )
+        .unwrap();
+        let function = RangeFunction::new(RangeOp::Rate, String::from("value"), Duration::from_millis(300_000));
+        let scanner = Scanner::new(catalog_list.clone(), boxed(function))
+            .with_range(Instant::from_millis(-299_999)..Instant::from_millis(60_000));
+        let aggregate = Aggregate::new(
+            AggregateOp::Sum,
+            String::from("value"),
+            Grouping::Without(vec![String::from("instance")]),
+        );
+        let expected = Chain::new(boxed(scanner))
+            .then(aggregate.as_impl_ref().to_owned())
+            .then(
+                Trim::new(Instant::from_millis(1)..Instant::from_millis(60_000))
+                    .as_impl_ref()
+                    .to_owned(),
+            );
+        assert!(query.plan == expected.as_impl_ref().to_owned());
+
+        let query = parse("FROM t LAST_OVER_TIME(value[1m])", catalog_list.clone(), now).unwrap();
+        let function = RangeFunction::new(
+            RangeOp::LastOverTime,
+            String::from("value"),
+            Duration::from_millis(60_000),
+        );
+        assert!(query.plan == Scanner::new(catalog_list, boxed(function)).as_impl_ref().to_owned());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let catalog_list = catalog_list();
+        let now = Instant::from_millis(0);
+        let cases = [
+            ("SELECT value", 1, 1),
+            ("FROM t\nWHERE job = ", 2, 13),
+            ("FROM t WHERE job > \"x\" SUM(value)", 1, 18),
+            ("FROM t RANGE now - 5 < time < now SUM(value)", 1, 20),
+            ("FROM t\n  SUM(value) BY job extra", 2, 21),
+            ("FROM t SUM(value[5m])", 1, 17),
+        ];
+        for (query, line, column) in cases {
+            let error = parse(query, catalog_list.clone(), now).unwrap_err();
+            assert!(matches!(error, QueryError::UnexpectedToken { .. }), "{}", error);
+            assert_eq!(error.position(), Position { line, column }, "{}", error);
+        }
+        let error = parse("FROM t\n  MEDIAN(value)", catalog_list.clone(), now).unwrap_err();
+        assert!(matches!(error, QueryError::UnknownFunction { .. }));
+        assert_eq!(error.to_string(), "unknown function \"MEDIAN\" at 2:3");
+        let error = parse("FROM t SUM(value) BY", catalog_list, now).unwrap_err();
+        assert!(matches!(error, QueryError::UnexpectedToken { found: Token::Eof, .. }));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let query = parse(
+            "FROM prometheus.metrics.http_requests_total WHERE instance = \"a\" \
+             RANGE now - 5s < time < now SUM(INCREASE(value[4s])) BY job",
+            catalog_list(),
+            Instant::from_millis(10_000),
+        )
+        .unwrap();
+        let result = future::block_on(query.evaluate(&mut Context::new())).unwrap();
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        assert_eq!(matrix.series.len(), 2);
+        assert_eq!(
+            matrix.series[0].labels,
+            vec![(String::from("job"), String::from("api"))]
+        );
+        // a counter growing by one per second increases by 4 over 4s, only within 5s < time < 10s
+        assert_eq!(matrix.start_at, Instant::from_millis(6000));
+        assert_eq!(matrix.series[0].values, vec![Some(4.0); 4]);
+        assert_eq!(matrix.series[1].values, vec![Some(4.0); 4]);
+    }
+}
@@ -0,0 +1,771 @@
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::catalog::CatalogList;
+use crate::common::{Duration, Instant};
+use crate::expression::aggregate::{Aggregate, AggregateOp, Grouping};
+use crate::expression::binary::{Binary, BinaryOperator, Cardinality, Matching};
+use crate::expression::chain::Chain;
+use crate::expression::filter::{BinaryOp, Column, Filter, Predicate};
+use crate::expression::function::{Function, MathOp};
+use crate::expression::range::{RangeFunction, RangeOp};
+use crate::expression::scan::Scanner;
+use crate::expression::shift::TimeShift;
+use crate::expression::trim::Trim;
+use crate::expression::{ExprImpl, Expression, Literal, Primitive};
+
+use super::error::QueryError;
+use super::lexer::{Position, Token, TokenStream};
+use super::parser::{aggregate_op, range_op};
+
+/// Metrics are tables of the default schema, and their samples are this field.
+pub const VALUE_FIELD: &str = "value";
+
+/// How far back an instant vector selector looks for the latest sample.
+const LOOKBACK: Duration = Duration::from_millis(5 * 60 * 1000);
+
+#[derive(Debug, Clone)]
+struct Selector {
+    name: String,
+    matchers: Vec<(String, BinaryOp, String)>,
+    range: Option<Duration>,
+    offset: Duration,
+    at: Option<Instant>,
+    position: Position,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Number(f64, Position),
+    Selector(Selector),
+    Aggregate {
+        op: AggregateOp,
+        grouping: Grouping,
+        inner: Box<Node>,
+    },
+    Call {
+        name: String,
+        args: Vec<Node>,
+        position: Position,
+    },
+    Binary {
+        op: BinaryOperator,
+        return_bool: bool,
+        matching: Matching,
+        cardinality: Cardinality,
+        lhs: Box<Node>,
+        rhs: Box<Node>,
+    },
+}
+
+impl Node {
+    fn position(&self) -> Position {
+        match self {
+            Node::Number(_, position) | Node::Call { position, .. } => *position,
+            Node::Selector(selector) => selector.position,
+            Node::Aggregate { inner, .. } => inner.position(),
+            Node::Binary { lhs, .. } => lhs.position(),
+        }
+    }
+
+    fn is_scalar(&self) -> bool {
+        match self {
+            Node::Number(..) => true,
+            Node::Binary { lhs, rhs, .. } => lhs.is_scalar() && rhs.is_scalar(),
+            _ => false,
+        }
+    }
+}
+
+/// Parses a PromQL expression and compiles it into an [`ExprImpl`] evaluated without arguments,
+/// into a [`Matrix`](crate::expression::matrix::Matrix) with one slot per time interval of the metrics,
+/// or into a `Primitive<f64>` for scalar expressions. Metric names are resolved through `catalog_list`,
+/// `range` bounds the evaluated timestamps, both ends included, and is what `@ start()` and `@ end()` refer to.
+pub fn parse(input: &str, catalog_list: Arc<CatalogList>, range: Range<Instant>) -> Result<ExprImpl, QueryError> {
+    let mut parser = PromParser {
+        tokens: TokenStream::new(input)?,
+        range: range.clone(),
+    };
+    let node = parser.expression(0)?;
+    if *parser.tokens.peek() != Token::Eof {
+        return parser.tokens.unexpected("operator");
+    }
+    let scalar = node.is_scalar();
+    let trim = Trim::new(range.start..range.end + Duration::from_millis(1));
+    let plan = Compiler { catalog_list, range }.compile(node)?;
+    if scalar {
+        return Ok(plan);
+    }
+    // scans are widened by lookback windows and offsets, the result only covers `range`
+    Ok(Chain::append(plan, [trim.as_impl_ref().to_owned()]))
+}
+
+struct PromParser {
+    tokens: TokenStream,
+    range: Range<Instant>,
+}
+
+/// Binding power of binary operators, from `or` to `^`.
+fn binary_operator(token: &Token) -> Option<(BinaryOperator, u8)> {
+    Some(match token {
+        Token::Ident(ident) => match ident.to_ascii_lowercase().as_str() {
+            "or" => (BinaryOperator::Or, 1),
+            "and" => (BinaryOperator::And, 2),
+            "unless" => (BinaryOperator::Unless, 2),
+            "atan2" => (BinaryOperator::Atan2, 5),
+            _ => return None,
+        },
+        Token::EqEq => (BinaryOperator::Eq, 3),
+        Token::NotEq => (BinaryOperator::NotEq, 3),
+        Token::Gt => (BinaryOperator::Gt, 3),
+        Token::Lt => (BinaryOperator::Lt, 3),
+        Token::GtEq => (BinaryOperator::GtEq, 3),
+        Token::LtEq => (BinaryOperator::LtEq, 3),
+        Token::Plus => (BinaryOperator::Add, 4),
+        Token::Minus => (BinaryOperator::Sub, 4),
+        Token::Star => (BinaryOperator::Mul, 5),
+        Token::Slash => (BinaryOperator::Div, 5),
+        Token::Percent => (BinaryOperator::Mod, 5),
+        Token::Caret => (BinaryOperator::Pow, 6),
+        _ => return None,
+    })
+}
+
+const POW_PRECEDENCE: u8 = 6;
+
+impl PromParser {
+    /// Binary expressions of operators binding at least as tight as `precedence`.
+    fn expression(&mut self, precedence: u8) -> Result<Node, QueryError> {
+        let mut lhs = self.unary()?;
+        while let Some((op, op_precedence)) = binary_operator(self.tokens.peek()) {
+            if op_precedence < precedence {
+                break;
+            }
+            let position = self.tokens.next().1;
+            let return_bool = self.tokens.is_keyword("bool");
+            if return_bool {
+                if !op.is_comparison() {
+                    return Err(QueryError::InvalidExpression {
+                        reason: String::from("bool modifier on a non-comparison operator"),
+                        position,
+                    });
+                }
+                self.tokens.next();
+            }
+            let (matching, cardinality) = self.vector_matching(op)?;
+            // `^` is right associative, all others are left associative
+            let rhs = if op == BinaryOperator::Pow {
+                self.expression(op_precedence)?
+            } else {
+                self.expression(op_precedence + 1)?
+            };
+            lhs = Node::Binary {
+                op,
+                return_bool,
+                matching,
+                cardinality,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            };
+        }
+        Ok(lhs)
+    }
+
+    fn vector_matching(&mut self, op: BinaryOperator) -> Result<(Matching, Cardinality), QueryError> {
+        let matching = if self.tokens.is_keyword("on") {
+            self.tokens.next();
+            Matching::On(self.labels()?)
+        } else if self.tokens.is_keyword("ignoring") {
+            self.tokens.next();
+            Matching::Ignoring(self.labels()?)
+        } else {
+            return Ok((Matching::Ignoring(vec![]), Cardinality::OneToOne));
+        };
+        let position = self.tokens.position();
+        let cardinality = if self.tokens.is_keyword("group_left") {
+            self.tokens.next();
+            Cardinality::ManyToOne(self.optional_labels()?)
+        } else if self.tokens.is_keyword("group_right") {
+            self.tokens.next();
+            Cardinality::OneToMany(self.optional_labels()?)
+        } else {
+            Cardinality::OneToOne
+        };
+        if op.is_set() && cardinality != Cardinality::OneToOne {
+            return Err(QueryError::InvalidExpression {
+                reason: String::from("no grouping allowed for set operators"),
+                position,
+            });
+        }
+        Ok((matching, cardinality))
+    }
+
+    fn unary(&mut self) -> Result<Node, QueryError> {
+        match self.tokens.peek() {
+            Token::Minus => {
+                let position = self.tokens.next().1;
+                // only `^` binds tighter than unary minus
+                let inner = self.expression(POW_PRECEDENCE)?;
+                Ok(match inner {
+                    Node::Number(number, _) => Node::Number(-number, position),
+                    inner => Node::Binary {
+                        op: BinaryOperator::Mul,
+                        return_bool: false,
+                        matching: Matching::Ignoring(vec![]),
+                        cardinality: Cardinality::OneToOne,
+                        lhs: Box::new(Node::Number(-1.0, position)),
+                        rhs: Box::new(inner),
+                    },
+                })
+            }
+            Token::Plus => {
+                self.tokens.next();
+                self.expression(POW_PRECEDENCE)
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    /// A primary expression with its range and `offset`/`@` modifiers.
+    fn postfix(&mut self) -> Result<Node, QueryError> {
+        let mut node = self.primary()?;
+        loop {
+            let position = self.tokens.position();
+            let selector = match &mut node {
+                Node::Selector(selector) => Some(selector),
+                _ => None,
+            };
+            let modifier = match self.tokens.peek() {
+                Token::LeftBracket => "range",
+                Token::At => "@",
+                _ if self.tokens.is_keyword("offset") => "offset",
+                _ => return Ok(node),
+            };
+            let selector = selector.ok_or_else(|| QueryError::InvalidExpression {
+                reason: format!("{} is only allowed on vector selectors", modifier),
+                position,
+            })?;
+            self.tokens.next();
+            match modifier {
+                "range" if selector.range.is_none() => {
+                    selector.range = Some(self.tokens.duration()?);
+                    self.tokens.expect(Token::RightBracket)?;
+                }
+                "offset" => {
+                    let negative = self.tokens.consume(Token::Minus);
+                    let offset = self.tokens.duration()?;
+                    selector.offset = if negative {
+                        Duration::from_millis(-offset.as_millis())
+                    } else {
+                        offset
+                    };
+                }
+                "@" => selector.at = Some(self.at()?),
+                _ => return self.tokens.unexpected("operator"),
+            }
+        }
+    }
+
+    /// Evaluation time of `@`, in seconds or as `start()` or `end()`.
+    fn at(&mut self) -> Result<Instant, QueryError> {
+        match self.tokens.next() {
+            (Token::Integer(seconds), _) => Ok(Instant::from_millis(seconds * 1000)),
+            (Token::Float(seconds), _) => Ok(Instant::from_millis((seconds * 1000.0) as i64)),
+            (Token::Ident(ident), _) if ident == "start" || ident == "end" => {
+                self.tokens.expect(Token::LeftParen)?;
+                self.tokens.expect(Token::RightParen)?;
+                Ok(if ident == "start" {
+                    self.range.start
+                } else {
+                    self.range.end
+                })
+            }
+            (found, position) => Err(QueryError::UnexpectedToken {
+                expected: String::from("timestamp"),
+                found,
+                position,
+            }),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, QueryError> {
+        let position = self.tokens.position();
+        match self.tokens.peek().clone() {
+            Token::Integer(integer) => {
+                self.tokens.next();
+                Ok(Node::Number(integer as f64, position))
+            }
+            Token::Float(float) => {
+                self.tokens.next();
+                Ok(Node::Number(float, position))
+            }
+            Token::LeftParen => {
+                self.tokens.next();
+                let node = self.expression(0)?;
+                self.tokens.expect(Token::RightParen)?;
+                Ok(node)
+            }
+            Token::LeftBrace => self.selector(None, position),
+            Token::Ident(ident) => {
+                self.tokens.next();
+                if ident.eq_ignore_ascii_case("inf") {
+                    return Ok(Node::Number(f64::INFINITY, position));
+                }
+                if ident.eq_ignore_ascii_case("nan") {
+                    return Ok(Node::Number(f64::NAN, position));
+                }
+                if let Some(op) = aggregate_op(&ident) {
+                    if matches!(self.tokens.peek(), Token::LeftParen)
+                        || self.tokens.is_keyword("by")
+                        || self.tokens.is_keyword("without")
+                    {
+                        return self.aggregation(op);
+                    }
+                }
+                if *self.tokens.peek() == Token::LeftParen {
+                    self.tokens.next();
+                    let mut args = Vec::new();
+                    while *self.tokens.peek() != Token::RightParen {
+                        args.push(self.expression(0)?);
+                        if !self.tokens.consume(Token::Comma) {
+                            break;
+                        }
+                    }
+                    self.tokens.expect(Token::RightParen)?;
+                    return Ok(Node::Call {
+                        name: ident,
+                        args,
+                        position,
+                    });
+                }
+                self.selector(Some(ident), position)
+            }
+            _ => self.tokens.unexpected("expression"),
+        }
+    }
+
+    fn aggregation(&mut self, op: AggregateOp) -> Result<Node, QueryError> {
+        let mut grouping = self.grouping()?;
+        self.tokens.expect(Token::LeftParen)?;
+        let inner = self.expression(0)?;
+        self.tokens.expect(Token::RightParen)?;
+        if grouping.is_none() {
+            grouping = self.grouping()?;
+        }
+        Ok(Node::Aggregate {
+            op,
+            grouping: grouping.unwrap_or(Grouping::By(vec![])),
+            inner: Box::new(inner),
+        })
+    }
+
+    fn grouping(&mut self) -> Result<Option<Grouping>, QueryError> {
+        if self.tokens.is_keyword("by") {
+            self.tokens.next();
+            return Ok(Some(Grouping::By(self.labels()?)));
+        }
+        if self.tokens.is_keyword("without") {
+            self.tokens.next();
+            return Ok(Some(Grouping::Without(self.labels()?)));
+        }
+        Ok(None)
+    }
+
+    /// `name{label="value", ...}`, the name may instead be given by a `__name__` matcher.
+    fn selector(&mut self, mut name: Option<String>, position: Position) -> Result<Node, QueryError> {
+        let mut matchers = Vec::new();
+        if self.tokens.consume(Token::LeftBrace) {
+            while *self.tokens.peek() != Token::RightBrace {
+                let (label, label_position) = self.tokens.ident()?;
+                let op = match self.tokens.next() {
+                    (Token::Eq, _) => BinaryOp::ExactMatch,
+                    (Token::NotEq, _) => BinaryOp::ExactNotMatch,
+                    (Token::RegexMatch, _) => BinaryOp::RegexMatch,
+                    (Token::RegexNotMatch, _) => BinaryOp::RegexNotMatch,
+                    (found, position) => {
+                        return Err(QueryError::UnexpectedToken {
+                            expected: String::from("matcher"),
+                            found,
+                            position,
+                        })
+                    }
+                };
+                let value = match self.tokens.next() {
+                    (Token::String(value), _) => value,
+                    (found, position) => {
+                        return Err(QueryError::UnexpectedToken {
+                            expected: String::from("string"),
+                            found,
+                            position,
+                        })
+                    }
+                };
+                if label == "__name__" {
+                    if op != BinaryOp::ExactMatch || name.is_some() {
+                        return Err(QueryError::InvalidExpression {
+                            reason: String::from("metric name must be given once, by an exact match"),
+                            position: label_position,
+                        });
+                    }
+                    name = Some(value);
+                } else {
+                    matchers.push((label, op, value));
+                }
+                if !self.tokens.consume(Token::Comma) {
+                    break;
+                }
+            }
+            self.tokens.expect(Token::RightBrace)?;
+        }
+        let name = name.ok_or_else(|| QueryError::InvalidExpression {
+            reason: String::from("vector selector must have a metric name"),
+            position,
+        })?;
+        Ok(Node::Selector(Selector {
+            name,
+            matchers,
+            range: None,
+            offset: Duration::from_millis(0),
+            at: None,
+            position,
+        }))
+    }
+
+    fn labels(&mut self) -> Result<Vec<String>, QueryError> {
+        self.tokens.expect(Token::LeftParen)?;
+        let mut labels = Vec::new();
+        while *self.tokens.peek() != Token::RightParen {
+            labels.push(self.tokens.ident()?.0);
+            if !self.tokens.consume(Token::Comma) {
+                break;
+            }
+        }
+        self.tokens.expect(Token::RightParen)?;
+        Ok(labels)
+    }
+
+    fn optional_labels(&mut self) -> Result<Vec<String>, QueryError> {
+        if *self.tokens.peek() == Token::LeftParen {
+            return self.labels();
+        }
+        Ok(vec![])
+    }
+}
+
+struct Compiler {
+    catalog_list: Arc<CatalogList>,
+    range: Range<Instant>,
+}
+
+impl Compiler {
+    fn compile(&self, node: Node) -> Result<ExprImpl, QueryError> {
+        match node {
+            Node::Number(number, _) => Ok(Primitive::new(number).as_impl_ref().to_owned()),
+            Node::Selector(selector) => match selector.range {
+                Some(_) => Err(QueryError::InvalidExpression {
+                    reason: String::from("range vector must be passed to a range function"),
+                    position: selector.position,
+                }),
+                None => self.compile_selector(selector, RangeOp::LastOverTime, LOOKBACK),
+            },
+            Node::Aggregate { op, grouping, inner } => {
+                let aggregate = Aggregate::new(op, String::from(VALUE_FIELD), grouping);
+                self.compile_then(*inner, aggregate.as_impl_ref().to_owned())
+            }
+            Node::Call { name, args, position } => self.compile_call(name, args, position),
+            Node::Binary {
+                op,
+                return_bool,
+                matching,
+                cardinality,
+                lhs,
+                rhs,
+            } => {
+                let mut binary = Binary::new(op, Box::new(self.compile(*lhs)?), Box::new(self.compile(*rhs)?))
+                    .with_matching(matching, cardinality);
+                if return_bool {
+                    binary = binary.with_bool();
+                }
+                Ok(binary.as_impl_ref().to_owned())
+            }
+        }
+    }
+
+    /// Compiles the instant vector `node`, and passes its result to `stage`.
+    fn compile_then(&self, node: Node, stage: ExprImpl) -> Result<ExprImpl, QueryError> {
+        if let Node::Number(_, position) = node {
+            return Err(QueryError::InvalidExpression {
+                reason: String::from("expected instant vector, found scalar"),
+                position,
+            });
+        }
+        Ok(Chain::append(self.compile(node)?, [stage]))
+    }
+
+    fn compile_call(&self, name: String, mut args: Vec<Node>, position: Position) -> Result<ExprImpl, QueryError> {
+        let invalid = |reason: &str| QueryError::InvalidExpression {
+            reason: format!("{}: {}", name, reason),
+            position,
+        };
+        let scalar = |node: Option<&Node>, default: Option<f64>| match node {
+            Some(Node::Number(number, _)) => Ok(*number),
+            None => default.ok_or_else(|| invalid("missing scalar argument")),
+            Some(node) => Err(QueryError::InvalidExpression {
+                reason: String::from("expected number literal"),
+                position: node.position(),
+            }),
+        };
+        if let Some(op) = range_op(&name) {
+            return match (args.pop(), args.is_empty()) {
+                (Some(Node::Selector(selector)), true) if selector.range.is_some() => {
+                    let range = selector.range.unwrap();
+                    self.compile_selector(selector, op, range)
+                }
+                _ => Err(invalid("expected one range vector argument")),
+            };
+        }
+        let (op, arity) = match name.to_ascii_lowercase().as_str() {
+            "abs" => (MathOp::Abs, 1..=1),
+            "ceil" => (MathOp::Ceil, 1..=1),
+            "floor" => (MathOp::Floor, 1..=1),
+            "exp" => (MathOp::Exp, 1..=1),
+            "ln" => (MathOp::Ln, 1..=1),
+            "log2" => (MathOp::Log2, 1..=1),
+            "log10" => (MathOp::Log10, 1..=1),
+            "sqrt" => (MathOp::Sqrt, 1..=1),
+            "sgn" => (MathOp::Sgn, 1..=1),
+            "round" => (MathOp::Round(scalar(args.get(1), Some(1.0))?), 1..=2),
+            "clamp_min" => (MathOp::ClampMin(scalar(args.get(1), None)?), 2..=2),
+            "clamp_max" => (MathOp::ClampMax(scalar(args.get(1), None)?), 2..=2),
+            _ => return Err(QueryError::UnknownFunction { name, position }),
+        };
+        if !arity.contains(&args.len()) {
+            return Err(invalid("wrong number of arguments"));
+        }
+        self.compile_then(args.swap_remove(0), Function::new(op).as_impl_ref().to_owned())
+    }
+
+    /// Scans the metric from `window` before the first evaluated timestamp, and evaluates `op`
+    /// over the samples matching the selector.
+    fn compile_selector(&self, selector: Selector, op: RangeOp, window: Duration) -> Result<ExprImpl, QueryError> {
+        let schema = self.catalog_list.get_default().get_default();
+        if schema.get(&selector.name).is_none() {
+            return Err(QueryError::UnknownMetric {
+                name: selector.name,
+                position: selector.position,
+            });
+        }
+        let shift = if selector.offset.as_millis() != 0 || selector.at.is_some() {
+            let shift = TimeShift::new(selector.offset);
+            let shift = match selector.at {
+                Some(at) => shift.with_at(at, self.range.clone()),
+                None => shift,
+            };
+            Some(shift.as_impl_ref().to_owned())
+        } else {
+            None
+        };
+        let mut plan = RangeFunction::new(op, String::from(VALUE_FIELD), window)
+            .as_impl_ref()
+            .to_owned();
+        let predicate = selector
+            .matchers
+            .into_iter()
+            .map(|(label, op, value)| {
+                Predicate::new(
+                    op,
+                    Box::new(Column::new(label).as_impl_ref().to_owned()),
+                    Box::new(value.as_impl_ref().to_owned()),
+                )
+                .as_impl_ref()
+                .to_owned()
+            })
+            .reduce(|lhs, rhs| {
+                Predicate::new(BinaryOp::And, Box::new(lhs), Box::new(rhs))
+                    .as_impl_ref()
+                    .to_owned()
+            });
+        if let Some(predicate) = predicate {
+            plan = Filter::new(Box::new(predicate), Box::new(plan))
+                .as_impl_ref()
+                .to_owned();
+        }
+        let evaluated = match selector.at {
+            Some(at) => at..at,
+            None => self.range.clone(),
+        };
+        let start = evaluated.start - selector.offset - window;
+        let end = evaluated.end - selector.offset + Duration::from_millis(1);
+        let scanner = Scanner::new(self.catalog_list.clone(), Box::new(plan))
+            .with_resource(Literal::new(selector.name))
+            .with_range(start..end);
+        Ok(Chain::append(scanner.as_impl_ref().to_owned(), shift))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures_lite::future;
+
+    use crate::catalog::CatalogList;
+    use crate::common::{Duration, Instant};
+    use crate::context::Context;
+    use crate::expression::aggregate::{Aggregate, AggregateOp, Grouping};
+    use crate::expression::chain::Chain;
+    use crate::expression::filter::{BinaryOp, Filter, Predicate};
+    use crate::expression::matrix::Matrix;
+    use crate::expression::range::{RangeFunction, RangeOp};
+    use crate::expression::scan::Scanner;
+    use crate::expression::trim::Trim;
+    use crate::expression::{ExprImpl, Expression, Literal, Primitive};
+    use crate::query::error::QueryError;
+    use crate::query::lexer::Position;
+    use crate::query::testing::{catalog_list, matcher};
+
+    use super::parse;
+
+    fn seconds(seconds: i64) -> Instant {
+        Instant::from_millis(seconds * 1000)
+    }
+
+    fn evaluate(query: &str, catalog_list: &Arc<CatalogList>) -> ExprImpl {
+        let expr = parse(query, catalog_list.clone(), seconds(300)..seconds(300)).unwrap();
+        future::block_on(expr.evaluate(&mut Context::new(), &[])).unwrap()
+    }
+
+    /// Values of every series at `at`.
+    fn values_at(result: &ExprImpl, at: Instant) -> Vec<Option<f64>> {
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        matrix.series.iter().map(|series| matrix.value_at(series, at)).collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let catalog_list = catalog_list();
+        let expr = parse(
+            r#"sum by (job) (rate(http_requests_total{job="api", instance!~"b|c"}[5m]))"#,
+            catalog_list.clone(),
+            seconds(300)..seconds(600),
+        )
+        .unwrap();
+        let aggregate = Aggregate::new(
+            AggregateOp::Sum,
+            String::from("value"),
+            Grouping::By(vec![String::from("job")]),
+        );
+        let function = RangeFunction::new(RangeOp::Rate, String::from("value"), Duration::SECOND * 300i64);
+        let predicate = Predicate::new(
+            BinaryOp::And,
+            matcher(BinaryOp::ExactMatch, "job", "api"),
+            matcher(BinaryOp::RegexNotMatch, "instance", "b|c"),
+        );
+        let filter = Filter::new(
+            Box::new(predicate.as_impl_ref().to_owned()),
+            Box::new(function.as_impl_ref().to_owned()),
+        );
+        let scanner = Scanner::new(catalog_list.clone(), Box::new(filter.as_impl_ref().to_owned()))
+            .with_resource(Literal::new(String::from("http_requests_total")))
+            .with_range(seconds(0)..seconds(600) + Duration::from_millis(1));
+        let trim = Trim::new(seconds(300)..seconds(600) + Duration::from_millis(1));
+        let expected = Chain::new(Box::new(scanner.as_impl_ref().to_owned()))
+            .then(aggregate.as_impl_ref().to_owned())
+            .then(trim.as_impl_ref().to_owned());
+        assert!(expr == expected.as_impl_ref().to_owned());
+
+        // same selector written with `__name__` and the grouping after the argument
+        let expr = parse(
+            r#"sum(rate({__name__="http_requests_total", job="api", instance!~"b|c"}[5m])) by (job)"#,
+            catalog_list.clone(),
+            seconds(300)..seconds(600),
+        )
+        .unwrap();
+        assert!(expr == expected.as_impl_ref().to_owned());
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let catalog_list = catalog_list();
+        let at = seconds(300);
+
+        let result = evaluate("sum by (job) (rate(http_requests_total[1m]))", &catalog_list);
+        assert_eq!(values_at(&result, at), vec![Some(2.0), Some(1.0)]);
+
+        // only the evaluated timestamp is left of the scanned lookback window
+        let result = evaluate("http_requests_total", &catalog_list);
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        assert!(matrix.start_at == at);
+        assert_eq!(matrix.series.len(), 3);
+        assert!(matrix.series.iter().all(|series| series.values == vec![Some(300.0)]));
+
+        // `-` binds looser than `^`, which is right associative
+        let result = evaluate("-2 ^ 3 ^ 2 + 1", &catalog_list);
+        assert_eq!(
+            result.as_any().downcast_ref::<Primitive<f64>>().unwrap().value(),
+            &-511.0
+        );
+
+        let result = evaluate(r#"http_requests_total{job="api"} * 2 > bool 500"#, &catalog_list);
+        assert_eq!(values_at(&result, at), vec![Some(1.0), Some(1.0)]);
+
+        let result = evaluate(
+            r#"http_requests_total / on (job) group_left sum by (job) (http_requests_total)"#,
+            &catalog_list,
+        );
+        assert_eq!(values_at(&result, at), vec![Some(0.5), Some(1.0), Some(0.5)]);
+
+        let result = evaluate(
+            r#"http_requests_total{instance="a"} unless on (job) http_requests_total{instance="b"}"#,
+            &catalog_list,
+        );
+        assert_eq!(values_at(&result, at), vec![Some(300.0)]);
+
+        let result = evaluate("clamp_max(abs(-http_requests_total{job=\"node\"}), 100)", &catalog_list);
+        assert_eq!(values_at(&result, at), vec![Some(100.0)]);
+
+        let result = evaluate(r#"http_requests_total{job="node"} offset 1m"#, &catalog_list);
+        assert_eq!(values_at(&result, at), vec![Some(240.0)]);
+
+        let result = evaluate(r#"http_requests_total{job="node"} @ 100"#, &catalog_list);
+        let matrix = result.as_any().downcast_ref::<Matrix>().unwrap();
+        assert!(matrix.start_at == at);
+        assert_eq!(matrix.series[0].values, vec![Some(100.0)]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let catalog_list = catalog_list();
+        let cases = [
+            ("rate(http_requests_total)", 1, 1),
+            ("http_requests_total[5m]", 1, 1),
+            ("sum(1)", 1, 5),
+            ("(1 + 1)[5m]", 1, 8),
+            ("http_requests_total + bool 1", 1, 21),
+            ("{job=\"api\"}", 1, 1),
+        ];
+        for (query, line, column) in cases {
+            let error = parse(query, catalog_list.clone(), seconds(0)..seconds(0)).unwrap_err();
+            assert!(matches!(error, QueryError::InvalidExpression { .. }), "{}", error);
+            assert_eq!(error.position(), Position { line, column }, "{}", error);
+        }
+        let error = parse("up\n  or down", catalog_list.clone(), seconds(0)..seconds(0)).unwrap_err();
+        assert!(matches!(error, QueryError::UnknownMetric { .. }));
+        assert_eq!(error.position(), Position { line: 1, column: 1 });
+        let error = parse(
+            "http_requests_total{job=}",
+            catalog_list.clone(),
+            seconds(0)..seconds(0),
+        )
+        .unwrap_err();
+        assert!(matches!(error, QueryError::UnexpectedToken { .. }));
+        assert_eq!(error.position(), Position { line: 1, column: 25 });
+        let error = parse(
+            "histogram_quantile(0.9, http_requests_total)",
+            catalog_list,
+            seconds(0)..seconds(0),
+        )
+        .unwrap_err();
+        assert!(matches!(error, QueryError::UnknownFunction { .. }));
+    }
+}
@@ -0,0 +1,621 @@
+use std::sync::Arc;
+
+use crate::catalog::CatalogList;
+use crate::common::{Duration, Instant};
+use crate::expression::aggregate::{Aggregate, AggregateOp, Grouping};
+use crate::expression::chain::Chain;
+use crate::expression::filter::{BinaryOp, Column, Filter, Predicate};
+use crate::expression::project::{Project, Projection};
+use crate::expression::scan::{self, Scanner};
+use crate::expression::{ExprImpl, Expression, Literal};
+use crate::source::TableMeta;
+
+use super::error::QueryError;
+use super::lexer::{Position, Token, TokenStream};
+use super::parser::aggregate_op;
+
+/// Recursive descent parser of
+///
+/// ```text
+/// query      := SELECT items FROM resource [WHERE condition] [GROUP BY ident {',' ident}]
+/// items      := '*' | item {',' item}
+/// item       := (ident | aggregate '(' ident ')') [AS ident]
+/// resource   := ident ['.' ident ['.' ident]]
+/// condition  := conjunction {OR conjunction}
+/// conjunction:= predicate {AND predicate}
+/// predicate  := '(' condition ')' | bound | ident (('=' | '!=' | '<>') string | [NOT] LIKE string
+///             | [NOT] IN '(' string {',' string} ')')
+/// bound      := TIME ('<' | '<=' | '>' | '>=') time | time ('<' | '<=') TIME [('<' | '<=') time]
+/// ```
+///
+/// Label columns select label values, `time` the timestamp, and field columns the samples of
+/// every series, one row per timestamp. Time bounds may only be joined by `AND` at the top level,
+/// parentheses included, they restrict the scan instead of filtering series.
+#[derive(Debug)]
+pub struct SqlParser {
+    tokens: TokenStream,
+    catalog_list: Arc<CatalogList>,
+    now: Instant,
+    /// Inclusive lower and exclusive upper time bound.
+    bounds: (Option<Instant>, Option<Instant>),
+    /// Where the first time bound is, to report bounds joined by `OR`.
+    bounded_at: Option<Position>,
+}
+
+enum Item {
+    Star,
+    Column(String, Position),
+    /// Operator, function name as written, and field.
+    Aggregate(AggregateOp, String, String, Position),
+}
+
+impl SqlParser {
+    pub fn new(input: &str, catalog_list: Arc<CatalogList>, now: Instant) -> Result<Self, QueryError> {
+        Ok(Self {
+            tokens: TokenStream::new(input)?,
+            catalog_list,
+            now,
+            bounds: (None, None),
+            bounded_at: None,
+        })
+    }
+
+    pub fn parse(mut self) -> Result<ExprImpl, QueryError> {
+        self.tokens.keyword("SELECT")?;
+        let items = self.items()?;
+        self.tokens.keyword("FROM")?;
+        let position = self.tokens.position();
+        let resource = self.resource()?;
+        let table = scan::resolve(&self.catalog_list, &resource).map_err(|_| QueryError::UnknownTable {
+            name: resource.as_ref().to_owned(),
+            position,
+        })?;
+        let meta = table.meta();
+        let predicate = if self.tokens.is_keyword("WHERE") {
+            self.tokens.next();
+            self.condition(meta, true)?
+        } else {
+            None
+        };
+        let group_by = if self.tokens.is_keyword("GROUP") {
+            let position = self.tokens.position();
+            self.tokens.next();
+            self.tokens.keyword("BY")?;
+            let mut labels = Vec::new();
+            loop {
+                labels.push(self.label(meta)?);
+                if !self.tokens.consume(Token::Comma) {
+                    break;
+                }
+            }
+            Some((labels, position))
+        } else {
+            None
+        };
+        if *self.tokens.peek() != Token::Eof {
+            return self.tokens.unexpected(&Token::Eof.to_string());
+        }
+
+        let (columns, aggregate) = projections(items, meta, group_by.as_ref().map(|(labels, _)| labels))?;
+        let range = match self.bounds {
+            (None, None) => None,
+            (start, end) => {
+                Some(start.unwrap_or(Instant::from_millis(i64::MIN))..end.unwrap_or(Instant::from_millis(i64::MAX)))
+            }
+        };
+        let mut project = Project::new(columns);
+        if let Some(range) = &range {
+            project = project.with_range(range.clone());
+        }
+        let project = project.as_impl_ref().to_owned();
+        // an aggregate consumes the chunks and is projected afterwards, a projection alone consumes them
+        let (mut plan, then) = match (aggregate, group_by) {
+            (Some((op, field)), group_by) => {
+                let labels = group_by.map_or_else(Vec::new, |(labels, _)| labels);
+                let aggregate = Aggregate::new(op, field, Grouping::By(labels));
+                (aggregate.as_impl_ref().to_owned(), Some(project))
+            }
+            (None, Some((_, position))) => {
+                return Err(QueryError::InvalidExpression {
+                    reason: String::from("GROUP BY needs an aggregate"),
+                    position,
+                })
+            }
+            (None, None) => (project, None),
+        };
+        if let Some(predicate) = predicate {
+            plan = Filter::new(Box::new(predicate), Box::new(plan))
+                .as_impl_ref()
+                .to_owned();
+        }
+        let mut scanner = Scanner::new(self.catalog_list, Box::new(plan)).with_resource(resource);
+        if let Some(range) = range {
+            scanner = scanner.with_range(range);
+        }
+        Ok(Chain::append(scanner.as_impl_ref().to_owned(), then))
+    }
+
+    fn items(&mut self) -> Result<Vec<(Item, Option<String>)>, QueryError> {
+        let mut items = Vec::new();
+        if self.tokens.consume(Token::Star) {
+            items.push((Item::Star, None));
+            return Ok(items);
+        }
+        loop {
+            let (name, position) = self.tokens.ident()?;
+            let item = match aggregate_op(&name) {
+                Some(op) if self.tokens.consume(Token::LeftParen) => {
+                    let (field, _) = self.tokens.ident()?;
+                    self.tokens.expect(Token::RightParen)?;
+                    Item::Aggregate(op, name, field, position)
+                }
+                _ => Item::Column(name, position),
+            };
+            let alias = if self.tokens.is_keyword("AS") {
+                self.tokens.next();
+                Some(self.tokens.ident()?.0)
+            } else {
+                None
+            };
+            items.push((item, alias));
+            if !self.tokens.consume(Token::Comma) {
+                return Ok(items);
+            }
+        }
+    }
+
+    fn resource(&mut self) -> Result<Literal, QueryError> {
+        let (mut resource, _) = self.tokens.ident()?;
+        for _ in 0..2 {
+            if !self.tokens.consume(Token::Dot) {
+                break;
+            }
+            resource.push('.');
+            resource.push_str(&self.tokens.ident()?.0);
+        }
+        Ok(Literal::new(resource))
+    }
+
+    /// A label column of `meta`.
+    fn label(&mut self, meta: &TableMeta) -> Result<String, QueryError> {
+        let (name, position) = self.tokens.ident()?;
+        if !meta.labels.iter().any(|label| label.name == name) {
+            return Err(QueryError::UnknownColumn { name, position });
+        }
+        Ok(name)
+    }
+
+    /// `None` if the condition only consists of time bounds.
+    fn condition(&mut self, meta: &TableMeta, top: bool) -> Result<Option<ExprImpl>, QueryError> {
+        // only bounds of this condition can't be joined by `OR`
+        let outer = self.bounded_at.take();
+        let mut lhs = self.conjunction(meta, top)?;
+        while self.tokens.is_keyword("OR") {
+            if let Some(position) = self.bounded_at.filter(|_| top) {
+                return Err(QueryError::InvalidExpression {
+                    reason: String::from("time bounds can only be joined by AND"),
+                    position,
+                });
+            }
+            self.tokens.next();
+            let rhs = self.conjunction(meta, false)?;
+            lhs = join(BinaryOp::Or, lhs, rhs);
+        }
+        self.bounded_at = outer.or(self.bounded_at);
+        Ok(lhs)
+    }
+
+    fn conjunction(&mut self, meta: &TableMeta, top: bool) -> Result<Option<ExprImpl>, QueryError> {
+        let mut lhs = self.predicate(meta, top)?;
+        while self.tokens.is_keyword("AND") {
+            self.tokens.next();
+            let rhs = self.predicate(meta, top)?;
+            lhs = join(BinaryOp::And, lhs, rhs);
+        }
+        Ok(lhs)
+    }
+
+    fn predicate(&mut self, meta: &TableMeta, top: bool) -> Result<Option<ExprImpl>, QueryError> {
+        if self.tokens.is_keyword("time")
+            || self.is_time(0)
+            || (*self.tokens.peek() == Token::LeftParen && self.is_time(1))
+        {
+            self.bound(top)?;
+            return Ok(None);
+        }
+        if self.tokens.consume(Token::LeftParen) {
+            let condition = self.condition(meta, top)?;
+            self.tokens.expect(Token::RightParen)?;
+            return Ok(condition);
+        }
+        let label = self.label(meta)?;
+        let negated = self.tokens.is_keyword("NOT");
+        if negated {
+            self.tokens.next();
+            if !self.tokens.is_keyword("LIKE") && !self.tokens.is_keyword("IN") {
+                return self.tokens.unexpected("LIKE or IN");
+            }
+        }
+        let (op, value) = match self.tokens.peek() {
+            Token::Eq => {
+                self.tokens.next();
+                (BinaryOp::ExactMatch, self.string()?)
+            }
+            Token::NotEq => {
+                self.tokens.next();
+                (BinaryOp::ExactNotMatch, self.string()?)
+            }
+            Token::Lt if self.tokens.peek_nth(1) == Some(&Token::Gt) => {
+                self.tokens.next();
+                self.tokens.next();
+                (BinaryOp::ExactNotMatch, self.string()?)
+            }
+            _ if self.tokens.is_keyword("LIKE") => {
+                self.tokens.next();
+                let op = if negated {
+                    BinaryOp::RegexNotMatch
+                } else {
+                    BinaryOp::RegexMatch
+                };
+                (op, like_to_regex(&self.string()?))
+            }
+            _ if self.tokens.is_keyword("IN") => {
+                self.tokens.next();
+                self.tokens.expect(Token::LeftParen)?;
+                let mut values = vec![regex::escape(&self.string()?)];
+                while self.tokens.consume(Token::Comma) {
+                    values.push(regex::escape(&self.string()?));
+                }
+                self.tokens.expect(Token::RightParen)?;
+                let op = if negated {
+                    BinaryOp::RegexNotMatch
+                } else {
+                    BinaryOp::RegexMatch
+                };
+                (op, values.join("|"))
+            }
+            _ => return self.tokens.unexpected("comparison"),
+        };
+        Ok(Some(
+            Predicate::new(
+                op,
+                Box::new(Column::new(label).as_impl_ref().to_owned()),
+                Box::new(value.as_impl_ref().to_owned()),
+            )
+            .as_impl_ref()
+            .to_owned(),
+        ))
+    }
+
+    /// Whether the `n`th token starts a time.
+    fn is_time(&self, n: usize) -> bool {
+        match self.tokens.peek_nth(n) {
+            Some(Token::Integer(_)) => true,
+            Some(Token::Ident(ident)) => ident.eq_ignore_ascii_case("now"),
+            _ => false,
+        }
+    }
+
+    fn bound(&mut self, top: bool) -> Result<(), QueryError> {
+        let position = self.tokens.position();
+        if !top {
+            return Err(QueryError::InvalidExpression {
+                reason: String::from("time bounds can only be joined by AND"),
+                position,
+            });
+        }
+        self.bounded_at.get_or_insert(position);
+        if self.tokens.is_keyword("time") {
+            self.tokens.next();
+            if !matches!(self.tokens.peek(), Token::Lt | Token::LtEq | Token::Gt | Token::GtEq) {
+                return self.tokens.unexpected("time comparison");
+            }
+            let (op, _) = self.tokens.next();
+            let time = self.tokens.time(self.now)?;
+            self.restrict(&op, time);
+            return Ok(());
+        }
+        let time = self.tokens.time(self.now)?;
+        // `t < time` is `time > t`
+        match self.tokens.peek() {
+            Token::Lt => self.restrict(&Token::Gt, time),
+            Token::LtEq => self.restrict(&Token::GtEq, time),
+            _ => return self.tokens.unexpected("'<' or '<='"),
+        }
+        self.tokens.next();
+        self.tokens.keyword("time")?;
+        if matches!(self.tokens.peek(), Token::Lt | Token::LtEq) {
+            let (op, _) = self.tokens.next();
+            let time = self.tokens.time(self.now)?;
+            self.restrict(&op, time);
+        }
+        Ok(())
+    }
+
+    /// Narrows the bounds by `time op bound`.
+    fn restrict(&mut self, op: &Token, bound: Instant) {
+        let (start, end) = &mut self.bounds;
+        let (bound, lower) = match op {
+            Token::Gt => (bound + Duration::from_millis(1), true),
+            Token::GtEq => (bound, true),
+            Token::Lt => (bound, false),
+            _ => (bound + Duration::from_millis(1), false),
+        };
+        if lower {
+            *start = Some(start.map_or(bound, |start| {
+                Instant::from_millis(start.as_millis().max(bound.as_millis()))
+            }));
+        } else {
+            *end = Some(end.map_or(bound, |end| {
+                Instant::from_millis(end.as_millis().min(bound.as_millis()))
+            }));
+        }
+    }
+
+    fn string(&mut self) -> Result<String, QueryError> {
+        match self.tokens.next() {
+            (Token::String(value), _) => Ok(value),
+            (found, position) => Err(QueryError::UnexpectedToken {
+                expected: String::from("string"),
+                found,
+                position,
+            }),
+        }
+    }
+}
+
+type Columns = Vec<(String, Projection)>;
+
+/// Columns of the projection, and the aggregate if any, after checking `items` against `meta`.
+fn projections(
+    items: Vec<(Item, Option<String>)>,
+    meta: &TableMeta,
+    group_by: Option<&Vec<String>>,
+) -> Result<(Columns, Option<(AggregateOp, String)>), QueryError> {
+    let is_field = |name: &str| meta.fields.iter().any(|field| field.name == name);
+    let mut columns = Vec::new();
+    let mut aggregate = None;
+    let mut ungrouped = None;
+    for (item, alias) in items {
+        match item {
+            Item::Star => {
+                columns.extend(
+                    meta.labels
+                        .iter()
+                        .map(|label| (label.name.clone(), Projection::Label(label.name.clone()))),
+                );
+                columns.push((String::from("time"), Projection::Time));
+                columns.extend(
+                    meta.fields
+                        .iter()
+                        .map(|field| (field.name.clone(), Projection::Field(field.name.clone()))),
+                );
+            }
+            Item::Column(name, position) => {
+                let projection = if name.eq_ignore_ascii_case("time") {
+                    Projection::Time
+                } else if meta.labels.iter().any(|label| label.name == name) {
+                    if !group_by.is_some_and(|labels| labels.contains(&name)) {
+                        ungrouped.get_or_insert(position);
+                    }
+                    Projection::Label(name.clone())
+                } else if is_field(&name) {
+                    ungrouped.get_or_insert(position);
+                    Projection::Field(name.clone())
+                } else {
+                    return Err(QueryError::UnknownColumn { name, position });
+                };
+                columns.push((alias.unwrap_or(name), projection));
+            }
+            Item::Aggregate(op, function, field, position) => {
+                if !is_field(&field) {
+                    return Err(QueryError::UnknownColumn { name: field, position });
+                }
+                if aggregate.is_some() {
+                    return Err(QueryError::InvalidExpression {
+                        reason: String::from("only one aggregate per query is supported"),
+                        position,
+                    });
+                }
+                let name = alias.unwrap_or_else(|| format!("{}({})", function.to_ascii_lowercase(), field));
+                columns.push((name, Projection::Field(field.clone())));
+                aggregate = Some((op, field));
+            }
+        }
+    }
+    if let Some(position) = ungrouped.filter(|_| aggregate.is_some()) {
+        return Err(QueryError::InvalidExpression {
+            reason: String::from("column must be aggregated or appear in GROUP BY"),
+            position,
+        });
+    }
+    Ok((columns, aggregate))
+}
+
+fn join(op: BinaryOp, lhs: Option<ExprImpl>, rhs: Option<ExprImpl>) -> Option<ExprImpl> {
+    match (lhs, rhs) {
+        (Some(lhs), Some(rhs)) => Some(
+            Predicate::new(op, Box::new(lhs), Box::new(rhs))
+                .as_impl_ref()
+                .to_owned(),
+        ),
+        (lhs, rhs) => lhs.or(rhs),
+    }
+}
+
+/// `%` matches any string and `_` any character.
+fn like_to_regex(pattern: &str) -> String {
+    pattern
+        .chars()
+        .map(|c| match c {
+            '%' => String::from(".*"),
+            '_' => String::from("."),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect()
+}
+
+/// Parses a SQL `SELECT` against `catalog_list`, `now` is the value of `now()`.
+pub fn parse(input: &str, catalog_list: Arc<CatalogList>, now: Instant) -> Result<ExprImpl, QueryError> {
+    SqlParser::new(input, catalog_list, now)?.parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_lite::future;
+
+    use crate::common::Instant;
+    use crate::context::Context;
+    use crate::expression::aggregate::{Aggregate, AggregateOp, Grouping};
+    use crate::expression::chain::Chain;
+    use crate::expression::filter::{BinaryOp, Filter, Predicate};
+    use crate::expression::project::{Datum, Project, Projection, Rows};
+    use crate::expression::scan::Scanner;
+    use crate::expression::{Expression, Literal};
+    use crate::query::error::QueryError;
+    use crate::query::lexer::Position;
+    use crate::query::testing::{boxed, catalog_list, matcher};
+
+    use super::parse;
+
+    fn evaluate(query: &str) -> Rows {
+        let plan = parse(query, catalog_list(), Instant::from_millis(3000)).unwrap();
+        let result = future::block_on(plan.evaluate(&mut Context::new(), &[])).unwrap();
+        result.as_any().downcast_ref::<Rows>().unwrap().clone()
+    }
+
+    #[test]
+    fn test_parse() {
+        let catalog_list = catalog_list();
+        let now = Instant::from_millis(600_000);
+        let plan = parse(
+            "SELECT region, time, AVG(user) AS user FROM telegraf.metrics.cpu\n\
+             WHERE now() - 5m <= time AND time < now() AND (host <> 'c' OR region IN ('eu', 'a.b'))\n\
+             AND host NOT LIKE 'web_%'\n\
+             GROUP BY region",
+            catalog_list.clone(),
+            now,
+        )
+        .unwrap();
+        let range = Instant::from_millis(300_000)..now;
+        let project = Project::new(vec![
+            (String::from("region"), Projection::Label(String::from("region"))),
+            (String::from("time"), Projection::Time),
+            (String::from("user"), Projection::Field(String::from("user"))),
+        ])
+        .with_range(range.clone());
+        let aggregate = Aggregate::new(
+            AggregateOp::Avg,
+            String::from("user"),
+            Grouping::By(vec![String::from("region")]),
+        );
+        let predicate = Predicate::new(
+            BinaryOp::And,
+            boxed(Predicate::new(
+                BinaryOp::Or,
+                matcher(BinaryOp::ExactNotMatch, "host", "c"),
+                matcher(BinaryOp::RegexMatch, "region", "eu|a\\.b"),
+            )),
+            matcher(BinaryOp::RegexNotMatch, "host", "web..*"),
+        );
+        let scanner = Scanner::new(
+            catalog_list.clone(),
+            boxed(Filter::new(boxed(predicate), boxed(aggregate))),
+        )
+        .with_resource(Literal::new(String::from("telegraf.metrics.cpu")))
+        .with_range(range);
+        let expected = Chain::new(boxed(scanner)).then(project.as_impl_ref().to_owned());
+        assert!(plan == expected.as_impl_ref().to_owned());
+
+        let plan = parse("select * from cpu where time > 1000", catalog_list.clone(), now).unwrap();
+        let columns = ["host", "region"]
+            .into_iter()
+            .map(|label| (String::from(label), Projection::Label(String::from(label))))
+            .chain([(String::from("time"), Projection::Time)])
+            .chain(
+                ["user", "system"]
+                    .into_iter()
+                    .map(|field| (String::from(field), Projection::Field(String::from(field)))),
+            )
+            .collect();
+        let range = Instant::from_millis(1001)..Instant::from_millis(i64::MAX);
+        let expected = Scanner::new(catalog_list, boxed(Project::new(columns).with_range(range.clone())))
+            .with_resource(Literal::new(String::from("cpu")))
+            .with_range(range);
+        assert!(plan == expected.as_impl_ref().to_owned());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let catalog_list = catalog_list();
+        let now = Instant::from_millis(0);
+        let cases = [
+            ("SELECT user FROM disk", 1, 18),
+            ("SELECT load FROM cpu", 1, 8),
+            ("SELECT user FROM cpu WHERE zone = 'eu'", 1, 28),
+            ("SELECT SUM(user) FROM cpu GROUP BY zone", 1, 36),
+            ("SELECT user FROM cpu WHERE time > 0 OR host = 'a'", 1, 28),
+            ("SELECT user FROM cpu WHERE host = 'a' OR time > 0", 1, 42),
+            ("SELECT user FROM cpu WHERE (time > 0 OR host = 'a')", 1, 29),
+            ("SELECT user FROM cpu WHERE (time > 0) OR host = 'a'", 1, 29),
+            ("SELECT host, SUM(user) FROM cpu", 1, 8),
+            ("SELECT SUM(user), MAX(user) FROM cpu", 1, 19),
+            ("SELECT host FROM cpu\nGROUP BY host", 2, 1),
+            ("SELECT user FROM cpu WHERE host > 'a'", 1, 33),
+        ];
+        for (query, line, column) in cases {
+            let error = parse(query, catalog_list.clone(), now).unwrap_err();
+            assert_eq!(error.position(), Position { line, column }, "{}", error);
+        }
+        let error = parse("SELECT user FROM disk", catalog_list.clone(), now).unwrap_err();
+        assert_eq!(error.to_string(), "unknown table \"disk\" at 1:18");
+        let error = parse("SELECT load FROM cpu", catalog_list, now).unwrap_err();
+        assert!(matches!(error, QueryError::UnknownColumn { .. }));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let rows = evaluate("SELECT host, time, user FROM cpu WHERE region = 'eu' AND 1000 <= time < now()");
+        assert_eq!(rows.columns, vec!["host", "time", "user"]);
+        let row = |host: &str, millis: i64, value: f64| {
+            vec![
+                Datum::String(String::from(host)),
+                Datum::Time(Instant::from_millis(millis)),
+                Datum::Float(value),
+            ]
+        };
+        assert_eq!(
+            rows.rows,
+            vec![
+                row("a", 1000, 1.0),
+                row("b", 1000, 1.0),
+                row("a", 2000, 2.0),
+                row("b", 2000, 2.0)
+            ]
+        );
+
+        // parenthesised bounds and conditions after bounds
+        let rows = evaluate(
+            "SELECT host, time, user FROM cpu WHERE (time >= 2000 AND host <> 'c') AND (host = 'a' OR host = 'b')",
+        );
+        assert_eq!(
+            rows.rows,
+            vec![
+                row("a", 2000, 2.0),
+                row("b", 2000, 2.0),
+                row("a", 3000, 3.0),
+                row("b", 3000, 3.0)
+            ]
+        );
+
+        let rows = evaluate("SELECT region, SUM(system) FROM cpu WHERE time >= 3000 GROUP BY region");
+        assert_eq!(rows.columns, vec!["region", "sum(system)"]);
+        assert_eq!(
+            rows.rows,
+            vec![
+                vec![Datum::String(String::from("eu")), Datum::Float(2.0)],
+                vec![Datum::String(String::from("us")), Datum::Float(1.0)],
+            ]
+        );
+    }
+}
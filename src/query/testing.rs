@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use crate::catalog::schema::Schema;
+use crate::catalog::{Catalog, CatalogList};
+use crate::column::{FieldMeta, FieldValue, LabelMeta, LabelType, LabelValue};
+use crate::common::{Duration, Instant};
+use crate::expression::filter::{BinaryOp, Column, Predicate};
+use crate::expression::{ExprImpl, Expression};
+use crate::primitive::PrimitiveType;
+use crate::source::{Table, TableMeta};
+
+/// Tables of the default schema, also reachable by their catalog and schema:
+///
+/// - `prometheus.metrics.http_requests_total`, counters of `job` and `instance` growing by one per
+///   second from 0s to 600s.
+/// - `telegraf.metrics.cpu`, `user` growing by one per second from 0s to 3s and `system` of 1 per
+///   `host` and `region`.
+pub(crate) fn catalog_list() -> Arc<CatalogList> {
+    let label = |name: &str| LabelMeta {
+        name: String::from(name),
+        data_type: LabelType::String,
+        index: vec![],
+    };
+    let field = |name: &str| FieldMeta {
+        name: String::from(name),
+        data_type: PrimitiveType::F64,
+    };
+    let table = |name: &str, labels: [&str; 2], fields: &[&str]| {
+        let meta = TableMeta {
+            labels: labels.into_iter().map(label).collect(),
+            fields: fields.iter().copied().map(field).collect(),
+            time_interval: Duration::SECOND,
+            series_len: 60,
+        };
+        Table::new(String::from(name), meta).unwrap()
+    };
+
+    let requests = table("http_requests_total", ["job", "instance"], &["value"]);
+    for second in 0..=600 {
+        for (job, instance) in [("api", "a"), ("api", "b"), ("node", "a")] {
+            requests
+                .insert(
+                    &[
+                        ("job", LabelValue::String(job)),
+                        ("instance", LabelValue::String(instance)),
+                    ],
+                    Instant::from_millis(second * 1000),
+                    &[("value", FieldValue::Float64(second as f64))],
+                )
+                .unwrap();
+        }
+    }
+    let cpu = table("cpu", ["host", "region"], &["user", "system"]);
+    for (host, region) in [("a", "eu"), ("b", "eu"), ("c", "us")] {
+        for second in 0..4 {
+            cpu.insert(
+                &[
+                    ("host", LabelValue::String(host)),
+                    ("region", LabelValue::String(region)),
+                ],
+                Instant::from_millis(second * 1000),
+                &[
+                    ("user", FieldValue::Float64(second as f64)),
+                    ("system", FieldValue::Float64(1.0)),
+                ],
+            )
+            .unwrap();
+        }
+    }
+
+    let catalog_list = Arc::new(CatalogList::new());
+    for (catalog, table) in [("prometheus", Arc::new(requests)), ("telegraf", Arc::new(cpu))] {
+        let schema = Arc::new(Schema::new());
+        schema.insert(table.clone());
+        let metrics = Arc::new(Catalog::new());
+        metrics.insert(String::from("metrics"), schema);
+        catalog_list.insert(String::from(catalog), metrics);
+        catalog_list.get_default().get_default().insert(table);
+    }
+    catalog_list
+}
+
+pub(crate) fn boxed(expr: impl Expression) -> Box<ExprImpl> {
+    Box::new(expr.as_impl_ref().to_owned())
+}
+
+pub(crate) fn matcher(op: BinaryOp, label: &str, value: &str) -> Box<ExprImpl> {
+    boxed(Predicate::new(
+        op,
+        boxed(Column::new(String::from(label))),
+        boxed(String::from(value)),
+    ))
+}
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use async_task::{Runnable, Task};
+
+use crate::error::RuntimeError;
+use crate::executor::catch_panic;
+
+/// Threads of the blocking pool by default.
+pub(crate) const MAX_BLOCKING_THREADS: usize = 64;
+/// How long a thread of the blocking pool waits for work before it exits.
+const KEEP_ALIVE: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Default)]
+struct State {
+    queue: VecDeque<Runnable>,
+    threads: usize,
+    idle: usize,
+    closed: bool,
+}
+
+/// Unpinned threads for blocking work, started on demand up to `max_threads`. Work beyond that
+/// waits in a queue.
+#[derive(Debug)]
+pub(crate) struct BlockingPool {
+    state: Mutex<State>,
+    condvar: Condvar,
+    max_threads: usize,
+}
+
+impl BlockingPool {
+    pub(crate) fn new(max_threads: usize) -> Self {
+        Self {
+            state: Mutex::default(),
+            condvar: Condvar::new(),
+            max_threads: max_threads.max(1),
+        }
+    }
+
+    pub(crate) fn spawn<T: Send + 'static>(
+        self: &Arc<Self>,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Task<Result<T, RuntimeError>> {
+        let pool = Arc::clone(self);
+        let (runnable, task) =
+            async_task::spawn(catch_panic(async move { f() }), move |runnable| pool.schedule(runnable));
+        runnable.schedule();
+        task
+    }
+
+    fn schedule(self: &Arc<Self>, runnable: Runnable) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            // dropping it cancels the task
+            return;
+        }
+        state.queue.push_back(runnable);
+        if state.idle >= state.queue.len() {
+            self.condvar.notify_one();
+        } else if state.threads < self.max_threads {
+            state.threads += 1;
+            let pool = Arc::clone(self);
+            thread::Builder::new()
+                .name(String::from("blocking"))
+                .spawn(move || pool.work())
+                .unwrap();
+        }
+    }
+
+    fn work(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(runnable) = state.queue.pop_front() {
+                drop(state);
+                runnable.run();
+                state = self.state.lock().unwrap();
+                continue;
+            }
+            if state.closed {
+                break;
+            }
+            state.idle += 1;
+            let (guard, timeout) = self.condvar.wait_timeout(state, KEEP_ALIVE).unwrap();
+            state = guard;
+            state.idle -= 1;
+            if timeout.timed_out() && state.queue.is_empty() {
+                break;
+            }
+        }
+        state.threads -= 1;
+    }
+
+    /// Cancels the queued work and lets idle threads exit, running work is left to finish.
+    pub(crate) fn close(&self) {
+        let queue = {
+            let mut state = self.state.lock().unwrap();
+            state.closed = true;
+            std::mem::take(&mut state.queue)
+        };
+        self.condvar.notify_all();
+        drop(queue);
+    }
+
+    /// Threads of the pool and the work queued for them.
+    pub(crate) fn load(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.threads, state.queue.len())
+    }
+}
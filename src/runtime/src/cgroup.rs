@@ -0,0 +1,51 @@
+use std::fs;
+use std::num::NonZeroUsize;
+use std::thread;
+
+/// CPUs the process may use, by its affinity and the CPU quota of its cgroup.
+pub(crate) fn available_cpus() -> usize {
+    let parallelism = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    cpu_quota().map_or(parallelism, |quota| quota.min(parallelism))
+}
+
+/// CPU quota of the cgroup of the process rounded up, `None` if unlimited or unknown. Containers
+/// see their own cgroup at the root of the hierarchy.
+fn cpu_quota() -> Option<usize> {
+    if let Ok(max) = fs::read_to_string("/sys/fs/cgroup/cpu.max") {
+        return parse_cpu_max(&max);
+    }
+    let quota = fs::read_to_string("/sys/fs/cgroup/cpu/cpu.cfs_quota_us").ok()?;
+    let period = fs::read_to_string("/sys/fs/cgroup/cpu/cpu.cfs_period_us").ok()?;
+    quota_cpus(quota.trim().parse().ok()?, period.trim().parse().ok()?)
+}
+
+/// Parses cgroup v2 `cpu.max`, `$MAX $PERIOD` where `$MAX` may be `max`.
+fn parse_cpu_max(max: &str) -> Option<usize> {
+    let mut parts = max.split_whitespace();
+    let quota = parts.next()?.parse().ok()?;
+    let period = parts.next()?.parse().ok()?;
+    quota_cpus(quota, period)
+}
+
+/// CPUs of a quota per period as in cgroup v1, where a negative quota means unlimited.
+fn quota_cpus(quota: i64, period: i64) -> Option<usize> {
+    if quota <= 0 || period <= 0 {
+        return None;
+    }
+    Some(((quota as f64 / period as f64).ceil() as usize).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_cpu_max, quota_cpus};
+
+    #[test]
+    fn test_cpu_quota() {
+        assert_eq!(parse_cpu_max("max 100000\n"), None);
+        assert_eq!(parse_cpu_max("200000 100000\n"), Some(2));
+        assert_eq!(parse_cpu_max("150000 100000"), Some(2));
+        assert_eq!(parse_cpu_max("1000 100000"), Some(1));
+        assert_eq!(quota_cpus(-1, 100000), None);
+        assert_eq!(quota_cpus(400000, 100000), Some(4));
+    }
+}
@@ -0,0 +1,489 @@
+//! Channels between tasks. [`local`] connects tasks of one executor, [`bounded`] tasks on any
+//! executors. Waking a receiving task schedules it on its executor, which unparks that executor.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Poll, Waker};
+
+use concurrent_queue::{ConcurrentQueue, PushError};
+use futures::task::AtomicWaker;
+use futures_lite::future;
+
+/// The receiver is gone, the value was not sent.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendError(..)")
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum TrySendError<T> {
+    /// The channel is at capacity.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => f.write_str("Full(..)"),
+            TrySendError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LocalShared<T> {
+    queue: VecDeque<T>,
+    receiver: Option<Waker>,
+    senders: usize,
+    closed: bool,
+}
+
+/// Creates an unbounded channel between tasks of the current executor. Neither half is `Send`.
+pub fn local<T>() -> (LocalSender<T>, LocalReceiver<T>) {
+    let shared = Rc::new(RefCell::new(LocalShared {
+        queue: VecDeque::new(),
+        receiver: None,
+        senders: 1,
+        closed: false,
+    }));
+    (
+        LocalSender {
+            shared: Rc::clone(&shared),
+        },
+        LocalReceiver { shared },
+    )
+}
+
+#[derive(Debug)]
+pub struct LocalSender<T> {
+    shared: Rc<RefCell<LocalShared<T>>>,
+}
+
+impl<T> LocalSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut shared = self.shared.borrow_mut();
+        if shared.closed {
+            return Err(SendError(value));
+        }
+        shared.queue.push_back(value);
+        if let Some(waker) = shared.receiver.take() {
+            waker.wake();
+        }
+        Ok(())
+    }
+}
+
+impl<T> Clone for LocalSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.borrow_mut().senders += 1;
+        Self {
+            shared: Rc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for LocalSender<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.senders -= 1;
+        if shared.senders == 0 {
+            if let Some(waker) = shared.receiver.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LocalReceiver<T> {
+    shared: Rc<RefCell<LocalShared<T>>>,
+}
+
+impl<T> LocalReceiver<T> {
+    /// The next value, `None` once all senders are gone and every value was received.
+    pub async fn recv(&mut self) -> Option<T> {
+        future::poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            match shared.queue.pop_front() {
+                Some(value) => Poll::Ready(Some(value)),
+                None if shared.senders == 0 => Poll::Ready(None),
+                None => {
+                    shared.receiver = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    #[inline]
+    pub fn try_recv(&mut self) -> Option<T> {
+        self.shared.borrow_mut().queue.pop_front()
+    }
+}
+
+impl<T> Drop for LocalReceiver<T> {
+    fn drop(&mut self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.closed = true;
+        shared.queue.clear();
+    }
+}
+
+#[derive(Debug)]
+struct Shared<T> {
+    queue: ConcurrentQueue<T>,
+    receiver: AtomicWaker,
+    senders_waiting: Mutex<Waiters>,
+    senders: AtomicUsize,
+    closed: AtomicBool,
+}
+
+/// Senders waiting for room, oldest first, one entry per pending send.
+#[derive(Debug, Default)]
+struct Waiters {
+    next: u64,
+    queue: VecDeque<(u64, Waker)>,
+}
+
+impl Waiters {
+    /// Registers `waker` under `key`, replacing the one of an earlier poll that was not woken yet.
+    fn register(&mut self, key: &mut Option<u64>, waker: &Waker) {
+        if let Some(entry) = key.and_then(|key| self.queue.iter_mut().find(|(id, _)| *id == key)) {
+            if !entry.1.will_wake(waker) {
+                entry.1 = waker.clone();
+            }
+            return;
+        }
+        let id = self.next;
+        self.next += 1;
+        self.queue.push_back((id, waker.clone()));
+        *key = Some(id);
+    }
+
+    /// Removes `key`, false if it was already woken.
+    fn remove(&mut self, key: u64) -> bool {
+        match self.queue.iter().position(|(id, _)| *id == key) {
+            Some(position) => {
+                self.queue.remove(position);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<T> Shared<T> {
+    /// Wakes the oldest waiting sender, for one freed slot.
+    fn wake_sender(&self) {
+        let waker = self.senders_waiting.lock().unwrap().queue.pop_front();
+        if let Some((_, waker)) = waker {
+            waker.wake();
+        }
+    }
+
+    fn wake_senders(&self) {
+        let wakers = std::mem::take(&mut self.senders_waiting.lock().unwrap().queue);
+        wakers.into_iter().for_each(|(_, waker)| waker.wake());
+    }
+}
+
+/// The waiting slot of one `send`, a send dropped after being woken passes the wakeup on.
+struct Waiter<'a, T> {
+    shared: &'a Shared<T>,
+    key: Option<u64>,
+}
+
+impl<T> Waiter<'_, T> {
+    fn register(&mut self, waker: &Waker) {
+        self.shared
+            .senders_waiting
+            .lock()
+            .unwrap()
+            .register(&mut self.key, waker);
+    }
+
+    fn done(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.shared.senders_waiting.lock().unwrap().remove(key);
+        }
+    }
+}
+
+impl<T> Drop for Waiter<'_, T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            let waiting = self.shared.senders_waiting.lock().unwrap().remove(key);
+            if !waiting {
+                self.shared.wake_sender();
+            }
+        }
+    }
+}
+
+/// Creates a channel holding up to `capacity` values between tasks on any executors, senders
+/// wait while it is full. Panics if `capacity` is zero, there are no rendezvous channels.
+pub fn bounded<T: Send>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "bounded channel capacity must be positive");
+    let shared = Arc::new(Shared {
+        queue: ConcurrentQueue::bounded(capacity),
+        receiver: AtomicWaker::new(),
+        senders_waiting: Mutex::default(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared },
+    )
+}
+
+#[derive(Debug)]
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.shared.closed.load(Ordering::Acquire) {
+            return Err(TrySendError::Closed(value));
+        }
+        match self.shared.queue.push(value) {
+            Ok(()) => {
+                self.shared.receiver.wake();
+                Ok(())
+            }
+            Err(PushError::Full(value)) => Err(TrySendError::Full(value)),
+            Err(PushError::Closed(value)) => Err(TrySendError::Closed(value)),
+        }
+    }
+
+    /// Sends `value`, waiting for room while the channel is full.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut value = Some(value);
+        let mut waiter = Waiter {
+            shared: &self.shared,
+            key: None,
+        };
+        let result = future::poll_fn(|cx| {
+            match self.try_send(value.take().unwrap()) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(rejected)) => return Poll::Ready(Err(SendError(rejected))),
+                Err(TrySendError::Full(rejected)) => value = Some(rejected),
+            }
+            waiter.register(cx.waker());
+            // the receiver may have made room before the waker was registered
+            match self.try_send(value.take().unwrap()) {
+                Ok(()) => Poll::Ready(Ok(())),
+                Err(TrySendError::Closed(rejected)) => Poll::Ready(Err(SendError(rejected))),
+                Err(TrySendError::Full(rejected)) => {
+                    value = Some(rejected);
+                    Poll::Pending
+                }
+            }
+        })
+        .await;
+        waiter.done();
+        result
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::Relaxed);
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.receiver.wake();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn try_recv(&mut self) -> Option<T> {
+        let value = self.shared.queue.pop().ok()?;
+        self.shared.wake_sender();
+        Some(value)
+    }
+
+    /// The next value, `None` once all senders are gone and every value was received.
+    pub async fn recv(&mut self) -> Option<T> {
+        future::poll_fn(|cx| {
+            if let Some(value) = self.try_recv() {
+                return Poll::Ready(Some(value));
+            }
+            self.shared.receiver.register(cx.waker());
+            // a value or the last sender may have gone before the waker was registered
+            if let Some(value) = self.try_recv() {
+                return Poll::Ready(Some(value));
+            }
+            if self.shared.senders.load(Ordering::Acquire) == 0 {
+                return Poll::Ready(self.try_recv());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+        self.shared.queue.close();
+        self.shared.wake_senders();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+
+    use core_affinity::CoreId;
+    use futures::task::{waker, ArcWake};
+    use futures_lite::future;
+
+    use super::{bounded, local, TrySendError};
+    use crate::{spawn_local, Runtime};
+
+    #[test]
+    fn test_local() {
+        let cores = [CoreId { id: 0 }];
+        let mut runtime = Runtime::new(&cores).unwrap();
+        runtime.run();
+        let task = runtime.spawn_to(0, || async {
+            let (sender, mut receiver) = local();
+            let producer = sender.clone();
+            spawn_local(async move {
+                for i in 0..3 {
+                    producer.send(i).unwrap();
+                    future::yield_now().await;
+                }
+            })
+            .detach();
+            drop(sender);
+            let mut received = vec![];
+            while let Some(i) = receiver.recv().await {
+                received.push(i);
+            }
+            received
+        });
+        assert_eq!(future::block_on(task).unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_bounded() {
+        let cores = [CoreId { id: 0 }, CoreId { id: 0 }];
+        let mut runtime = Runtime::new(&cores).unwrap();
+        runtime.run();
+        let (sender, mut receiver) = bounded(1);
+        sender.try_send(0).unwrap();
+        assert!(matches!(sender.try_send(1), Err(TrySendError::Full(1))));
+        let producers = (0..2)
+            .map(|id| {
+                let sender = sender.clone();
+                runtime.spawn_to(id, move || async move {
+                    for i in 1..=100 {
+                        sender.send(i).await.unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        drop(sender);
+        let consumer = runtime.spawn_to(1, || async move {
+            let mut sum = 0;
+            while let Some(i) = receiver.recv().await {
+                sum += i;
+            }
+            sum
+        });
+        for producer in producers {
+            future::block_on(producer).unwrap();
+        }
+        assert_eq!(future::block_on(consumer).unwrap(), 2 * 5050);
+
+        let (sender, receiver) = bounded(1);
+        drop(receiver);
+        assert!(future::block_on(sender.send(1)).is_err());
+
+        let panic = std::panic::catch_unwind(|| bounded::<u32>(0)).unwrap_err();
+        assert_eq!(
+            panic.downcast_ref::<&str>(),
+            Some(&"bounded channel capacity must be positive")
+        );
+    }
+
+    struct Wakes(AtomicUsize);
+
+    impl ArcWake for Wakes {
+        fn wake_by_ref(wakes: &Arc<Self>) {
+            wakes.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn test_waiting_senders() {
+        let (sender, mut receiver) = bounded(1);
+        sender.try_send(0).unwrap();
+        let wakes = [(); 3].map(|_| Arc::new(Wakes(AtomicUsize::new(0))));
+        let mut first = Box::pin(sender.send(1));
+        let mut second = Box::pin(sender.send(2));
+        let mut third = Box::pin(sender.send(3));
+        for _ in 0..10 {
+            for (send, wakes) in [first.as_mut(), second.as_mut(), third.as_mut()]
+                .into_iter()
+                .zip(&wakes)
+            {
+                let waker = waker(Arc::clone(wakes));
+                assert!(send.poll(&mut Context::from_waker(&waker)).is_pending());
+            }
+        }
+        // re-polling replaces a send's waker rather than queueing another one
+        assert_eq!(sender.shared.senders_waiting.lock().unwrap().queue.len(), 3);
+
+        // one freed slot wakes one sender, the oldest
+        assert_eq!(receiver.try_recv(), Some(0));
+        let woken = wakes.each_ref().map(|wakes| wakes.0.load(Ordering::Relaxed));
+        assert_eq!(woken, [1, 0, 0]);
+
+        // a woken send that is dropped passes the wakeup on
+        drop(first);
+        let woken = wakes.each_ref().map(|wakes| wakes.0.load(Ordering::Relaxed));
+        assert_eq!(woken, [1, 1, 0]);
+        let waker = waker(Arc::clone(&wakes[1]));
+        assert_eq!(
+            second.as_mut().poll(&mut Context::from_waker(&waker)),
+            Poll::Ready(Ok(()))
+        );
+        assert_eq!(sender.shared.senders_waiting.lock().unwrap().queue.len(), 1);
+
+        drop(third);
+        assert!(sender.shared.senders_waiting.lock().unwrap().queue.is_empty());
+        assert_eq!(receiver.try_recv(), Some(2));
+    }
+}
@@ -0,0 +1,63 @@
+use async_task::Runnable;
+use concurrent_queue::ConcurrentQueue;
+
+pub(crate) const CLASSES: usize = 2;
+
+/// How urgent the tasks of a class are. Every executor round shares its budget between the classes
+/// by their weight, so a flood of batch tasks can't starve latency sensitive ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SchedulingClass {
+    /// Short tasks that must not wait, like ingest.
+    #[default]
+    Latency,
+    /// Long running work, like queries.
+    Batch,
+}
+
+impl SchedulingClass {
+    pub const ALL: [SchedulingClass; CLASSES] = [SchedulingClass::Latency, SchedulingClass::Batch];
+
+    #[inline]
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+
+    /// Weight if not set by [`crate::Runtime::with_class_weight`].
+    pub(crate) fn default_weight(self) -> usize {
+        match self {
+            SchedulingClass::Latency => 4,
+            SchedulingClass::Batch => 1,
+        }
+    }
+}
+
+/// One queue per [`SchedulingClass`].
+#[derive(Debug)]
+pub(crate) struct ClassQueues([ConcurrentQueue<Runnable>; CLASSES]);
+
+impl ClassQueues {
+    pub(crate) fn new() -> Self {
+        Self([(); CLASSES].map(|_| ConcurrentQueue::unbounded()))
+    }
+
+    #[inline]
+    pub(crate) fn push(&self, class: SchedulingClass, runnable: Runnable) {
+        self.0[class.index()].push(runnable).unwrap();
+    }
+
+    #[inline]
+    pub(crate) fn pop(&self, class: SchedulingClass) -> Option<Runnable> {
+        self.0[class.index()].pop().ok()
+    }
+
+    #[inline]
+    pub(crate) fn len(&self, class: SchedulingClass) -> usize {
+        self.0[class.index()].len()
+    }
+
+    /// Tasks queued in all classes.
+    #[inline]
+    pub(crate) fn total_len(&self) -> usize {
+        self.0.iter().map(ConcurrentQueue::len).sum()
+    }
+}
@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use snafu::Snafu;
 
 #[derive(Snafu, Debug)]
@@ -7,4 +9,10 @@ pub enum RuntimeError {
     GetCoreError,
     #[snafu(display("not too much cores, require: {}, has: {}", require, has))]
     NotMuchCores { require: usize, has: usize },
+    #[snafu(display("timed out after {:?}", duration))]
+    Timeout { duration: Duration },
+    #[snafu(display("task panicked: {}", message))]
+    Panicked { message: String },
+    #[snafu(display("{} executors failed to stop before the shutdown deadline", executors))]
+    Shutdown { executors: usize },
 }
@@ -1,110 +1,336 @@
-use std::cell::{OnceCell, RefCell};
+use std::cell::{Cell, RefCell};
 use std::collections::VecDeque;
 use std::future::Future;
+use std::panic::AssertUnwindSafe;
 use std::rc::Rc;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::task::Waker;
+use std::time::Instant;
 
 use async_task::{Runnable, Task};
-use concurrent_queue::ConcurrentQueue;
+use futures::FutureExt;
 use futures_lite::future;
 
+use crate::class::{ClassQueues, SchedulingClass, CLASSES};
+use crate::error::RuntimeError;
+use crate::io::{Driver, IO_POLL_INTERVAL};
+use crate::park::Parker;
+use crate::stats::{Metrics, Queue};
+use crate::steal::Stealing;
+use crate::timer::{self, TimerWheel};
+
 const NR_TASKS: usize = 256;
+/// Idle rounds of the executor loop before it parks by default.
+pub(crate) const SPIN_BEFORE_PARK: usize = 64;
 
 thread_local! {
-    pub(crate) static CONTEXT: OnceCell<Context> = OnceCell::new()
+    pub(crate) static CONTEXT: Current = Current::default()
+}
+
+/// Context of the executor running on this thread. A simulation switches it between executors.
+#[derive(Debug, Default)]
+pub(crate) struct Current(RefCell<Option<Rc<Context>>>);
+
+impl Current {
+    #[inline]
+    pub(crate) fn get(&self) -> Option<Rc<Context>> {
+        self.0.borrow().clone()
+    }
+
+    #[inline]
+    pub(crate) fn replace(&self, context: Option<Rc<Context>>) -> Option<Rc<Context>> {
+        self.0.replace(context)
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct Context {
-    pub(crate) local: Rc<RefCell<VecDeque<Runnable>>>,
-    pub(crate) assigned: Arc<ConcurrentQueue<Runnable>>,
-    pub(crate) global: Arc<ConcurrentQueue<Runnable>>,
+    pub(crate) local: Rc<[RefCell<VecDeque<Runnable>>; CLASSES]>,
+    pub(crate) assigned: Arc<ClassQueues>,
+    pub(crate) global: Arc<ClassQueues>,
+    pub(crate) parker: Arc<Parker>,
+    /// Parkers of every executor of the runtime, all are woken for a task queued globally.
+    pub(crate) parkers: Vec<Arc<Parker>>,
+    /// Set if work stealing is on.
+    pub(crate) stealing: Option<Stealing>,
+    /// Timers of the tasks polled here, locked by tasks stolen away when they cancel.
+    pub(crate) timers: Arc<Mutex<TimerWheel>>,
+    /// Class of the running task, inherited by the tasks it spawns.
+    pub(crate) class: Cell<SchedulingClass>,
+    pub(crate) metrics: Arc<Metrics>,
+    /// Runs the file operations of the tasks polled here.
+    pub(crate) io: Driver,
 }
 
 impl Context {
-    fn new(global: Arc<ConcurrentQueue<Runnable>>, assigned: Arc<ConcurrentQueue<Runnable>>) -> Self {
+    pub(crate) fn new(
+        global: Arc<ClassQueues>,
+        assigned: Arc<ClassQueues>,
+        parker: Arc<Parker>,
+        parkers: Vec<Arc<Parker>>,
+        stealing: Option<Stealing>,
+        metrics: Arc<Metrics>,
+        io: Driver,
+    ) -> Self {
+        metrics.set_io_uring(io.is_uring());
         Context {
-            local: Rc::new(RefCell::new(VecDeque::new())),
+            local: Rc::new([(); CLASSES].map(|_| RefCell::new(VecDeque::new()))),
             assigned,
             global,
+            parker,
+            parkers,
+            stealing,
+            timers: Arc::new(Mutex::new(TimerWheel::new(timer::now()))),
+            class: Cell::new(SchedulingClass::default()),
+            metrics,
+            io,
+        }
+    }
+
+    pub(crate) fn run(&self, runnable: Runnable) {
+        let start = Instant::now();
+        runnable.run();
+        self.metrics.record_poll(start.elapsed());
+    }
+
+    pub(crate) fn pop(&self, queue: Queue, class: SchedulingClass) -> Option<Runnable> {
+        match queue {
+            Queue::Local => self.local[class.index()].borrow_mut().pop_front(),
+            Queue::Assigned => self.assigned.pop(class),
+            Queue::Global => self.global.pop(class),
+            Queue::Stealable => self.stealing.as_ref().and_then(|stealing| stealing.own().pop(class)),
+        }
+    }
+
+    /// Tasks queued in `queue` and `class`.
+    pub(crate) fn queued(&self, queue: Queue, class: SchedulingClass) -> usize {
+        match queue {
+            Queue::Local => self.local[class.index()].borrow().len(),
+            Queue::Assigned => self.assigned.len(class),
+            Queue::Global => self.global.len(class),
+            Queue::Stealable => self.stealing.as_ref().map_or(0, |stealing| stealing.own().len(class)),
+        }
+    }
+
+    /// Runs tasks of `queue` and `class` until `ran` reaches `limit`.
+    fn run_queue(&self, queue: Queue, class: SchedulingClass, ran: &mut usize, limit: usize) {
+        while *ran < limit {
+            match self.pop(queue, class) {
+                Some(runnable) => {
+                    self.metrics.record_task(queue);
+                    self.run(runnable);
+                }
+                None => break,
+            }
+            *ran += 1;
+        }
+    }
+
+    /// Runs up to `budget` tasks of `class`, half of it for local tasks, half of the rest for
+    /// assigned ones and what is left for global and stealable ones. What a queue leaves of its
+    /// part goes to the others. Returns how many ran.
+    fn run_class(&self, class: SchedulingClass, budget: usize) -> usize {
+        self.class.set(class);
+        let mut ran = 0;
+        self.run_queue(Queue::Local, class, &mut ran, budget / 2);
+        let limit = ran + (budget - ran) / 2;
+        self.run_queue(Queue::Assigned, class, &mut ran, limit);
+        for queue in [Queue::Global, Queue::Stealable, Queue::Local, Queue::Assigned] {
+            self.run_queue(queue, class, &mut ran, budget);
+        }
+        ran
+    }
+
+    /// Runs a round of up to `NR_TASKS` tasks, `shares` of them per class while all classes have
+    /// work. Steals if there was nothing to run. Returns how many ran.
+    fn run_round(&self, shares: [usize; CLASSES]) -> usize {
+        // tasks of completed file operations run in this round
+        self.io.poll();
+        let mut ran = 0;
+        for (class, share) in SchedulingClass::ALL.into_iter().zip(shares) {
+            ran += self.run_class(class, share);
+        }
+        // what a class left of its share goes to the others
+        for class in SchedulingClass::ALL {
+            if ran < NR_TASKS {
+                ran += self.run_class(class, NR_TASKS - ran);
+            }
+        }
+        if let Some(stealing) = &self.stealing {
+            if ran == 0 {
+                ran = stealing.steal(|class, runnable| {
+                    self.class.set(class);
+                    self.run(runnable);
+                });
+            }
+        }
+        let local_depth = self.local.iter().map(|queue| queue.borrow().len()).sum();
+        self.metrics.set_local_depth(local_depth);
+        ran
+    }
+
+    /// Drops the queued tasks of this executor, which cancels them. The global queue is left to
+    /// the other executors.
+    fn cancel_queued(&self) {
+        for class in SchedulingClass::ALL {
+            for queue in [Queue::Local, Queue::Assigned, Queue::Stealable] {
+                while self.pop(queue, class).is_some() {}
+            }
         }
     }
 }
 
 #[derive(Debug)]
-pub struct Executor;
+pub struct Executor {
+    spin_before_park: usize,
+    weights: [usize; CLASSES],
+}
 
 impl Executor {
-    /// Creates a new executor.
-    pub fn new(global: Arc<ConcurrentQueue<Runnable>>, assigned: Arc<ConcurrentQueue<Runnable>>) -> Self {
-        CONTEXT.with(|context| context.set(Context::new(global, assigned)).unwrap());
-        Executor {}
+    /// Creates a new executor, `parker` is unparked whenever a task is scheduled to it, and
+    /// `parkers` of all executors sharing `global` when a task is queued there.
+    pub(crate) fn new(
+        global: Arc<ClassQueues>,
+        assigned: Arc<ClassQueues>,
+        parker: Arc<Parker>,
+        parkers: Vec<Arc<Parker>>,
+        stealing: Option<Stealing>,
+        metrics: Arc<Metrics>,
+        io: Driver,
+    ) -> Self {
+        let context = Context::new(global, assigned, parker, parkers, stealing, metrics, io);
+        let previous = CONTEXT.with(|current| current.replace(Some(Rc::new(context))));
+        assert!(previous.is_none(), "a thread runs a single executor");
+        Executor {
+            spin_before_park: SPIN_BEFORE_PARK,
+            weights: SchedulingClass::ALL.map(SchedulingClass::default_weight),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn with_spin_before_park(mut self, spins: usize) -> Self {
+        self.spin_before_park = spins;
+        self
     }
 
-    pub async fn run(&mut self, future: impl Future<Output = ()>) {
-        // A future that runs tasks forever.
+    #[inline]
+    pub(crate) fn with_weights(mut self, weights: [usize; CLASSES]) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    /// Tasks each class may run per round, at least one so that no class starves.
+    fn shares(&self) -> [usize; CLASSES] {
+        let total = self.weights.iter().sum::<usize>().max(1);
+        self.weights.map(|weight| (NR_TASKS * weight / total).max(1))
+    }
+
+    pub async fn run<T>(&mut self, future: impl Future<Output = T>) -> T {
+        let spin_before_park = self.spin_before_park;
+        let shares = self.shares();
+        let (parker, timers, metrics) = CONTEXT.with(|context| {
+            let context = context.get().unwrap();
+            (
+                Arc::clone(&context.parker),
+                Arc::clone(&context.timers),
+                Arc::clone(&context.metrics),
+            )
+        });
+        // A future that runs tasks forever, and parks once there were none for a while, until the
+        // next timer is due at the latest, or shortly while the kernel did not take file operations.
         let run_forever = async move {
+            let mut idle = 0;
             loop {
-                CONTEXT.with(|context| {
-                    let context = context.get().unwrap();
-                    let mut capacity = NR_TASKS;
-                    for _ in 0..(capacity / 2) {
-                        let runnable = context.local.borrow_mut().pop_front();
-                        if let Some(runnable) = runnable {
-                            runnable.run();
-                            capacity -= 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    for _ in 0..(capacity / 2) {
-                        if let Ok(runnable) = context.assigned.pop() {
-                            runnable.run();
-                            capacity -= 1;
-                        } else {
-                            break;
-                        }
-                    }
-                    for _ in 0..capacity {
-                        if let Ok(runnable) = context.global.pop() {
-                            runnable.run();
-                        } else {
-                            break;
-                        }
-                    }
-                });
+                let round = Instant::now();
+                // tasks of due timers run in this round
+                let expired = timers.lock().unwrap().advance(timer::now());
+                expired.into_iter().for_each(Waker::wake);
+                let ran = CONTEXT.with(|context| context.get().unwrap().run_round(shares));
+                metrics.record_round(ran > 0, round.elapsed());
 
+                if ran > 0 {
+                    idle = 0;
+                } else {
+                    idle += 1;
+                }
+                if idle > spin_before_park {
+                    let deadline = timers.lock().unwrap().next_deadline();
+                    let parked = Instant::now();
+                    let mut timeout = deadline.map(|deadline| deadline.saturating_duration_since(parked));
+                    if CONTEXT.with(|context| context.get().unwrap().io.unsubmitted()) > 0 {
+                        timeout = Some(timeout.map_or(IO_POLL_INTERVAL, |timeout| timeout.min(IO_POLL_INTERVAL)));
+                    }
+                    parker.park(timeout);
+                    metrics.record_park(parked.elapsed());
+                    idle = 0;
+                }
                 future::yield_now().await;
             }
         };
 
         // Run `future` and `run_forever` concurrently until `future` completes.
-        future::or(future, run_forever).await;
+        future::or(future, run_forever).await
+    }
+
+    /// Runs the queued tasks until there are none or `deadline` passed, then cancels the rest.
+    pub(crate) fn drain(&mut self, deadline: Instant) {
+        let shares = self.shares();
+        CONTEXT.with(|context| {
+            let context = context.get().unwrap();
+            while Instant::now() < deadline && context.run_round(shares) > 0 {}
+            context.cancel_queued();
+        });
     }
 }
 
-pub fn spawn_local<T>(future: impl Future<Output = T>) -> Task<T> {
+/// Fails the task with [`RuntimeError::Panicked`] if polling `future` panics, so that the panic
+/// doesn't unwind through `Runnable::run` into the executor.
+pub(crate) fn catch_panic<T>(future: impl Future<Output = T>) -> impl Future<Output = Result<T, RuntimeError>> {
+    AssertUnwindSafe(future).catch_unwind().map(|result| {
+        result.map_err(|payload| {
+            let message = match payload.downcast::<String>() {
+                Ok(message) => *message,
+                Err(payload) => payload
+                    .downcast_ref::<&str>()
+                    .map_or("unknown", |message| message)
+                    .to_owned(),
+            };
+            RuntimeError::Panicked { message }
+        })
+    })
+}
+
+/// Spawns a task on the current executor, in the [`SchedulingClass`] of the running task.
+pub fn spawn_local<T>(future: impl Future<Output = T>) -> Task<Result<T, RuntimeError>> {
     let schedule = CONTEXT.with(|context| {
         let context = context.get().unwrap();
-        let queue = Rc::clone(&context.local);
+        let queues = Rc::clone(&context.local);
+        let class = context.class.get();
+        let parker = Arc::clone(&context.parker);
         move |runnable| {
-            queue.borrow_mut().push_back(runnable);
+            queues[class.index()].borrow_mut().push_back(runnable);
+            parker.unpark();
         }
     });
-    let (runnable, task) = unsafe { async_task::spawn_unchecked(future, schedule) };
+    let (runnable, task) = unsafe { async_task::spawn_unchecked(catch_panic(future), schedule) };
     runnable.schedule();
     task
 }
 
-pub fn spawn<T: Send + Sync>(future: impl Future<Output = T> + Send + Sync) -> Task<T> {
-    let schedule = CONTEXT.with(|context| {
+/// Spawns a `Send` task from within an executor, in the [`SchedulingClass`] of the running task.
+/// With work stealing it is queued on the current executor, where idle siblings may steal it,
+/// otherwise on the global queue.
+pub fn spawn<T: Send + Sync>(future: impl Future<Output = T> + Send + Sync) -> Task<Result<T, RuntimeError>> {
+    let (queue, class, parkers) = CONTEXT.with(|context| {
         let context = context.get().unwrap();
-        let global = Arc::clone(&context.global);
-        move |runnable| {
-            global.push(runnable).unwrap();
-        }
+        let queue = match &context.stealing {
+            Some(stealing) => Arc::clone(&stealing.queues[stealing.id]),
+            None => Arc::clone(&context.global),
+        };
+        (queue, context.class.get(), context.parkers.clone())
     });
-    let (runnable, task) = unsafe { async_task::spawn_unchecked(future, schedule) };
+    let schedule = move |runnable| Stealing::push(&queue, class, &parkers, runnable);
+    let (runnable, task) = unsafe { async_task::spawn_unchecked(catch_panic(future), schedule) };
     runnable.schedule();
     task
 }
@@ -112,23 +338,31 @@ pub fn spawn<T: Send + Sync>(future: impl Future<Output = T> + Send + Sync) -> T
 #[cfg(test)]
 mod test {
     use super::{spawn_local, Executor};
-    use concurrent_queue::ConcurrentQueue;
+    use crate::class::{ClassQueues, SchedulingClass};
+    use crate::io::Driver;
+    use crate::park::Parker;
     use futures_lite::future;
     use futures_lite::future::yield_now;
     use std::cell::RefCell;
     use std::rc::Rc;
-    use std::sync::Arc;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_runtime() {
         let mut ex = Executor::new(
-            Arc::new(ConcurrentQueue::unbounded()),
-            Arc::new(ConcurrentQueue::unbounded()),
+            Arc::new(ClassQueues::new()),
+            Arc::new(ClassQueues::new()),
+            Arc::new(Parker::new()),
+            vec![],
+            None,
+            Arc::default(),
+            Driver::Inline,
         );
 
         let task = spawn_local(async { 1 + 2 });
         future::block_on(ex.run(async {
-            let res = task.await * 2;
+            let res = task.await.unwrap() * 2;
             assert_eq!(res, 6);
         }));
     }
@@ -136,8 +370,13 @@ mod test {
     #[test]
     fn test_yield() {
         let mut ex = Executor::new(
-            Arc::new(ConcurrentQueue::unbounded()),
-            Arc::new(ConcurrentQueue::unbounded()),
+            Arc::new(ClassQueues::new()),
+            Arc::new(ClassQueues::new()),
+            Arc::new(Parker::new()),
+            vec![],
+            None,
+            Arc::default(),
+            Driver::Inline,
         );
 
         let counter = Rc::new(RefCell::new(0));
@@ -168,9 +407,85 @@ mod test {
                 assert_eq!(*c, 2);
                 *c = 3;
             }
-            t.await;
+            t.await.unwrap();
         });
-        future::block_on(ex.run(task));
+        future::block_on(ex.run(task)).unwrap();
         assert_eq!(*counter.as_ref().borrow(), 4);
     }
+
+    #[test]
+    fn test_weighted_classes() {
+        let assigned = Arc::new(ClassQueues::new());
+        let mut ex = Executor::new(
+            Arc::new(ClassQueues::new()),
+            Arc::clone(&assigned),
+            Arc::new(Parker::new()),
+            vec![],
+            None,
+            Arc::default(),
+            Driver::Inline,
+        );
+
+        let order = Arc::new(Mutex::new(vec![]));
+        let tasks = (0..300)
+            .flat_map(|_| SchedulingClass::ALL)
+            .map(|class| {
+                let order = Arc::clone(&order);
+                let queue = Arc::clone(&assigned);
+                let (runnable, task) =
+                    async_task::spawn(async move { order.lock().unwrap().push(class) }, move |runnable| {
+                        queue.push(class, runnable)
+                    });
+                runnable.schedule();
+                task
+            })
+            .collect::<Vec<_>>();
+        future::block_on(ex.run(async {
+            for task in tasks {
+                task.await;
+            }
+        }));
+        let order = order.lock().unwrap();
+        // while both have work, batch only gets its weighted share of a round
+        let batch = order[..256]
+            .iter()
+            .filter(|class| **class == SchedulingClass::Batch)
+            .count();
+        assert_eq!(batch, 256 / 5);
+        assert_eq!(order.len(), 600);
+    }
+
+    #[test]
+    fn test_drain() {
+        let assigned = Arc::new(ClassQueues::new());
+        let mut ex = Executor::new(
+            Arc::new(ClassQueues::new()),
+            Arc::clone(&assigned),
+            Arc::new(Parker::new()),
+            vec![],
+            None,
+            Arc::default(),
+            Driver::Inline,
+        );
+        let spawn = |i: usize| {
+            let queue = Arc::clone(&assigned);
+            let (runnable, task) = async_task::spawn(async move { i }, move |runnable| {
+                queue.push(SchedulingClass::default(), runnable)
+            });
+            runnable.schedule();
+            task
+        };
+
+        let tasks = (0..1000).map(spawn).collect::<Vec<_>>();
+        ex.drain(Instant::now() + Duration::from_secs(3600));
+        let sum = tasks.into_iter().map(future::block_on).sum::<usize>();
+        assert_eq!(sum, 999 * 1000 / 2);
+
+        // the deadline passed, queued tasks are cancelled
+        let tasks = (0..1000).map(spawn).collect::<Vec<_>>();
+        ex.drain(Instant::now());
+        assert!(tasks
+            .into_iter()
+            .all(|task| future::block_on(task.fallible()).is_none()));
+    }
 }
@@ -0,0 +1,264 @@
+//! Files for the tasks of the runtime. Each executor submits their operations to its own
+//! io_uring where the kernel supports it, and reaps completions every round, so a task resumes on
+//! the executor that issued them. Otherwise they run on the blocking pool. Operations own their
+//! buffers until they complete, even if their future is dropped, and take an offset rather than
+//! moving a cursor.
+
+use std::ffi::CString;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::io::{self as driver, Op};
+
+/// How to open a [`File`], like [`std::fs::OpenOptions`].
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    create: bool,
+    truncate: bool,
+    mode: u32,
+}
+
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OpenOptions {
+    /// Nothing set, created files get mode `0o644`.
+    pub fn new() -> Self {
+        Self {
+            read: false,
+            write: false,
+            append: false,
+            create: false,
+            truncate: false,
+            mode: 0o644,
+        }
+    }
+
+    #[inline]
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    #[inline]
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    #[inline]
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    #[inline]
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    #[inline]
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Permissions of a created file, before the umask.
+    #[inline]
+    pub fn mode(&mut self, mode: u32) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    fn flags(&self) -> i32 {
+        let mut flags = match (self.read, self.write || self.append) {
+            (true, true) => libc::O_RDWR,
+            (false, true) => libc::O_WRONLY,
+            _ => libc::O_RDONLY,
+        };
+        for (set, flag) in [
+            (self.append, libc::O_APPEND),
+            (self.create, libc::O_CREAT),
+            (self.truncate, libc::O_TRUNC),
+        ] {
+            if set {
+                flags |= flag;
+            }
+        }
+        flags
+    }
+
+    pub async fn open(&self, path: impl AsRef<Path>) -> io::Result<File> {
+        let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+        let op = Op::Open {
+            path,
+            flags: self.flags(),
+            mode: self.mode,
+            opened: None,
+        };
+        match driver::submit(op).await {
+            (_, Some(Op::Open { opened: Some(fd), .. })) => Ok(File { fd: Arc::new(fd) }),
+            (result, _) => Err(driver::result(result)
+                .err()
+                .unwrap_or_else(|| io::ErrorKind::Other.into())),
+        }
+    }
+}
+
+/// A file opened by [`OpenOptions`], clones share its descriptor. Its futures must be polled
+/// within the runtime and are not `Send`, a task may use clones on any executor though.
+#[derive(Debug, Clone)]
+pub struct File {
+    fd: Arc<OwnedFd>,
+}
+
+impl File {
+    /// Opens the file at `path` to read.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<File> {
+        OpenOptions::new().read(true).open(path).await
+    }
+
+    /// Opens the file at `path` to write, creates it if missing and truncates it otherwise.
+    pub async fn create(path: impl AsRef<Path>) -> io::Result<File> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .await
+    }
+
+    /// Reads up to `buf.len()` bytes at `offset` into `buf`, and returns how many were read, zero
+    /// at the end of the file, along with the buffer.
+    pub async fn read_at(&self, buf: Vec<u8>, offset: u64) -> (io::Result<usize>, Vec<u8>) {
+        let op = Op::Read {
+            fd: Arc::clone(&self.fd),
+            buf,
+            offset,
+        };
+        match driver::submit(op).await {
+            (result, Some(Op::Read { buf, .. })) => (driver::result(result), buf),
+            (result, _) => (driver::result(result), Vec::new()),
+        }
+    }
+
+    /// Writes up to `buf.len()` bytes of `buf` at `offset`, and returns how many were written
+    /// along with the buffer.
+    pub async fn write_at(&self, buf: Vec<u8>, offset: u64) -> (io::Result<usize>, Vec<u8>) {
+        let op = Op::Write {
+            fd: Arc::clone(&self.fd),
+            buf,
+            offset,
+        };
+        match driver::submit(op).await {
+            (result, Some(Op::Write { buf, .. })) => (driver::result(result), buf),
+            (result, _) => (driver::result(result), Vec::new()),
+        }
+    }
+
+    /// Flushes the data and metadata of the file to its device.
+    pub async fn sync_all(&self) -> io::Result<()> {
+        let (result, _) = driver::submit(Op::Fsync {
+            fd: Arc::clone(&self.fd),
+        })
+        .await;
+        driver::result(result).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+
+    use core_affinity::CoreId;
+    use futures_lite::future;
+
+    use super::{File, OpenOptions};
+    use crate::{Runtime, Simulation};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("runtime-{name}-{}", process::id()))
+    }
+
+    /// Writes a chunk to `path` and reads part of it back.
+    async fn roundtrip(path: PathBuf) -> Vec<u8> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .await
+            .unwrap();
+        let (written, _) = file.write_at(b"hello chunk".to_vec(), 0).await;
+        assert_eq!(written.unwrap(), 11);
+        file.sync_all().await.unwrap();
+        let (read, mut buf) = File::open(&path).await.unwrap().read_at(vec![0; 8], 6).await;
+        buf.truncate(read.unwrap());
+        let missing = File::open(path.with_extension("missing")).await;
+        assert_eq!(missing.unwrap_err().kind(), ErrorKind::NotFound);
+        fs::remove_file(path).unwrap();
+        buf
+    }
+
+    /// Whether the kernel supports io_uring.
+    fn uring_supported() -> bool {
+        #[cfg(target_os = "linux")]
+        return crate::uring::Ring::new(1).is_ok();
+        #[cfg(not(target_os = "linux"))]
+        false
+    }
+
+    #[test]
+    fn test_file() {
+        for io_uring in [true, false] {
+            let cores = [CoreId { id: 0 }];
+            let mut runtime = Runtime::new(&cores).unwrap().with_io_uring(io_uring);
+            runtime.run();
+            let path = temp_path(&format!("file-{io_uring}"));
+            let task = runtime.spawn_to(0, || roundtrip(path));
+            assert_eq!(future::block_on(task).unwrap(), b"chunk");
+            if io_uring && !uring_supported() {
+                println!("skipped io_uring, the kernel doesn't support it");
+                continue;
+            }
+            assert_eq!(runtime.stats().executors[0].io_uring, io_uring);
+        }
+
+        let mut simulation = Simulation::new(1, 0);
+        let chunk = simulation.block_on(roundtrip(temp_path("file-simulation")));
+        assert_eq!(chunk, b"chunk");
+    }
+
+    #[test]
+    fn test_in_flight() {
+        let cores = [CoreId { id: 0 }];
+        let mut runtime = Runtime::new(&cores).unwrap();
+        runtime.run();
+        let path = temp_path("in-flight");
+        fs::write(&path, b"hello chunk").unwrap();
+        // more reads at once than the completion queue holds
+        let task = runtime.spawn_to(0, || async move {
+            let file = File::open(&path).await.unwrap();
+            let reads = (0..1000).map(|_| file.read_at(vec![0; 5], 6));
+            let reads = futures::future::join_all(reads).await;
+            fs::remove_file(path).unwrap();
+            reads
+                .into_iter()
+                .all(|(read, buf)| read.unwrap() == 5 && buf == b"chunk")
+        });
+        assert!(future::block_on(task).unwrap());
+    }
+}
@@ -0,0 +1,455 @@
+use std::cell::RefCell;
+#[cfg(target_os = "linux")]
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::time::Duration;
+
+use crate::blocking::BlockingPool;
+use crate::executor::{Context, CONTEXT};
+use crate::park::Parker;
+#[cfg(target_os = "linux")]
+use crate::uring::{self, Ring, Sqe};
+
+/// Entries of the io_uring of each executor, more operations per round are submitted in batches.
+#[cfg(target_os = "linux")]
+const RING_ENTRIES: u32 = 256;
+/// Longest an executor parks while the kernel did not take operations queued on its ring, their
+/// submission is retried then.
+pub(crate) const IO_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A file operation. It owns what the kernel reads or writes until it completes.
+#[derive(Debug)]
+pub(crate) enum Op {
+    Open {
+        path: CString,
+        flags: i32,
+        mode: u32,
+        /// Set once it completed, closed with the operation unless taken.
+        opened: Option<OwnedFd>,
+    },
+    Read {
+        fd: Arc<OwnedFd>,
+        buf: Vec<u8>,
+        offset: u64,
+    },
+    Write {
+        fd: Arc<OwnedFd>,
+        buf: Vec<u8>,
+        offset: u64,
+    },
+    Fsync {
+        fd: Arc<OwnedFd>,
+    },
+}
+
+/// Bytes of `buf` one operation transfers at most, as results are `i32`.
+#[inline]
+fn len(buf: &[u8]) -> usize {
+    buf.len().min(i32::MAX as usize)
+}
+
+impl Op {
+    /// Runs it with a blocking syscall, returns what io_uring would: the result or `-errno`.
+    fn run_blocking(&mut self) -> i32 {
+        let result = unsafe {
+            match self {
+                Op::Open { path, flags, mode, .. } => {
+                    libc::open(path.as_ptr(), *flags | libc::O_CLOEXEC, *mode as libc::c_uint) as isize
+                }
+                Op::Read { fd, buf, offset } => libc::pread(
+                    fd.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    len(buf),
+                    *offset as libc::off_t,
+                ),
+                Op::Write { fd, buf, offset } => libc::pwrite(
+                    fd.as_raw_fd(),
+                    buf.as_ptr() as *const libc::c_void,
+                    len(buf),
+                    *offset as libc::off_t,
+                ),
+                Op::Fsync { fd } => libc::fsync(fd.as_raw_fd()) as isize,
+            }
+        };
+        if result < 0 {
+            -io::Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)
+        } else {
+            result as i32
+        }
+    }
+
+    /// Takes ownership of what the operation created.
+    fn complete(&mut self, result: i32) {
+        if let Op::Open { opened, .. } = self {
+            if result >= 0 {
+                *opened = Some(unsafe { OwnedFd::from_raw_fd(result) });
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sqe(&mut self, user_data: u64) -> Sqe {
+        let sqe = Sqe {
+            user_data,
+            ..Default::default()
+        };
+        match self {
+            Op::Open { path, flags, mode, .. } => Sqe {
+                opcode: uring::OP_OPENAT,
+                fd: libc::AT_FDCWD,
+                addr: path.as_ptr() as u64,
+                len: *mode,
+                op_flags: (*flags | libc::O_CLOEXEC) as u32,
+                ..sqe
+            },
+            Op::Read { fd, buf, offset } => Sqe {
+                opcode: uring::OP_READ,
+                fd: fd.as_raw_fd(),
+                off: *offset,
+                addr: buf.as_mut_ptr() as u64,
+                len: len(buf) as u32,
+                ..sqe
+            },
+            Op::Write { fd, buf, offset } => Sqe {
+                opcode: uring::OP_WRITE,
+                fd: fd.as_raw_fd(),
+                off: *offset,
+                addr: buf.as_ptr() as u64,
+                len: len(buf) as u32,
+                ..sqe
+            },
+            Op::Fsync { fd } => Sqe {
+                opcode: uring::OP_FSYNC,
+                fd: fd.as_raw_fd(),
+                ..sqe
+            },
+        }
+    }
+}
+
+/// Converts the result of an operation.
+#[inline]
+pub(crate) fn result(result: i32) -> io::Result<usize> {
+    if result < 0 {
+        Err(io::Error::from_raw_os_error(-result))
+    } else {
+        Ok(result as usize)
+    }
+}
+
+/// Runs the file operations of an executor.
+#[derive(Debug)]
+pub(crate) enum Driver {
+    #[cfg(target_os = "linux")]
+    Uring(RefCell<UringDriver>),
+    /// Runs them on the blocking pool.
+    Blocking(Arc<BlockingPool>),
+    /// Runs them right away on the polling thread, as in a simulation.
+    Inline,
+}
+
+impl Driver {
+    /// An io_uring if `uring` is set and the kernel supports it, the blocking pool otherwise.
+    /// Completions on the ring unpark the executor through `parker`.
+    pub(crate) fn new(uring: bool, blocking: Arc<BlockingPool>, parker: &Parker) -> Self {
+        #[cfg(target_os = "linux")]
+        if uring {
+            if let Ok(ring) = Ring::new(RING_ENTRIES) {
+                if parker
+                    .eventfd()
+                    .and_then(|eventfd| ring.register_eventfd(eventfd))
+                    .is_ok()
+                {
+                    return Driver::Uring(RefCell::new(UringDriver::new(ring)));
+                }
+            }
+        }
+        #[cfg(not(target_os = "linux"))]
+        let _ = (uring, parker);
+        Driver::Blocking(blocking)
+    }
+
+    #[inline]
+    pub(crate) fn is_uring(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Driver::Uring(_) = self {
+            return true;
+        }
+        false
+    }
+
+    /// Submits the operations queued since the last call, and wakes the tasks of completed ones.
+    pub(crate) fn poll(&self) {
+        #[cfg(target_os = "linux")]
+        if let Driver::Uring(driver) = self {
+            driver.borrow_mut().poll();
+        }
+    }
+
+    /// Operations the kernel did not take yet, no completion unparks the executor for them.
+    pub(crate) fn unsubmitted(&self) -> usize {
+        #[cfg(target_os = "linux")]
+        if let Driver::Uring(driver) = self {
+            return driver.borrow().unsubmitted();
+        }
+        0
+    }
+}
+
+/// Runs `op` with the driver of the current executor. Returns its result as `-errno` on failure,
+/// and the operation to take its buffer back, which is gone if the runtime shut down meanwhile.
+pub(crate) async fn submit(mut op: Op) -> (i32, Option<Op>) {
+    let context = CONTEXT.with(|context| context.get().expect("files must be used within the runtime"));
+    match &context.io {
+        #[cfg(target_os = "linux")]
+        Driver::Uring(driver) => {
+            let slot = driver.borrow_mut().push(op);
+            Submitted {
+                context: Rc::clone(&context),
+                slot,
+                done: false,
+            }
+            .await
+        }
+        Driver::Blocking(pool) => {
+            let task = pool.spawn(move || {
+                let result = op.run_blocking();
+                op.complete(result);
+                (result, op)
+            });
+            match task.fallible().await {
+                Some(Ok((result, op))) => (result, Some(op)),
+                _ => (-libc::ECANCELED, None),
+            }
+        }
+        Driver::Inline => {
+            let result = op.run_blocking();
+            op.complete(result);
+            (result, Some(op))
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+enum Slot {
+    Free,
+    /// Waiting for room on the ring, in `queued`.
+    Queued {
+        op: Op,
+        waker: Option<Waker>,
+    },
+    Waiting {
+        op: Op,
+        waker: Option<Waker>,
+    },
+    Done {
+        op: Op,
+        result: i32,
+    },
+    /// Its future was dropped, the operation is dropped once it completes.
+    Abandoned(Op),
+}
+
+/// Operations of an executor on its io_uring, each in a slot named by its `user_data`.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub(crate) struct UringDriver {
+    ring: Ring,
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    /// Slots of operations not yet on the ring, oldest first.
+    queued: VecDeque<usize>,
+    in_flight: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl UringDriver {
+    fn new(ring: Ring) -> Self {
+        Self {
+            ring,
+            slots: Vec::new(),
+            free: Vec::new(),
+            queued: VecDeque::new(),
+            in_flight: 0,
+        }
+    }
+
+    /// Queues `op` for the next poll and returns its slot. It waits for room on the ring while as
+    /// many operations are in flight as the ring can complete.
+    fn push(&mut self, op: Op) -> usize {
+        let slot = self.free.pop().unwrap_or_else(|| {
+            self.slots.push(Slot::Free);
+            self.slots.len() - 1
+        });
+        self.slots[slot] = Slot::Queued { op, waker: None };
+        self.queued.push_back(slot);
+        self.fill();
+        slot
+    }
+
+    /// Moves queued operations onto the ring while it has room for them and their completions.
+    fn fill(&mut self) {
+        while self.in_flight < self.ring.cq_entries() as usize {
+            let Some(&slot) = self.queued.front() else {
+                return;
+            };
+            let Slot::Queued { op, .. } = &mut self.slots[slot] else {
+                unreachable!("queued slot without an operation");
+            };
+            let sqe = op.sqe(slot as u64);
+            // the submission queue is full, the kernel takes what is queued and makes room
+            if !self.ring.push(sqe) && (self.ring.submit(0).is_err() || !self.ring.push(sqe)) {
+                return;
+            }
+            self.queued.pop_front();
+            if let Slot::Queued { op, waker } = std::mem::replace(&mut self.slots[slot], Slot::Free) {
+                self.slots[slot] = Slot::Waiting { op, waker };
+            }
+            self.in_flight += 1;
+        }
+    }
+
+    fn poll(&mut self) {
+        if self.in_flight == 0 && self.queued.is_empty() {
+            return;
+        }
+        // completions make room for queued operations
+        self.complete();
+        self.fill();
+        // retried next round if the kernel is short of resources
+        let _ = self.ring.submit(0);
+        self.complete();
+    }
+
+    /// Operations the kernel did not take, and queued ones while no completion makes room for them.
+    fn unsubmitted(&self) -> usize {
+        let queued = if self.in_flight == 0 { self.queued.len() } else { 0 };
+        self.ring.unsubmitted() as usize + queued
+    }
+
+    fn complete(&mut self) {
+        let (slots, free) = (&mut self.slots, &mut self.free);
+        let mut completed = 0;
+        self.ring.complete(|user_data, result| {
+            completed += 1;
+            let slot = &mut slots[user_data as usize];
+            match std::mem::replace(slot, Slot::Free) {
+                Slot::Waiting { mut op, waker } => {
+                    op.complete(result);
+                    *slot = Slot::Done { op, result };
+                    // schedules the task on this executor
+                    waker.into_iter().for_each(Waker::wake);
+                }
+                Slot::Abandoned(mut op) => {
+                    op.complete(result);
+                    free.push(user_data as usize);
+                }
+                Slot::Free | Slot::Queued { .. } | Slot::Done { .. } => unreachable!("completion of an idle slot"),
+            }
+        });
+        self.in_flight -= completed;
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for UringDriver {
+    /// Waits for the operations in flight, as the kernel may still access their buffers.
+    fn drop(&mut self) {
+        while self.in_flight > 0 {
+            match self.ring.submit(1) {
+                Ok(()) => self.complete(),
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    // better leaked than freed under the kernel
+                    std::mem::forget(std::mem::take(&mut self.slots));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Future of an operation on the ring of the executor that pushed it.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct Submitted {
+    context: Rc<Context>,
+    slot: usize,
+    /// Its slot was freed, and may hold another operation.
+    done: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl Submitted {
+    fn driver(&self) -> &RefCell<UringDriver> {
+        match &self.context.io {
+            Driver::Uring(driver) => driver,
+            _ => unreachable!("submitted without a ring"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Future for Submitted {
+    type Output = (i32, Option<Op>);
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        let poll = {
+            let mut driver = self.driver().borrow_mut();
+            let driver = &mut *driver;
+            let slot = &mut driver.slots[self.slot];
+            match std::mem::replace(slot, Slot::Free) {
+                Slot::Done { op, result } => {
+                    driver.free.push(self.slot);
+                    Poll::Ready((result, Some(op)))
+                }
+                Slot::Queued { op, .. } => {
+                    *slot = Slot::Queued {
+                        op,
+                        waker: Some(cx.waker().clone()),
+                    };
+                    Poll::Pending
+                }
+                Slot::Waiting { op, .. } => {
+                    *slot = Slot::Waiting {
+                        op,
+                        waker: Some(cx.waker().clone()),
+                    };
+                    Poll::Pending
+                }
+                Slot::Free | Slot::Abandoned(_) => unreachable!("polled after completion"),
+            }
+        };
+        self.done = poll.is_ready();
+        poll
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Submitted {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        let mut driver = self.driver().borrow_mut();
+        let driver = &mut *driver;
+        match std::mem::replace(&mut driver.slots[self.slot], Slot::Free) {
+            Slot::Waiting { op, .. } => driver.slots[self.slot] = Slot::Abandoned(op),
+            // never reached the kernel
+            Slot::Queued { .. } => {
+                driver.queued.retain(|&slot| slot != self.slot);
+                driver.free.push(self.slot);
+            }
+            // completed but never taken
+            _ => driver.free.push(self.slot),
+        }
+    }
+}
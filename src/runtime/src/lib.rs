@@ -2,27 +2,67 @@
 #![feature(once_cell)]
 #![feature(can_vector)]
 
+mod blocking;
+mod cgroup;
+pub mod channel;
+mod class;
 pub mod error;
 mod executor;
+pub mod fs;
+mod io;
+mod park;
+mod sim;
+mod stats;
+mod steal;
+mod timer;
+#[cfg(target_os = "linux")]
+mod uring;
 
 use std::future::Future;
 use std::sync::Arc;
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use async_task::{Runnable, Task};
-use concurrent_queue::ConcurrentQueue;
+pub use async_task::Task;
+use blocking::{BlockingPool, MAX_BLOCKING_THREADS};
+pub use class::SchedulingClass;
+use class::{ClassQueues, CLASSES};
 pub use core_affinity::{get_core_ids, CoreId};
-use error::RuntimeError;
-pub use executor::spawn_local;
-use executor::Executor;
+use error::{RuntimeError, ShutdownSnafu};
+use executor::{catch_panic, Executor, SPIN_BEFORE_PARK};
+pub use executor::{spawn, spawn_local};
 use futures::channel::oneshot;
 use futures_lite::future;
+use io::Driver;
+use park::Parker;
+pub use sim::Simulation;
+use snafu::ensure;
+use stats::Metrics;
+pub use stats::{ExecutorStats, RuntimeStats};
+pub use steal::StealCounters;
+use steal::Stealing;
+pub use timer::{interval, sleep, sleep_until, timeout, Interval, Sleep};
+
+/// Time executors get after the deadline of [`Runtime::shutdown`] to finish their running task.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(100);
 
 #[derive(Debug)]
 pub struct Runtime {
-    cores: Vec<CoreId>,
+    /// Core of each executor, `None` if it runs unpinned.
+    cores: Vec<Option<CoreId>>,
     executors: Vec<ExecutorHandler>,
-    global_tasks: Arc<ConcurrentQueue<Runnable>>,
+    global_tasks: Arc<ClassQueues>,
+    /// One per core, unparked when a task is scheduled to it.
+    parkers: Vec<Arc<Parker>>,
+    spin_before_park: usize,
+    weights: [usize; CLASSES],
+    work_stealing: bool,
+    /// One per core, only used with work stealing.
+    stealable: Vec<Arc<ClassQueues>>,
+    steal_counters: Vec<Arc<StealCounters>>,
+    metrics: Vec<Arc<Metrics>>,
+    blocking: Arc<BlockingPool>,
+    io_uring: bool,
 }
 
 unsafe impl Send for Runtime {}
@@ -46,63 +86,274 @@ impl Runtime {
                     has: inuse.len(),
                 });
             }
-            inuse.push(*require);
+            inuse.push(Some(*require));
+        }
+        Ok(Self::with_cores(inuse))
+    }
+
+    /// Runs `executors` threads the OS schedules on any core, for where cores can't be pinned.
+    pub fn unpinned(executors: usize) -> Self {
+        Self::with_cores(vec![None; executors])
+    }
+
+    /// Runs an executor per CPU the process may use, as limited by its affinity and cgroup CPU
+    /// quota, as in containers. They are pinned to the first cores if those can be listed.
+    pub fn from_available_parallelism() -> Self {
+        let executors = cgroup::available_cpus();
+        match get_core_ids() {
+            Some(cores) if cores.len() >= executors => {
+                Self::with_cores(cores.into_iter().take(executors).map(Some).collect())
+            }
+            _ => Self::unpinned(executors),
         }
-        Ok(Self {
-            cores: inuse,
+    }
+
+    fn with_cores(cores: Vec<Option<CoreId>>) -> Self {
+        Self {
+            parkers: cores.iter().map(|_| Arc::new(Parker::new())).collect(),
+            stealable: cores.iter().map(|_| Arc::new(ClassQueues::new())).collect(),
+            steal_counters: cores.iter().map(|_| Arc::default()).collect(),
+            metrics: cores.iter().map(|_| Arc::default()).collect(),
+            blocking: Arc::new(BlockingPool::new(MAX_BLOCKING_THREADS)),
+            cores,
             executors: Vec::new(),
-            global_tasks: Arc::new(ConcurrentQueue::unbounded()),
-        })
+            global_tasks: Arc::new(ClassQueues::new()),
+            spin_before_park: SPIN_BEFORE_PARK,
+            weights: SchedulingClass::ALL.map(SchedulingClass::default_weight),
+            work_stealing: false,
+            io_uring: true,
+        }
+    }
+
+    /// Lets idle executors run `Send` tasks that were [`spawn`]ed on busy ones. Tasks spawned with
+    /// [`Runtime::spawn_to`] or [`spawn_local`] always stay on their core. Must be set before [`Runtime::run`].
+    #[inline]
+    pub fn with_work_stealing(mut self, enabled: bool) -> Self {
+        self.work_stealing = enabled;
+        self
+    }
+
+    /// Counters of what executor `id` stole, they stay zero without work stealing.
+    #[inline]
+    pub fn steal_counters(&self, id: usize) -> &StealCounters {
+        &self.steal_counters[id]
+    }
+
+    /// Rounds of an executor finding no task before it parks its thread until one is scheduled.
+    /// Higher values trade idle CPU for latency, must be set before [`Runtime::run`].
+    #[inline]
+    pub fn with_spin_before_park(mut self, spins: usize) -> Self {
+        self.spin_before_park = spins;
+        self
+    }
+
+    /// Share of `class` in the tasks an executor runs per round while other classes have work
+    /// too. Latency weighs 4 and batch 1 by default, must be set before [`Runtime::run`].
+    #[inline]
+    pub fn with_class_weight(mut self, class: SchedulingClass, weight: usize) -> Self {
+        self.weights[class.index()] = weight;
+        self
+    }
+
+    /// Bound of the threads of [`Runtime::spawn_blocking`], 64 by default. Must be set before
+    /// spawning blocking work.
+    #[inline]
+    pub fn with_max_blocking_threads(mut self, threads: usize) -> Self {
+        self.blocking = Arc::new(BlockingPool::new(threads));
+        self
     }
 
+    /// Runs file operations on an io_uring per executor where the kernel supports it, which is the
+    /// default, or on the blocking pool. Must be set before [`Runtime::run`].
+    #[inline]
+    pub fn with_io_uring(mut self, enabled: bool) -> Self {
+        self.io_uring = enabled;
+        self
+    }
+
+    /// Starts the executors. One that can't be pinned to its core runs unpinned, as reported by
+    /// [`ExecutorStats::pinned`].
     pub fn run(&mut self) {
-        for &id in &self.cores {
-            let assigned = Arc::new(ConcurrentQueue::unbounded());
+        for (index, (&core, parker)) in self.cores.iter().zip(&self.parkers).enumerate() {
+            let assigned = Arc::new(ClassQueues::new());
             let local_assigned = Arc::clone(&assigned);
             let local_global = Arc::clone(&self.global_tasks);
-            let (closer, recv) = oneshot::channel::<()>();
+            let local_parker = Arc::clone(parker);
+            let parkers = self.parkers.clone();
+            let metrics = Arc::clone(&self.metrics[index]);
+            let spin_before_park = self.spin_before_park;
+            let weights = self.weights;
+            let stealing = self.work_stealing.then(|| Stealing {
+                id: index,
+                queues: self.stealable.clone(),
+                counters: Arc::clone(&self.steal_counters[index]),
+            });
+            let io_uring = self.io_uring;
+            let blocking = Arc::clone(&self.blocking);
+            let (closer, recv) = oneshot::channel::<Instant>();
             let join = thread::spawn(move || {
-                core_affinity::set_for_current(id);
-                let mut ex = Executor::new(local_global, local_assigned);
-                future::block_on(ex.run(async move {
-                    recv.await.unwrap();
-                }));
+                metrics.set_pinned(core.is_some_and(pin));
+                // the ring is set up on the thread that uses it
+                let io = Driver::new(io_uring, blocking, &local_parker);
+                let mut ex = Executor::new(
+                    local_global,
+                    local_assigned,
+                    local_parker,
+                    parkers,
+                    stealing,
+                    metrics,
+                    io,
+                )
+                .with_spin_before_park(spin_before_park)
+                .with_weights(weights);
+                let deadline = future::block_on(ex.run(async move { recv.await.unwrap_or_else(|_| Instant::now()) }));
+                ex.drain(deadline);
             });
 
             self.executors.push(ExecutorHandler { join, assigned, closer });
         }
     }
 
-    pub fn spawn<T: Send + Sync + 'static>(&self, future: impl Future<Output = T> + Send + Sync + 'static) -> Task<T> {
+    /// Snapshot of what every executor did and has queued, counters only grow.
+    pub fn stats(&self) -> RuntimeStats {
+        let executors = self
+            .metrics
+            .iter()
+            .enumerate()
+            .map(|(id, metrics)| ExecutorStats {
+                stolen_tasks: self.steal_counters[id].stolen(),
+                assigned_depth: self.executors.get(id).map_or(0, |ex| ex.assigned.total_len()),
+                stealable_depth: self.stealable[id].total_len(),
+                ..metrics.snapshot()
+            })
+            .collect();
+        let (blocking_threads, blocking_depth) = self.blocking.load();
+        RuntimeStats {
+            global_depth: self.global_tasks.total_len(),
+            blocking_threads,
+            blocking_depth,
+            executors,
+        }
+    }
+
+    /// Number of cores the runtime runs on, valid ids of [`Runtime::spawn_to`] are below it.
+    #[inline]
+    pub fn cores(&self) -> usize {
+        self.cores.len()
+    }
+
+    /// Spawns a task of the default [`SchedulingClass`] on any executor. Its result is
+    /// [`RuntimeError::Panicked`] if it panics, the executor keeps running.
+    pub fn spawn<T: Send + Sync + 'static>(
+        &self,
+        future: impl Future<Output = T> + Send + Sync + 'static,
+    ) -> Task<Result<T, RuntimeError>> {
+        self.spawn_with_class(SchedulingClass::default(), future)
+    }
+
+    pub fn spawn_with_class<T: Send + Sync + 'static>(
+        &self,
+        class: SchedulingClass,
+        future: impl Future<Output = T> + Send + Sync + 'static,
+    ) -> Task<Result<T, RuntimeError>> {
         let global_tasks = Arc::clone(&self.global_tasks);
+        let parkers = self.parkers.clone();
         let schedule = move |runnable| {
-            global_tasks.push(runnable).unwrap();
+            global_tasks.push(class, runnable);
+            // any idle executor may take it
+            for parker in &parkers {
+                parker.unpark();
+            }
         };
-        let (runnable, task) = unsafe { async_task::spawn_unchecked(future, schedule) };
+        let (runnable, task) = unsafe { async_task::spawn_unchecked(catch_panic(future), schedule) };
         runnable.schedule();
         task
     }
 
+    /// Spawns a task of the default [`SchedulingClass`] on executor `id`.
     pub fn spawn_to<T: Send + 'static, F: Future<Output = T> + 'static>(
         &self,
         id: usize,
         future: impl (FnOnce() -> F) + Send,
-    ) -> Task<T> {
+    ) -> Task<Result<T, RuntimeError>> {
+        self.spawn_to_with_class(id, SchedulingClass::default(), future)
+    }
+
+    pub fn spawn_to_with_class<T: Send + 'static, F: Future<Output = T> + 'static>(
+        &self,
+        id: usize,
+        class: SchedulingClass,
+        future: impl (FnOnce() -> F) + Send,
+    ) -> Task<Result<T, RuntimeError>> {
         let assigned = Arc::clone(&self.executors[id].assigned);
+        let parker = Arc::clone(&self.parkers[id]);
         let schedule = move |runnable| {
-            assigned.push(runnable).unwrap();
+            assigned.push(class, runnable);
+            parker.unpark();
         };
-        let (runnable, task) = unsafe { async_task::spawn_unchecked(future(), schedule) };
+        let (runnable, task) = unsafe { async_task::spawn_unchecked(catch_panic(future()), schedule) };
         runnable.schedule();
         task
     }
+
+    /// Runs `f` on a thread of the blocking pool, so that work like decompression, fsync or
+    /// regex compilation doesn't stall the tasks of an executor. The pool is unpinned and bounded,
+    /// see [`Runtime::with_max_blocking_threads`].
+    pub fn spawn_blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce() -> T + Send + 'static,
+    ) -> Task<Result<T, RuntimeError>> {
+        self.blocking.spawn(f)
+    }
+
+    /// Stops the executors. Each runs its queued tasks until there are none or `deadline` passed,
+    /// and cancels the rest. Tasks waiting on anything else are never resumed. Fails if executors
+    /// panicked, or are still stuck in a task a grace period after `deadline`, their threads are
+    /// left detached then.
+    pub fn shutdown(mut self, deadline: Instant) -> Result<(), RuntimeError> {
+        let joins = self.close(deadline);
+        let stop_before = deadline.max(Instant::now()) + SHUTDOWN_GRACE;
+        let mut failed: usize = 0;
+        for join in joins {
+            while !join.is_finished() && Instant::now() < stop_before {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if !join.is_finished() || join.join().is_err() {
+                failed += 1;
+            }
+        }
+        ensure!(failed == 0, ShutdownSnafu { executors: failed });
+        Ok(())
+    }
+
+    /// Signals every executor to drain until `deadline` and stop, and cancels queued blocking work.
+    fn close(&mut self, deadline: Instant) -> Vec<JoinHandle<()>> {
+        self.blocking.close();
+        self.executors
+            .drain(..)
+            .zip(&self.parkers)
+            .map(|(ex, parker)| {
+                // the executor is gone if it panicked, its join reports that
+                let _ = ex.closer.send(deadline);
+                // a parked executor only sees the closer once woken
+                parker.unpark();
+                ex.join
+            })
+            .collect()
+    }
+}
+
+/// Pins the current thread to `core`, `false` if its affinity isn't exactly `core` afterwards.
+fn pin(core: CoreId) -> bool {
+    core_affinity::set_for_current(core);
+    matches!(get_core_ids().as_deref(), Some([pinned]) if pinned.id == core.id)
 }
 
 impl Drop for Runtime {
+    /// Cancels the queued tasks and waits for the executors to stop.
     fn drop(&mut self) {
-        for ex in self.executors.drain(..) {
-            ex.closer.send(()).unwrap();
-            ex.join.join().unwrap();
+        for join in self.close(Instant::now()) {
+            let _ = join.join();
         }
     }
 }
@@ -110,18 +361,22 @@ impl Drop for Runtime {
 #[derive(Debug)]
 struct ExecutorHandler {
     join: JoinHandle<()>,
-    assigned: Arc<ConcurrentQueue<Runnable>>,
-    closer: oneshot::Sender<()>,
+    assigned: Arc<ClassQueues>,
+    closer: oneshot::Sender<Instant>,
 }
 
 #[cfg(test)]
 mod tests {
-    use super::spawn_local;
+    use super::{interval, sleep, spawn, spawn_local, timeout};
+    use crate::error::RuntimeError;
     use crate::Runtime;
     use core_affinity::CoreId;
     use futures_lite::future;
     use std::cell::Cell;
     use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::{Duration, Instant};
 
     #[test]
     fn test_runtime() {
@@ -131,8 +386,214 @@ mod tests {
         future::block_on(runtime.spawn_to(0, || async {
             let printable = Rc::new(Cell::new(1));
             let p_clone = Rc::clone(&printable);
-            spawn_local(async { p_clone.set(p_clone.get() + 1) }).await;
+            spawn_local(async { p_clone.set(p_clone.get() + 1) }).await.unwrap();
             println!("{}", printable.get());
-        }));
+        }))
+        .unwrap();
+    }
+
+    #[test]
+    fn test_unpinned() {
+        let mut runtime = Runtime::unpinned(2);
+        runtime.run();
+        assert_eq!(future::block_on(runtime.spawn_to(1, || async { 1 })).unwrap(), 1);
+        assert!(!runtime.stats().executors[1].pinned);
+
+        let mut runtime = Runtime::from_available_parallelism();
+        runtime.run();
+        assert!(runtime.cores() >= 1);
+        assert_eq!(future::block_on(runtime.spawn(async { 2 })).unwrap(), 2);
+
+        // beyond any CPU set, the executor runs unpinned
+        let mut runtime = Runtime::with_cores(vec![Some(CoreId { id: 0 }), Some(CoreId { id: 1023 })]);
+        runtime.run();
+        assert_eq!(future::block_on(runtime.spawn_to(0, || async { 3 })).unwrap(), 3);
+        assert_eq!(future::block_on(runtime.spawn_to(1, || async { 4 })).unwrap(), 4);
+        let pinned = runtime
+            .stats()
+            .executors
+            .iter()
+            .map(|executor| executor.pinned)
+            .collect::<Vec<_>>();
+        assert_eq!(pinned, vec![true, false]);
+    }
+
+    #[test]
+    fn test_stats() {
+        let cores = (0..2).map(|_| CoreId { id: 0 }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap().with_spin_before_park(0);
+        runtime.run();
+        let task = runtime.spawn_to(1, || async {
+            spawn_local(async { thread::sleep(Duration::from_millis(10)) })
+                .await
+                .unwrap();
+        });
+        future::block_on(task).unwrap();
+        future::block_on(runtime.spawn(async {})).unwrap();
+        let stats = runtime.stats();
+        assert_eq!(stats.global_depth, 0);
+        let executor = &stats.executors[1];
+        // the assigned task ran again once the local one woke it
+        assert_eq!((executor.local_tasks, executor.assigned_tasks), (1, 2));
+        assert_eq!(
+            stats
+                .executors
+                .iter()
+                .map(|executor| executor.global_tasks)
+                .sum::<u64>(),
+            1
+        );
+        assert!(executor.poll_time >= executor.max_poll_time);
+    }
+
+    #[test]
+    fn test_spawn_blocking() {
+        let cores = (0..1).map(|id| CoreId { id }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap().with_max_blocking_threads(1);
+        runtime.run();
+        let (release, released) = mpsc::channel();
+        let blocking = runtime.spawn_blocking(move || released.recv().unwrap());
+        // queued behind the first, as the pool has a single thread
+        let queued = runtime.spawn_blocking(|| 2);
+        let failed = runtime.spawn_blocking(|| panic!("boom"));
+        // the executor isn't stalled meanwhile, the blocking work waits for it
+        future::block_on(runtime.spawn_to(0, || async {})).unwrap();
+        assert_eq!(runtime.stats().blocking_threads, 1);
+        release.send(1).unwrap();
+
+        let task = runtime.spawn_to(0, || async move { blocking.await.unwrap() + queued.await.unwrap() });
+        assert_eq!(future::block_on(task).unwrap(), 3);
+        assert!(matches!(future::block_on(failed), Err(RuntimeError::Panicked { .. })));
+    }
+
+    #[test]
+    fn test_park() {
+        let cores = (0..2).map(|_| CoreId { id: 0 }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap().with_spin_before_park(0);
+        runtime.run();
+        // both executors are parked by now, scheduling must wake them
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(future::block_on(runtime.spawn_to(1, || async { 1 })).unwrap(), 1);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(future::block_on(runtime.spawn(async { 2 })).unwrap(), 2);
+        let task = runtime.spawn_to(0, || async {
+            let local = spawn_local(async {
+                futures_lite::future::yield_now().await;
+                3
+            });
+            local.await.unwrap()
+        });
+        assert_eq!(future::block_on(task).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_spawn_woken_elsewhere() {
+        let cores = (0..2).map(|_| CoreId { id: 0 }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap().with_spin_before_park(0);
+        runtime.run();
+        let (sender, receiver) = futures::channel::oneshot::channel();
+        // without stealing the spawned task is queued globally, waking it must unpark an executor
+        let task = runtime.spawn_to(0, || async { spawn(async { receiver.await.unwrap() }).await.unwrap() });
+        thread::sleep(Duration::from_millis(50));
+        sender.send(1).unwrap();
+        assert_eq!(future::block_on(task).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_work_stealing() {
+        let cores = (0..2).map(|_| CoreId { id: 0 }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap().with_work_stealing(true);
+        runtime.run();
+        let task = runtime.spawn_to(0, || async {
+            let tasks = (0..8)
+                .map(|i| {
+                    spawn(async move {
+                        thread::sleep(Duration::from_millis(5));
+                        i
+                    })
+                })
+                .collect::<Vec<_>>();
+            let mut sum = 0;
+            for task in tasks {
+                sum += task.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(future::block_on(task).unwrap(), 28);
+        // the spawning task itself stays on its core, only the other one steals
+        assert_eq!(runtime.steal_counters(0).stolen(), 0);
+    }
+
+    #[test]
+    fn test_timer() {
+        let cores = (0..1).map(|id| CoreId { id }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap().with_spin_before_park(0);
+        runtime.run();
+        let task = runtime.spawn_to(0, || async {
+            let start = Instant::now();
+            sleep(Duration::from_millis(20)).await;
+            assert!(start.elapsed() >= Duration::from_millis(20));
+
+            let mut ticks = interval(Duration::from_millis(10));
+            let first = ticks.tick().await;
+            ticks.tick().await;
+            // ticks keep their phase, late ones are skipped
+            let third = ticks.tick().await - first;
+            assert!(third >= Duration::from_millis(20) && third.as_millis() % 10 == 0);
+
+            assert_eq!(timeout(Duration::from_millis(10), async { 1 }).await.unwrap(), 1);
+            let pending = timeout(Duration::from_millis(10), future::pending::<()>()).await;
+            assert!(matches!(pending, Err(RuntimeError::Timeout { .. })));
+        });
+        future::block_on(task).unwrap();
+    }
+
+    #[test]
+    fn test_panic() {
+        let cores = (0..1).map(|id| CoreId { id }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap();
+        runtime.run();
+        let task = runtime.spawn_to(0, || async {
+            let local = spawn_local(async { panic!("boom") }).await;
+            assert!(matches!(local, Err(RuntimeError::Panicked { message }) if message == "boom"));
+            panic!("{}", 42)
+        });
+        assert!(matches!(future::block_on(task), Err(RuntimeError::Panicked { message }) if message == "42"));
+        // the executor survived
+        assert_eq!(future::block_on(runtime.spawn_to(0, || async { 1 })).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let cores = (0..2).map(|_| CoreId { id: 0 }).collect::<Vec<_>>();
+        let mut runtime = Runtime::new(&cores).unwrap();
+        runtime.run();
+        let queued = (0..1000)
+            .map(|i| runtime.spawn_to(i % 2, move || async move { i }))
+            .collect::<Vec<_>>();
+        // queued tasks run until the deadline, the executor-level drain checks cancelling
+        runtime.shutdown(Instant::now() + Duration::from_secs(3600)).unwrap();
+        let sum = queued
+            .into_iter()
+            .map(|task| future::block_on(task).unwrap())
+            .sum::<usize>();
+        assert_eq!(sum, 999 * 1000 / 2);
+
+        let mut runtime = Runtime::new(&cores).unwrap();
+        runtime.run();
+        let (started, running) = mpsc::channel();
+        let (release, released) = mpsc::channel::<()>();
+        runtime
+            .spawn_to(1, move || async move {
+                started.send(()).unwrap();
+                let _ = released.recv();
+            })
+            .detach();
+        running.recv().unwrap();
+        assert!(matches!(
+            runtime.shutdown(Instant::now()),
+            Err(RuntimeError::Shutdown { executors: 1 })
+        ));
+        release.send(()).unwrap();
     }
 }
@@ -0,0 +1,109 @@
+#[cfg(target_os = "linux")]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::OnceLock;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(target_os = "linux")]
+use std::{io, ptr};
+
+/// Parks the thread of an idle executor until a task is scheduled to it or its next timer is due.
+#[derive(Debug, Default)]
+pub(crate) struct Parker {
+    notified: Mutex<bool>,
+    condvar: Condvar,
+    /// Set for an executor with an io_uring, which signals it on completions. The executor then
+    /// parks polling it, and `unpark` signals it too.
+    #[cfg(target_os = "linux")]
+    eventfd: OnceLock<OwnedFd>,
+    /// The executor is parked polling the eventfd.
+    #[cfg(target_os = "linux")]
+    polling: AtomicBool,
+}
+
+impl Parker {
+    pub(crate) fn new() -> Self {
+        Default::default()
+    }
+
+    /// The eventfd the executor parks on from now on, created on first use.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn eventfd(&self) -> io::Result<RawFd> {
+        if let Some(eventfd) = self.eventfd.get() {
+            return Ok(eventfd.as_raw_fd());
+        }
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // only the executor thread sets it
+        let eventfd = self.eventfd.get_or_init(|| unsafe { OwnedFd::from_raw_fd(fd) });
+        Ok(eventfd.as_raw_fd())
+    }
+
+    /// Wakes the executor if parked, or makes its next `park` return at once.
+    pub(crate) fn unpark(&self) {
+        *self.notified.lock().unwrap() = true;
+        self.condvar.notify_one();
+        #[cfg(target_os = "linux")]
+        if self.polling.load(Ordering::SeqCst) {
+            if let Some(eventfd) = self.eventfd.get() {
+                let one = 1u64;
+                unsafe { libc::write(eventfd.as_raw_fd(), &one as *const u64 as *const libc::c_void, 8) };
+            }
+        }
+    }
+
+    /// Blocks until `unpark` was called since the last `park` returned, or `timeout` passed.
+    /// With an eventfd, a completion on the ring of the executor returns as well.
+    pub(crate) fn park(&self, timeout: Option<Duration>) {
+        #[cfg(target_os = "linux")]
+        if let Some(eventfd) = self.eventfd.get() {
+            self.park_eventfd(eventfd, timeout);
+            return;
+        }
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut notified = self.notified.lock().unwrap();
+        while !*notified {
+            notified = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return;
+                    }
+                    self.condvar.wait_timeout(notified, deadline - now).unwrap().0
+                }
+                None => self.condvar.wait(notified).unwrap(),
+            };
+        }
+        *notified = false;
+    }
+
+    #[cfg(target_os = "linux")]
+    fn park_eventfd(&self, eventfd: &OwnedFd, timeout: Option<Duration>) {
+        // an `unpark` after the check sees `polling` and signals the eventfd
+        self.polling.store(true, Ordering::SeqCst);
+        if !std::mem::take(&mut *self.notified.lock().unwrap()) {
+            let timeout = timeout.map(|timeout| libc::timespec {
+                tv_sec: libc::time_t::try_from(timeout.as_secs()).unwrap_or(libc::time_t::MAX),
+                tv_nsec: timeout.subsec_nanos() as libc::c_long,
+            });
+            let mut pollfd = libc::pollfd {
+                fd: eventfd.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout = timeout
+                .as_ref()
+                .map_or(ptr::null(), |timeout| timeout as *const libc::timespec);
+            unsafe { libc::ppoll(&mut pollfd, 1, timeout, ptr::null()) };
+            // resets it, what it counted is handled by the next round
+            let mut count = 0u64;
+            unsafe { libc::read(eventfd.as_raw_fd(), &mut count as *mut u64 as *mut libc::c_void, 8) };
+            *self.notified.lock().unwrap() = false;
+        }
+        self.polling.store(false, Ordering::SeqCst);
+    }
+}
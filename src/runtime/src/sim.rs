@@ -0,0 +1,400 @@
+use std::future::Future;
+use std::pin::pin;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use async_task::Task;
+use futures::task::noop_waker;
+
+use crate::class::{ClassQueues, SchedulingClass};
+use crate::error::RuntimeError;
+use crate::executor::{catch_panic, Context, CONTEXT};
+use crate::io::Driver;
+use crate::park::Parker;
+use crate::stats::{ExecutorStats, Queue, RuntimeStats};
+use crate::steal::Stealing;
+use crate::timer;
+
+/// Runs executors on the calling thread, for tests that must reproduce an interleaving exactly.
+/// Each step runs one queued task of one executor, or lets an idle executor steal, both picked by
+/// a scheduler seeded with `seed`. Timers run on a virtual clock, which jumps to the next deadline
+/// once no task is queued. No thread is started and nothing is pinned. Tasks use
+/// [`crate::spawn_local`], [`crate::spawn`], timers and channels as on a [`crate::Runtime`]. File
+/// operations complete as soon as they are polled.
+#[derive(Debug)]
+pub struct Simulation {
+    executors: Vec<Rc<Context>>,
+    global: Arc<ClassQueues>,
+    start: Instant,
+    /// State of the splitmix64 generator.
+    state: u64,
+}
+
+impl Simulation {
+    pub fn new(executors: usize, seed: u64) -> Self {
+        assert!(executors > 0, "a simulation needs an executor");
+        let start = Instant::now();
+        timer::set_virtual_now(Some(start));
+        let global = Arc::new(ClassQueues::new());
+        let executors = Self::contexts(executors, &global, false);
+        let previous = CONTEXT.with(|current| current.replace(Some(Rc::clone(&executors[0]))));
+        assert!(previous.is_none(), "a thread runs a single executor");
+        Self {
+            executors,
+            global,
+            start,
+            state: seed,
+        }
+    }
+
+    /// Lets an executor without tasks of its own steal those [`crate::spawn`]ed on the others, as
+    /// [`crate::Runtime::with_work_stealing`]. Must be set before spawning tasks.
+    pub fn with_work_stealing(mut self, enabled: bool) -> Self {
+        self.executors = Self::contexts(self.executors.len(), &self.global, enabled);
+        CONTEXT.with(|current| current.replace(Some(Rc::clone(&self.executors[0]))));
+        self
+    }
+
+    fn contexts(executors: usize, global: &Arc<ClassQueues>, stealing: bool) -> Vec<Rc<Context>> {
+        let stealable = (0..executors).map(|_| Arc::new(ClassQueues::new())).collect::<Vec<_>>();
+        (0..executors)
+            .map(|id| {
+                let stealing = stealing.then(|| Stealing {
+                    id,
+                    queues: stealable.clone(),
+                    counters: Arc::default(),
+                });
+                Rc::new(Context::new(
+                    Arc::clone(global),
+                    Arc::new(ClassQueues::new()),
+                    Arc::new(Parker::new()),
+                    vec![],
+                    stealing,
+                    Arc::default(),
+                    Driver::Inline,
+                ))
+            })
+            .collect()
+    }
+
+    #[inline]
+    pub fn executors(&self) -> usize {
+        self.executors.len()
+    }
+
+    /// Virtual time passed since the simulation started.
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        timer::now() - self.start
+    }
+
+    pub fn spawn<T: 'static>(&self, future: impl Future<Output = T> + 'static) -> Task<Result<T, RuntimeError>> {
+        let global = Arc::clone(&self.global);
+        let schedule = move |runnable| global.push(SchedulingClass::default(), runnable);
+        // the simulation isn't `Send`, so its tasks stay on this thread
+        let (runnable, task) = unsafe { async_task::spawn_unchecked(catch_panic(future), schedule) };
+        runnable.schedule();
+        task
+    }
+
+    pub fn spawn_to<T: 'static, F: Future<Output = T> + 'static>(
+        &self,
+        id: usize,
+        future: impl FnOnce() -> F,
+    ) -> Task<Result<T, RuntimeError>> {
+        let assigned = Arc::clone(&self.executors[id].assigned);
+        let schedule = move |runnable| assigned.push(SchedulingClass::default(), runnable);
+        let (runnable, task) = unsafe { async_task::spawn_unchecked(catch_panic(future()), schedule) };
+        runnable.schedule();
+        task
+    }
+
+    /// Runs a single task, or the tasks an idle executor steals at once, or advances the clock to
+    /// the next timer if none is queued. Returns `false` once there is neither.
+    pub fn step(&mut self) -> bool {
+        let now = timer::now();
+        for context in &self.executors {
+            let expired = context.timers.lock().unwrap().advance(now);
+            expired.into_iter().for_each(Waker::wake);
+        }
+        // `None` steals from the siblings
+        let mut queued = Vec::new();
+        for (id, context) in self.executors.iter().enumerate() {
+            let own = queued.len();
+            for class in SchedulingClass::ALL {
+                for queue in [Queue::Local, Queue::Assigned, Queue::Global, Queue::Stealable] {
+                    if context.queued(queue, class) > 0 {
+                        queued.push((id, Some((queue, class))));
+                    }
+                }
+            }
+            // like an executor, only an idle one steals
+            if let Some(stealing) = context.stealing.as_ref().filter(|_| queued.len() == own) {
+                let mut siblings = stealing.queues.iter().enumerate().filter(|(sibling, _)| *sibling != id);
+                if siblings.any(|(_, queue)| queue.total_len() > 0) {
+                    queued.push((id, None));
+                }
+            }
+        }
+        if queued.is_empty() {
+            let next = self
+                .executors
+                .iter()
+                .filter_map(|context| context.timers.lock().unwrap().next_deadline())
+                .min();
+            return match next {
+                Some(deadline) => {
+                    timer::set_virtual_now(Some(deadline.max(now)));
+                    true
+                }
+                None => false,
+            };
+        }
+        let (id, pick) = queued[self.next_random() as usize % queued.len()];
+        let context = &self.executors[id];
+        CONTEXT.with(|current| current.replace(Some(Rc::clone(context))));
+        match (pick, &context.stealing) {
+            (Some((queue, class)), _) => {
+                context.class.set(class);
+                if let Some(runnable) = context.pop(queue, class) {
+                    context.metrics.record_task(queue);
+                    context.run(runnable);
+                }
+            }
+            (None, Some(stealing)) => {
+                stealing.steal(|class, runnable| {
+                    context.class.set(class);
+                    context.run(runnable);
+                });
+            }
+            (None, None) => unreachable!("only executors with work stealing steal"),
+        }
+        true
+    }
+
+    /// Snapshot of what every executor did and has queued, as [`crate::Runtime::stats`]. Only
+    /// task counts are meaningful, the virtual clock stands still while tasks run.
+    pub fn stats(&self) -> RuntimeStats {
+        let executors = self
+            .executors
+            .iter()
+            .map(|context| ExecutorStats {
+                stolen_tasks: context
+                    .stealing
+                    .as_ref()
+                    .map_or(0, |stealing| stealing.counters.stolen()),
+                assigned_depth: context.assigned.total_len(),
+                stealable_depth: context
+                    .stealing
+                    .as_ref()
+                    .map_or(0, |stealing| stealing.own().total_len()),
+                ..context.metrics.snapshot()
+            })
+            .collect();
+        RuntimeStats {
+            global_depth: self.global.total_len(),
+            executors,
+            ..Default::default()
+        }
+    }
+
+    /// Steps until no task is queued and no timer is pending.
+    pub fn run(&mut self) {
+        while self.step() {}
+    }
+
+    /// Steps until `future` completes, it is polled on executor 0 after every step.
+    ///
+    /// # Panics
+    ///
+    /// If `future` is pending while no task and timer is left, which would never complete.
+    pub fn block_on<T>(&mut self, future: impl Future<Output = T>) -> T {
+        let mut future = pin!(future);
+        let waker = noop_waker();
+        let mut cx = TaskContext::from_waker(&waker);
+        loop {
+            CONTEXT.with(|current| current.replace(Some(Rc::clone(&self.executors[0]))));
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            assert!(self.step(), "simulation deadlocked, no task or timer is left");
+        }
+    }
+
+    /// splitmix64, good enough to shuffle a schedule and trivially reproducible.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        CONTEXT.with(|current| current.replace(None));
+        // queued tasks are cancelled with the executors, their timers may still look at the clock
+        self.executors.clear();
+        timer::set_virtual_now(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use futures_lite::future;
+
+    use super::Simulation;
+    use crate::channel::bounded;
+    use crate::error::RuntimeError;
+    use crate::timer::now;
+    use crate::{interval, sleep, spawn, spawn_local, timeout};
+
+    #[test]
+    fn test_runtime() {
+        let mut simulation = Simulation::new(2, 3);
+        let task = simulation.spawn_to(1, || async {
+            let count = Rc::new(Cell::new(1));
+            let local = Rc::clone(&count);
+            spawn_local(async move {
+                future::yield_now().await;
+                local.set(local.get() + 1)
+            })
+            .await
+            .unwrap();
+            spawn(async { 2 }).await.unwrap() + count.get()
+        });
+        assert_eq!(simulation.block_on(task).unwrap(), 4);
+        let task = simulation.spawn(async { 5 });
+        assert_eq!(simulation.block_on(task).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut simulation = Simulation::new(2, 0);
+        let task = simulation.spawn_to(1, || async { spawn_local(async {}).await.unwrap() });
+        simulation.block_on(task).unwrap();
+        let task = simulation.spawn(async {});
+        simulation.block_on(task).unwrap();
+        let stats = simulation.stats();
+        assert_eq!(stats.global_depth, 0);
+        let executor = &stats.executors[1];
+        // the assigned task ran again once the local one woke it
+        assert_eq!((executor.local_tasks, executor.assigned_tasks), (1, 2));
+        assert_eq!(
+            stats
+                .executors
+                .iter()
+                .map(|executor| executor.global_tasks)
+                .sum::<u64>(),
+            1
+        );
+        assert!(executor.poll_time >= executor.max_poll_time);
+    }
+
+    #[test]
+    fn test_work_stealing() {
+        let mut simulation = Simulation::new(2, 0).with_work_stealing(true);
+        let task = simulation.spawn_to(0, || async {
+            let tasks = (0..8).map(|i| spawn(async move { i })).collect::<Vec<_>>();
+            let mut sum = 0;
+            for task in tasks {
+                sum += task.await.unwrap();
+            }
+            sum
+        });
+        assert_eq!(simulation.block_on(task).unwrap(), 28);
+        let stats = simulation.stats();
+        // the spawning task itself stays on its executor, the idle one steals the spawned ones
+        assert!(stats.executors[1].stolen_tasks > 0);
+        assert_eq!(stats.executors[0].stolen_tasks, 0);
+        assert_eq!(stats.executors[0].stealable_depth, 0);
+    }
+
+    #[test]
+    fn test_timer() {
+        let mut simulation = Simulation::new(1, 0);
+        let task = simulation.spawn_to(0, || async {
+            let start = now();
+            sleep(Duration::from_millis(20)).await;
+            assert_eq!(now() - start, Duration::from_millis(20));
+
+            let mut ticks = interval(Duration::from_millis(10));
+            let first = ticks.tick().await;
+            assert_eq!(ticks.tick().await - first, Duration::from_millis(10));
+            sleep(Duration::from_millis(25)).await;
+            // a late tick completes at once, missed ones are skipped and later ones keep their phase
+            assert_eq!(ticks.tick().await - first, Duration::from_millis(20));
+            assert_eq!(ticks.tick().await - first, Duration::from_millis(40));
+
+            assert_eq!(timeout(Duration::from_millis(10), async { 1 }).await.unwrap(), 1);
+            let start = now();
+            let pending = timeout(Duration::from_millis(10), future::pending::<()>()).await;
+            assert!(matches!(pending, Err(RuntimeError::Timeout { .. })));
+            assert_eq!(now() - start, Duration::from_millis(10));
+        });
+        simulation.block_on(task).unwrap();
+    }
+
+    /// Order in which the steps of tasks spread over executors ran.
+    fn interleaving(seed: u64) -> Vec<(usize, usize)> {
+        let mut simulation = Simulation::new(3, seed);
+        let order = Rc::new(RefCell::new(vec![]));
+        for task in 0..6 {
+            let order = Rc::clone(&order);
+            simulation
+                .spawn_to(task % 3, move || async move {
+                    for step in 0..3 {
+                        order.borrow_mut().push((task, step));
+                        future::yield_now().await;
+                    }
+                })
+                .detach();
+        }
+        simulation.run();
+        order.take()
+    }
+
+    #[test]
+    fn test_deterministic() {
+        assert_eq!(interleaving(7), interleaving(7));
+        assert_eq!(interleaving(7).len(), 18);
+        assert!((0..8).any(|seed| interleaving(seed) != interleaving(7)));
+    }
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut simulation = Simulation::new(2, 1);
+        let slept = simulation.spawn_to(1, || async {
+            sleep(Duration::from_secs(3600)).await;
+            let local = spawn_local(timeout(Duration::from_secs(1), sleep(Duration::from_secs(60))));
+            local.await.unwrap()
+        });
+        let result = simulation.block_on(slept).unwrap();
+        assert!(matches!(result, Err(RuntimeError::Timeout { .. })));
+        assert_eq!(simulation.elapsed(), Duration::from_secs(3601));
+
+        let (sender, mut receiver) = bounded(1);
+        simulation
+            .spawn_to(0, move || async move {
+                for i in 0..10 {
+                    sender.send(i).await.unwrap();
+                }
+            })
+            .detach();
+        let sum = simulation.spawn_to(1, move || async move {
+            let mut sum = 0;
+            while let Some(i) = receiver.recv().await {
+                sum += i;
+            }
+            sum
+        });
+        assert_eq!(simulation.block_on(sum).unwrap(), 45);
+    }
+}
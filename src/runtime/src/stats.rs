@@ -0,0 +1,147 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Counters an executor updates as it runs, snapshotted by [`crate::Runtime::stats`].
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    local_tasks: AtomicU64,
+    assigned_tasks: AtomicU64,
+    global_tasks: AtomicU64,
+    stealable_tasks: AtomicU64,
+    local_depth: AtomicUsize,
+    poll_nanos: AtomicU64,
+    max_poll_nanos: AtomicU64,
+    busy_nanos: AtomicU64,
+    idle_nanos: AtomicU64,
+    io_uring: AtomicBool,
+    pinned: AtomicBool,
+}
+
+/// Queue of an executor a task was taken from.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Queue {
+    Local,
+    Assigned,
+    Global,
+    Stealable,
+}
+
+impl Metrics {
+    /// Counts a task taken from `queue`.
+    #[inline]
+    pub(crate) fn record_task(&self, queue: Queue) {
+        let counter = match queue {
+            Queue::Local => &self.local_tasks,
+            Queue::Assigned => &self.assigned_tasks,
+            Queue::Global => &self.global_tasks,
+            Queue::Stealable => &self.stealable_tasks,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn record_poll(&self, elapsed: Duration) {
+        let nanos = elapsed.as_nanos() as u64;
+        self.poll_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_poll_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Counts a round of the executor loop, busy if it ran any task.
+    #[inline]
+    pub(crate) fn record_round(&self, busy: bool, elapsed: Duration) {
+        let counter = if busy { &self.busy_nanos } else { &self.idle_nanos };
+        counter.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn record_park(&self, elapsed: Duration) {
+        self.record_round(false, elapsed);
+    }
+
+    /// Local tasks are only visible to their executor, so it publishes their number.
+    #[inline]
+    pub(crate) fn set_local_depth(&self, depth: usize) {
+        self.local_depth.store(depth, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn set_io_uring(&self, io_uring: bool) {
+        self.io_uring.store(io_uring, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn set_pinned(&self, pinned: bool) {
+        self.pinned.store(pinned, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ExecutorStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let duration = |counter: &AtomicU64| Duration::from_nanos(counter.load(Ordering::Relaxed));
+        ExecutorStats {
+            local_tasks: load(&self.local_tasks),
+            assigned_tasks: load(&self.assigned_tasks),
+            global_tasks: load(&self.global_tasks),
+            stealable_tasks: load(&self.stealable_tasks),
+            local_depth: self.local_depth.load(Ordering::Relaxed),
+            poll_time: duration(&self.poll_nanos),
+            max_poll_time: duration(&self.max_poll_nanos),
+            busy_time: duration(&self.busy_nanos),
+            idle_time: duration(&self.idle_nanos),
+            io_uring: self.io_uring.load(Ordering::Relaxed),
+            pinned: self.pinned.load(Ordering::Relaxed),
+            ..Default::default()
+        }
+    }
+}
+
+/// What an executor did since it started, and what is queued on it. Tasks are counted each time
+/// they run, which is once per wake.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExecutorStats {
+    /// Tasks run from the queue of `spawn_local`.
+    pub local_tasks: u64,
+    /// Tasks run from the queue of `Runtime::spawn_to`.
+    pub assigned_tasks: u64,
+    /// Tasks run from the queue shared by all executors.
+    pub global_tasks: u64,
+    /// Tasks run from its own stealable queue.
+    pub stealable_tasks: u64,
+    /// Tasks run from the stealable queues of other executors.
+    pub stolen_tasks: u64,
+    /// Tasks queued at the snapshot, local ones as of the last round of the executor.
+    pub local_depth: usize,
+    pub assigned_depth: usize,
+    pub stealable_depth: usize,
+    /// Time spent polling tasks.
+    pub poll_time: Duration,
+    /// Longest poll of a task, long ones block the other tasks of the executor.
+    pub max_poll_time: Duration,
+    /// Time of rounds of the executor loop that ran tasks.
+    pub busy_time: Duration,
+    /// Time of rounds that found no task, and parked.
+    pub idle_time: Duration,
+    /// Whether its file operations run on an io_uring rather than the blocking pool.
+    pub io_uring: bool,
+    /// Whether its thread is pinned to its core, one that failed to pin runs unpinned.
+    pub pinned: bool,
+}
+
+impl ExecutorStats {
+    #[inline]
+    pub fn tasks(&self) -> u64 {
+        self.local_tasks + self.assigned_tasks + self.global_tasks + self.stealable_tasks + self.stolen_tasks
+    }
+}
+
+/// Snapshot of [`crate::Runtime::stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeStats {
+    /// Tasks queued for any executor.
+    pub global_depth: usize,
+    /// Threads of the blocking pool.
+    pub blocking_threads: usize,
+    /// Blocking work waiting for a thread.
+    pub blocking_depth: usize,
+    /// By executor id.
+    pub executors: Vec<ExecutorStats>,
+}
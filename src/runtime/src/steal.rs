@@ -0,0 +1,78 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use async_task::Runnable;
+
+use crate::class::{ClassQueues, SchedulingClass};
+use crate::park::Parker;
+
+/// How much an executor stole from its siblings.
+#[derive(Debug, Default)]
+pub struct StealCounters {
+    attempts: AtomicU64,
+    stolen: AtomicU64,
+}
+
+impl StealCounters {
+    /// Idle rounds in which the executor looked for tasks of its siblings.
+    #[inline]
+    pub fn attempts(&self) -> u64 {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    /// Tasks taken from siblings and run.
+    #[inline]
+    pub fn stolen(&self) -> u64 {
+        self.stolen.load(Ordering::Relaxed)
+    }
+}
+
+/// The stealable queues of all executors of a runtime, as seen by executor `id`. Only `Send` tasks
+/// spawned from within an executor are stealable, tasks bound to a core never enter these queues.
+#[derive(Debug)]
+pub(crate) struct Stealing {
+    pub(crate) id: usize,
+    pub(crate) queues: Vec<Arc<ClassQueues>>,
+    pub(crate) counters: Arc<StealCounters>,
+}
+
+impl Stealing {
+    #[inline]
+    pub(crate) fn own(&self) -> &ClassQueues {
+        &self.queues[self.id]
+    }
+
+    /// Queues `runnable` on this executor and wakes every executor, idle ones may steal it.
+    pub(crate) fn push(queue: &ClassQueues, class: SchedulingClass, parkers: &[Arc<Parker>], runnable: Runnable) {
+        queue.push(class, runnable);
+        for parker in parkers {
+            parker.unpark();
+        }
+    }
+
+    /// Runs up to half of the tasks of the first class of the first sibling that has any with
+    /// `run`, returns how many ran.
+    pub(crate) fn steal(&self, mut run: impl FnMut(SchedulingClass, Runnable)) -> usize {
+        self.counters.attempts.fetch_add(1, Ordering::Relaxed);
+        let len = self.queues.len();
+        let victims = (1..len).map(|offset| &self.queues[(self.id + offset) % len]);
+        for (victim, class) in victims.flat_map(|victim| SchedulingClass::ALL.map(|class| (victim, class))) {
+            let count = victim.len(class).div_ceil(2);
+            let mut ran = 0;
+            for _ in 0..count {
+                match victim.pop(class) {
+                    Some(runnable) => {
+                        run(class, runnable);
+                        ran += 1;
+                    }
+                    None => break,
+                }
+            }
+            if ran > 0 {
+                self.counters.stolen.fetch_add(ran as u64, Ordering::Relaxed);
+                return ran;
+            }
+        }
+        0
+    }
+}
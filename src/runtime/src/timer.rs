@@ -0,0 +1,275 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use futures_lite::future;
+
+use crate::error::RuntimeError;
+use crate::executor::CONTEXT;
+
+thread_local! {
+    /// Time of the simulation running on this thread.
+    static VIRTUAL_NOW: Cell<Option<Instant>> = const { Cell::new(None) }
+}
+
+/// Time as seen by timers, virtual within a simulation.
+#[inline]
+pub(crate) fn now() -> Instant {
+    VIRTUAL_NOW.with(Cell::get).unwrap_or_else(Instant::now)
+}
+
+/// Switches timers of this thread to a virtual clock at `now`, or back to real time.
+pub(crate) fn set_virtual_now(now: Option<Instant>) {
+    VIRTUAL_NOW.with(|virtual_now| virtual_now.set(now));
+}
+
+const SLOTS: usize = 256;
+const TICK: Duration = Duration::from_millis(1);
+/// Deadline of sleeps too long for an [`Instant`], roughly 30 years away.
+const FAR_FUTURE: Duration = Duration::from_secs(86400 * 365 * 30);
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    tick: u64,
+    waker: Waker,
+}
+
+/// Where a timer is in the wheel, to cancel it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TimerKey {
+    id: u64,
+    tick: u64,
+}
+
+/// Hashed timer wheel of one executor with millisecond ticks. Timers further out than one
+/// revolution share slots with nearer ones and are skipped until their tick is reached.
+#[derive(Debug)]
+pub(crate) struct TimerWheel {
+    origin: Instant,
+    /// Ticks since `origin` whose timers fired already.
+    elapsed: u64,
+    slots: Vec<Vec<Entry>>,
+    next_id: u64,
+    len: usize,
+}
+
+impl TimerWheel {
+    pub(crate) fn new(origin: Instant) -> Self {
+        Self {
+            origin,
+            elapsed: 0,
+            slots: (0..SLOTS).map(|_| Vec::new()).collect(),
+            next_id: 0,
+            len: 0,
+        }
+    }
+
+    /// First tick not before `deadline`.
+    fn tick_of(&self, deadline: Instant) -> u64 {
+        let nanos = deadline.saturating_duration_since(self.origin).as_nanos();
+        let tick = TICK.as_nanos();
+        u64::try_from(nanos.div_ceil(tick)).unwrap_or(u64::MAX)
+    }
+
+    pub(crate) fn insert(&mut self, deadline: Instant, waker: Waker) -> TimerKey {
+        let key = TimerKey {
+            id: self.next_id,
+            tick: self.tick_of(deadline).max(self.elapsed + 1),
+        };
+        self.next_id += 1;
+        self.len += 1;
+        self.slots[key.tick as usize % SLOTS].push(Entry {
+            id: key.id,
+            tick: key.tick,
+            waker,
+        });
+        key
+    }
+
+    pub(crate) fn remove(&mut self, key: TimerKey) {
+        let slot = &mut self.slots[key.tick as usize % SLOTS];
+        if let Some(position) = slot.iter().position(|entry| entry.id == key.id) {
+            slot.swap_remove(position);
+            self.len -= 1;
+        }
+    }
+
+    /// Removes the timers due at `now` and returns their wakers.
+    pub(crate) fn advance(&mut self, now: Instant) -> Vec<Waker> {
+        let now = (now.saturating_duration_since(self.origin).as_nanos() / TICK.as_nanos()) as u64;
+        let mut wakers = Vec::new();
+        if now <= self.elapsed || self.len == 0 {
+            self.elapsed = self.elapsed.max(now);
+            return wakers;
+        }
+        let ticks = (now - self.elapsed).min(SLOTS as u64);
+        for tick in self.elapsed + 1..=self.elapsed + ticks {
+            let slot = &mut self.slots[tick as usize % SLOTS];
+            let mut i = 0;
+            while i < slot.len() {
+                if slot[i].tick <= now {
+                    wakers.push(slot.swap_remove(i).waker);
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        self.len -= wakers.len();
+        self.elapsed = now;
+        wakers
+    }
+
+    /// Earliest deadline of all timers, `None` if there is none or it can't be represented.
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        if self.len == 0 {
+            return None;
+        }
+        let tick = self.slots.iter().flatten().map(|entry| entry.tick).min()?;
+        // a tick is a millisecond
+        self.origin.checked_add(Duration::from_millis(tick))
+    }
+}
+
+/// Future of [`sleep`] and [`sleep_until`]. It registers with the timer wheel of the executor
+/// polling it, so it must be polled within the runtime.
+#[derive(Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    timer: Option<(Arc<Mutex<TimerWheel>>, TimerKey)>,
+}
+
+impl Sleep {
+    #[inline]
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    fn cancel(&mut self) {
+        if let Some((wheel, key)) = self.timer.take() {
+            wheel.lock().unwrap().remove(key);
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<()> {
+        if now() >= self.deadline {
+            self.cancel();
+            return Poll::Ready(());
+        }
+        // the task may have a new waker, or moved to another executor by stealing
+        self.cancel();
+        let wheel = CONTEXT.with(|context| {
+            let context = context.get().expect("timers must be polled within the runtime");
+            Arc::clone(&context.timers)
+        });
+        let key = wheel.lock().unwrap().insert(self.deadline, cx.waker().clone());
+        self.timer = Some((wheel, key));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+/// Completes after `duration`, durations too long for an [`Instant`] sleep for about 30 years.
+pub fn sleep(duration: Duration) -> Sleep {
+    let now = now();
+    sleep_until(now.checked_add(duration).unwrap_or(now + FAR_FUTURE))
+}
+
+/// Completes at `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, timer: None }
+}
+
+/// Ticks every `period`, see [`interval`].
+#[derive(Debug)]
+pub struct Interval {
+    next: Instant,
+    period: Duration,
+}
+
+impl Interval {
+    /// Waits for the next tick and returns when it was due. Ticks missed by a slow consumer are
+    /// skipped, later ticks keep their phase.
+    pub async fn tick(&mut self) -> Instant {
+        sleep_until(self.next).await;
+        let tick = self.next;
+        let now = now();
+        while self.next <= now {
+            self.next += self.period;
+        }
+        tick
+    }
+}
+
+/// Ticks every `period`, the first tick completes at once.
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::ZERO, "interval period must be positive");
+    Interval { next: now(), period }
+}
+
+/// Runs `future` to completion, or fails with [`RuntimeError::Timeout`] if it takes longer than `duration`.
+pub async fn timeout<T>(duration: Duration, future: impl Future<Output = T>) -> Result<T, RuntimeError> {
+    future::or(async { Ok(future.await) }, async {
+        sleep(duration).await;
+        Err(RuntimeError::Timeout { duration })
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Waker;
+    use std::time::{Duration, Instant};
+
+    use futures::task::noop_waker;
+
+    use super::{sleep, TimerWheel};
+
+    #[test]
+    fn test_timer_wheel() {
+        let origin = Instant::now();
+        let mut wheel = TimerWheel::new(origin);
+        let waker: Waker = noop_waker();
+        let at = |millis: u64| origin + Duration::from_millis(millis);
+        wheel.insert(at(5), waker.clone());
+        let cancelled = wheel.insert(at(7), waker.clone());
+        // a revolution later, in the same slot as the first
+        wheel.insert(at(5 + 256), waker.clone());
+        wheel.insert(at(1000), waker);
+        assert_eq!(wheel.next_deadline(), Some(at(5)));
+
+        assert_eq!(wheel.advance(at(4)).len(), 0);
+        assert_eq!(wheel.advance(at(5)).len(), 1);
+        wheel.remove(cancelled);
+        assert_eq!(wheel.next_deadline(), Some(at(261)));
+        assert_eq!(wheel.advance(at(260)).len(), 0);
+        assert_eq!(wheel.advance(at(900)).len(), 1);
+        // more than a revolution at once
+        assert_eq!(wheel.advance(at(5000)).len(), 1);
+        assert_eq!(wheel.next_deadline(), None);
+
+        // ticks beyond `u32::MAX`
+        let far = at(u64::from(u32::MAX) + 5);
+        wheel.insert(far, noop_waker());
+        assert_eq!(wheel.next_deadline(), Some(far));
+        assert_eq!(wheel.advance(at(6000)).len(), 0);
+    }
+
+    #[test]
+    fn test_sleep_saturates() {
+        let start = Instant::now();
+        assert!(sleep(Duration::MAX).deadline() > start + Duration::from_secs(86400 * 365));
+    }
+}
@@ -0,0 +1,271 @@
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub(crate) const OP_FSYNC: u8 = 3;
+pub(crate) const OP_OPENAT: u8 = 18;
+pub(crate) const OP_READ: u8 = 22;
+pub(crate) const OP_WRITE: u8 = 23;
+
+const FEAT_SINGLE_MMAP: u32 = 1 << 0;
+/// Since Linux 5.6, along with `OP_OPENAT`, `OP_READ` and `OP_WRITE`.
+const FEAT_RW_CUR_POS: u32 = 1 << 3;
+const ENTER_GETEVENTS: u32 = 1 << 0;
+const REGISTER_EVENTFD: u32 = 4;
+const OFF_SQ_RING: i64 = 0;
+const OFF_SQES: i64 = 0x1000_0000;
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+/// Submission queue entry, as `struct io_uring_sqe`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Sqe {
+    pub(crate) opcode: u8,
+    pub(crate) flags: u8,
+    pub(crate) ioprio: u16,
+    pub(crate) fd: i32,
+    pub(crate) off: u64,
+    pub(crate) addr: u64,
+    pub(crate) len: u32,
+    pub(crate) op_flags: u32,
+    pub(crate) user_data: u64,
+    pub(crate) buf_index: u16,
+    pub(crate) personality: u16,
+    pub(crate) splice_fd_in: i32,
+    pub(crate) addr3: u64,
+    pub(crate) pad: u64,
+}
+
+/// Completion queue entry, as `struct io_uring_cqe`.
+#[repr(C)]
+#[derive(Debug)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// An io_uring of one executor, without any kernel polling thread. Requires Linux 5.6.
+#[derive(Debug)]
+pub(crate) struct Ring {
+    fd: OwnedFd,
+    map: *mut u8,
+    map_len: usize,
+    sqes: *mut Sqe,
+    sqes_len: usize,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_entries: u32,
+    sq_array: *mut u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    /// Entries queued but not yet taken by the kernel.
+    unsubmitted: u32,
+}
+
+fn mmap(fd: RawFd, len: usize, offset: i64) -> io::Result<*mut u8> {
+    let map = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED | libc::MAP_POPULATE,
+            fd,
+            offset,
+        )
+    };
+    if map == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(map as *mut u8)
+}
+
+impl Ring {
+    pub(crate) fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(libc::SYS_io_uring_setup, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+        let required = FEAT_SINGLE_MMAP | FEAT_RW_CUR_POS;
+        if params.features & required != required {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring requires Linux 5.6",
+            ));
+        }
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * size_of::<u32>();
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        // both rings share one mapping
+        let map_len = sq_len.max(cq_len);
+        let map = mmap(fd.as_raw_fd(), map_len, OFF_SQ_RING)?;
+        let sqes_len = params.sq_entries as usize * size_of::<Sqe>();
+        let sqes = match mmap(fd.as_raw_fd(), sqes_len, OFF_SQES) {
+            Ok(sqes) => sqes as *mut Sqe,
+            Err(error) => {
+                unsafe { libc::munmap(map as *mut libc::c_void, map_len) };
+                return Err(error);
+            }
+        };
+        let at = |offset: u32| unsafe { map.add(offset as usize) };
+        unsafe {
+            Ok(Self {
+                sq_head: at(params.sq_off.head) as *const AtomicU32,
+                sq_tail: at(params.sq_off.tail) as *const AtomicU32,
+                sq_mask: *(at(params.sq_off.ring_mask) as *const u32),
+                sq_entries: params.sq_entries,
+                sq_array: at(params.sq_off.array) as *mut u32,
+                cq_head: at(params.cq_off.head) as *const AtomicU32,
+                cq_tail: at(params.cq_off.tail) as *const AtomicU32,
+                cq_mask: *(at(params.cq_off.ring_mask) as *const u32),
+                cqes: at(params.cq_off.cqes) as *const Cqe,
+                fd,
+                map,
+                map_len,
+                sqes,
+                sqes_len,
+                unsubmitted: 0,
+            })
+        }
+    }
+
+    /// Completions the ring holds, more operations in flight overflow it.
+    #[inline]
+    pub(crate) fn cq_entries(&self) -> u32 {
+        self.cq_mask + 1
+    }
+
+    /// Entries queued but not yet taken by the kernel.
+    #[inline]
+    pub(crate) fn unsubmitted(&self) -> u32 {
+        self.unsubmitted
+    }
+
+    /// Makes the kernel signal `eventfd` on every completion.
+    pub(crate) fn register_eventfd(&self, eventfd: RawFd) -> io::Result<()> {
+        let result = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_register,
+                self.fd.as_raw_fd(),
+                REGISTER_EVENTFD,
+                &eventfd as *const RawFd,
+                1u32,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Queues `sqe` for the next [`Ring::submit`], `false` if the submission queue is full.
+    pub(crate) fn push(&mut self, sqe: Sqe) -> bool {
+        unsafe {
+            let head = (*self.sq_head).load(Ordering::Acquire);
+            // only this thread writes the tail
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            if tail.wrapping_sub(head) == self.sq_entries {
+                return false;
+            }
+            let index = tail & self.sq_mask;
+            *self.sqes.add(index as usize) = sqe;
+            *self.sq_array.add(index as usize) = index;
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.unsubmitted += 1;
+        true
+    }
+
+    /// Hands the queued entries to the kernel, and waits for `wait` completions.
+    pub(crate) fn submit(&mut self, wait: u32) -> io::Result<()> {
+        let flags = if wait > 0 { ENTER_GETEVENTS } else { 0 };
+        let submitted = unsafe {
+            libc::syscall(
+                libc::SYS_io_uring_enter,
+                self.fd.as_raw_fd(),
+                self.unsubmitted,
+                wait,
+                flags,
+                ptr::null::<libc::sigset_t>(),
+                0usize,
+            )
+        };
+        if submitted < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        self.unsubmitted -= submitted as u32;
+        Ok(())
+    }
+
+    /// Passes `user_data` and result of every completion to `f`.
+    pub(crate) fn complete(&mut self, mut f: impl FnMut(u64, i32)) {
+        unsafe {
+            // only this thread writes the head
+            let mut head = (*self.cq_head).load(Ordering::Relaxed);
+            let tail = (*self.cq_tail).load(Ordering::Acquire);
+            while head != tail {
+                let cqe = &*self.cqes.add((head & self.cq_mask) as usize);
+                f(cqe.user_data, cqe.res);
+                head = head.wrapping_add(1);
+            }
+            (*self.cq_head).store(head, Ordering::Release);
+        }
+    }
+}
+
+impl Drop for Ring {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.sqes as *mut libc::c_void, self.sqes_len);
+            libc::munmap(self.map as *mut libc::c_void, self.map_len);
+        }
+    }
+}
@@ -1,29 +1,381 @@
 use std::fmt::Debug;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
 
-use crate::column::MutableChunk;
+use rustc_hash::FxHasher;
+use snafu::{ensure, Snafu};
 
-// sharding over table or table cover sharding?
+use crate::column::{
+    ChunkMeta, FieldImpl, FieldMeta, FieldValue, ImmutableChunk, InsertError, LabelImpl, LabelMeta, LabelValue,
+    MutableChunk,
+};
+use crate::common::{Duration, Instant};
 
-#[derive(Debug)]
-struct TableShard {
-    mutable_chunks: Vec<MutableChunk>,
+#[derive(Snafu, Debug)]
+pub enum TableError {
+    #[snafu(display("time interval: {:?} must be positive", time_interval))]
+    InvalidTimeInterval { time_interval: Duration },
+    #[snafu(display("series length must be positive"))]
+    EmptySeries,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct TableMeta {
+    pub labels: Vec<LabelMeta>,
+    pub fields: Vec<FieldMeta>,
+    /// Interval between two samples of a series.
+    pub time_interval: Duration,
+    /// Number of samples of a series in one chunk.
+    pub series_len: u32,
+}
+
+impl TableMeta {
+    /// Chunks must span some time, so that every timestamp maps to one of them.
+    fn validate(&self) -> Result<(), TableError> {
+        ensure!(
+            self.time_interval.as_millis() > 0,
+            InvalidTimeIntervalSnafu {
+                time_interval: self.time_interval
+            }
+        );
+        ensure!(self.series_len > 0, EmptySeriesSnafu);
+        Ok(())
+    }
+
+    /// Start of the chunk that `timestamp` falls into, chunks are aligned to their own span.
+    #[inline]
+    fn chunk_start(&self, timestamp: Instant) -> Instant {
+        let span = (self.time_interval * self.series_len).as_millis();
+        Instant::from_millis(timestamp.as_millis() - timestamp.as_millis().rem_euclid(span))
+    }
+
+    fn create_chunk(&self, start_at: Instant) -> MutableChunk {
+        MutableChunk::new(
+            self.labels.iter().map(LabelImpl::new).collect(),
+            self.fields
+                .iter()
+                .map(|field| FieldImpl::new(field, self.series_len))
+                .collect(),
+            ChunkMeta::new(start_at, self.time_interval, self.series_len),
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct TableShard {
+    pub(crate) mutable_chunks: Vec<MutableChunk>,
+    pub(crate) immutable_chunks: Vec<Arc<ImmutableChunk>>,
 }
 
+impl TableShard {
+    /// `None` when the chunk of `start_at` is sealed already.
+    fn chunk_mut(&mut self, meta: &TableMeta, start_at: Instant) -> Option<&mut MutableChunk> {
+        if self
+            .immutable_chunks
+            .iter()
+            .any(|chunk| chunk.meta.start_at == start_at)
+        {
+            return None;
+        }
+        let position = match self
+            .mutable_chunks
+            .iter()
+            .position(|chunk| chunk.meta.start_at == start_at)
+        {
+            Some(position) => position,
+            None => {
+                self.mutable_chunks.push(meta.create_chunk(start_at));
+                self.mutable_chunks.len() - 1
+            }
+        };
+        Some(&mut self.mutable_chunks[position])
+    }
+
+    fn seal(&mut self, watermark: Instant) {
+        let (closed, open) = std::mem::take(&mut self.mutable_chunks)
+            .into_iter()
+            .partition::<Vec<_>, _>(|chunk| chunk.meta.window_end() <= watermark);
+        self.mutable_chunks = open;
+        self.immutable_chunks
+            .extend(closed.into_iter().map(|chunk| Arc::new(chunk.freeze())));
+    }
+}
+
+/// Series are hash-partitioned over the shards of a table by their label fingerprint, every shard
+/// is owned by one core of the runtime, see [`Executor`](crate::executor::Executor).
 #[derive(Debug)]
 pub struct Table {
     name: String,
-    shards: Vec<TableShard>,
+    meta: TableMeta,
+    shards: Vec<RwLock<TableShard>>,
 }
 
 impl PartialEq for Table {
     fn eq(&self, other: &Self) -> bool {
-        self as *const Self == other as *const Self
+        std::ptr::eq(self, other)
     }
 }
 
 impl Table {
+    pub fn new(name: String, meta: TableMeta) -> Result<Self, TableError> {
+        Self::with_shards(name, meta, 1)
+    }
+
+    pub fn with_shards(name: String, meta: TableMeta, shards: usize) -> Result<Self, TableError> {
+        meta.validate()?;
+        Ok(Self {
+            name,
+            meta,
+            shards: (0..shards.max(1)).map(|_| RwLock::new(TableShard::default())).collect(),
+        })
+    }
+
     #[inline]
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    #[inline]
+    pub fn meta(&self) -> &TableMeta {
+        &self.meta
+    }
+
+    #[inline]
+    pub(crate) fn shards(&self) -> &[RwLock<TableShard>] {
+        &self.shards
+    }
+
+    /// Shard of the series identified by `labels`, independent of their order. The hash has fixed
+    /// keys, so a series keeps its shard across processes and toolchains.
+    pub fn shard_of(&self, labels: &[(&str, LabelValue<'_>)]) -> usize {
+        let mut labels = labels.to_vec();
+        labels.sort_by_key(|(name, _)| *name);
+        let mut hasher = FxHasher::default();
+        labels.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    /// Writes the field values sampled at `timestamp` into the series identified by `labels`.
+    /// Samples of a sealed chunk are rejected as out of range. It runs on the core owning the
+    /// shard, [`Executor::insert`](crate::executor::Executor::insert) routes writes there.
+    pub(crate) fn insert(
+        &self,
+        labels: &[(&str, LabelValue<'_>)],
+        timestamp: Instant,
+        fields: &[(&str, FieldValue)],
+    ) -> Result<(), InsertError> {
+        let start_at = self.meta.chunk_start(timestamp);
+        let mut shard = self.shards[self.shard_of(labels)].write().unwrap();
+        shard
+            .chunk_mut(&self.meta, start_at)
+            .ok_or(InsertError::OutOfRange { timestamp })?
+            .insert(labels, timestamp, fields)
+    }
+
+    /// Freezes every chunk whose time window ended by `watermark`, its end is exclusive.
+    pub fn seal(&self, watermark: Instant) {
+        for shard in &self.shards {
+            shard.write().unwrap().seal(watermark);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::array::Array;
+    use crate::column::{FieldImpl, FieldMeta, FieldValue, InsertError, LabelMeta, LabelType, LabelValue};
+    use crate::common::{Duration, Instant};
+    use crate::index::IndexType;
+    use crate::primitive::PrimitiveType;
+
+    use super::{Table, TableError, TableMeta};
+
+    fn table() -> Table {
+        Table::new(
+            String::from("http_requests_total"),
+            TableMeta {
+                labels: vec![
+                    LabelMeta {
+                        name: String::from("job"),
+                        data_type: LabelType::String,
+                        index: vec![IndexType::Inverted(())],
+                    },
+                    LabelMeta {
+                        name: String::from("instance"),
+                        data_type: LabelType::IPv4,
+                        index: vec![],
+                    },
+                ],
+                fields: vec![FieldMeta {
+                    name: String::from("value"),
+                    data_type: PrimitiveType::F64,
+                }],
+                time_interval: Duration::SECOND,
+                series_len: 60,
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_insert() {
+        let table = table();
+        let labels = [
+            ("job", LabelValue::String("prometheus")),
+            ("instance", LabelValue::IPv4([127, 0, 0, 1].into())),
+        ];
+        for second in [0, 1, 59, 60] {
+            table
+                .insert(
+                    &labels,
+                    Instant::from_millis(second * 1000),
+                    &[("value", FieldValue::Float64(second as f64))],
+                )
+                .unwrap();
+        }
+        // non-aligned samples round down, also in the last interval of a chunk
+        for (millis, value) in [(2000, -1.0), (59_500, -59.5)] {
+            table
+                .insert(
+                    &[("job", LabelValue::String("node"))],
+                    Instant::from_millis(millis),
+                    &[("value", FieldValue::Float64(value))],
+                )
+                .unwrap();
+        }
+
+        let shard = table.shards()[0].read().unwrap();
+        assert_eq!(shard.mutable_chunks.len(), 2);
+        let chunk = &shard.mutable_chunks[0];
+        assert_eq!(chunk.len(), 2);
+        assert_eq!(chunk.get_label("instance").unwrap().id(1), Some(0));
+        let value = match chunk.get_field("value").unwrap() {
+            FieldImpl::Float64(column) => column.array(),
+            _ => unreachable!(),
+        };
+        let series = value.get(0).unwrap();
+        assert!(series.get(0) == Some(Some(&0.0)));
+        assert!(series.get(1) == Some(Some(&1.0)));
+        assert!(series.get(2) == Some(None));
+        assert!(series.get(59) == Some(Some(&59.0)));
+        assert!(value.get(1).unwrap().get(2) == Some(Some(&-1.0)));
+        assert!(value.get(1).unwrap().get(59) == Some(Some(&-59.5)));
+
+        let chunk = &shard.mutable_chunks[1];
+        assert!(chunk.meta.start_at() == Instant::from_millis(60_000));
+        assert_eq!(chunk.len(), 1);
+    }
+
+    #[test]
+    fn test_insert_error() {
+        let table = table();
+        let timestamp = Instant::from_millis(0);
+        assert!(matches!(
+            table.insert(&[("env", LabelValue::String("prod"))], timestamp, &[]),
+            Err(InsertError::LabelNotFound { .. })
+        ));
+        assert!(matches!(
+            table.insert(&[("job", LabelValue::Int(1))], timestamp, &[]),
+            Err(InsertError::MismatchedLabelType { .. })
+        ));
+        assert!(matches!(
+            table.insert(&[], timestamp, &[("value", FieldValue::Int64(1))]),
+            Err(InsertError::MismatchedFieldType { .. })
+        ));
+        assert!(matches!(
+            table.insert(&[], timestamp, &[("count", FieldValue::Float64(1.0))]),
+            Err(InsertError::FieldNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_meta() {
+        let meta = table().meta().clone();
+        let name = String::from("http_requests_total");
+        let zero_interval = TableMeta {
+            time_interval: Duration::from_millis(0),
+            ..meta.clone()
+        };
+        assert!(matches!(
+            Table::new(name.clone(), zero_interval),
+            Err(TableError::InvalidTimeInterval { .. })
+        ));
+        let empty = TableMeta { series_len: 0, ..meta };
+        assert!(matches!(Table::new(name, empty), Err(TableError::EmptySeries)));
+    }
+
+    #[test]
+    fn test_shard_of() {
+        let table = Table::with_shards(String::from("http_requests_total"), table().meta().clone(), 4).unwrap();
+        let mut series = [0; 4];
+        for job in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            let labels = [
+                ("job", LabelValue::String(job)),
+                ("instance", LabelValue::IPv4([127, 0, 0, 1].into())),
+            ];
+            let shard = table.shard_of(&labels);
+            assert_eq!(shard, table.shard_of(&[labels[1], labels[0]]));
+            table
+                .insert(&labels, Instant::from_millis(0), &[("value", FieldValue::Float64(1.0))])
+                .unwrap();
+            series[shard] += 1;
+        }
+        for (shard, series) in table.shards().iter().zip(series) {
+            let shard = shard.read().unwrap();
+            assert_eq!(
+                shard.mutable_chunks.iter().map(|chunk| chunk.len()).sum::<usize>(),
+                series
+            );
+        }
+        assert!(series.iter().filter(|series| **series > 0).count() > 1);
+        // the same in every process
+        assert_eq!(series, [4, 1, 2, 1]);
+    }
+
+    #[test]
+    fn test_seal() {
+        let table = table();
+        let labels = [("job", LabelValue::String("prometheus"))];
+        for second in [0, 60] {
+            table
+                .insert(
+                    &labels,
+                    Instant::from_millis(second * 1000),
+                    &[("value", FieldValue::Float64(second as f64))],
+                )
+                .unwrap();
+        }
+        // the window of the first chunk is still open within its last interval
+        table.seal(Instant::from_millis(59_500));
+        table
+            .insert(
+                &labels,
+                Instant::from_millis(59_800),
+                &[("value", FieldValue::Float64(59.8))],
+            )
+            .unwrap();
+        table.seal(Instant::from_millis(60_000));
+        {
+            let shard = table.shards()[0].read().unwrap();
+            assert_eq!(shard.mutable_chunks.len(), 1);
+            assert_eq!(shard.immutable_chunks.len(), 1);
+            assert!(shard.immutable_chunks[0].meta.start_at() == Instant::from_millis(0));
+            assert_eq!(shard.immutable_chunks[0].len(), 1);
+        }
+        assert!(matches!(
+            table.insert(
+                &labels,
+                Instant::from_millis(1000),
+                &[("value", FieldValue::Float64(1.0))]
+            ),
+            Err(InsertError::OutOfRange { .. })
+        ));
+        table
+            .insert(
+                &labels,
+                Instant::from_millis(61_000),
+                &[("value", FieldValue::Float64(61.0))],
+            )
+            .unwrap();
+    }
 }